@@ -1,4 +1,5 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     tonic_build::compile_protos("proto/Bus.proto")?;
+    tonic_build::compile_protos("proto/DataLake.proto")?;
     Ok(())
 }
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::AclConfig;
+use crate::data_lake::TDataLake;
+use crate::data_lake::path_tree::Path;
+use crate::data_lake::permissions::Permissions;
+
+/// Clients of the API front-ends and their permissions, see `AclConfig`.
+/// Without configuration (`Acl::default()`) every client may access everything.
+#[derive(Clone, Default)]
+pub struct Acl
+{
+    rules: Option<Arc<Rules>>,
+}
+
+struct Rules
+{
+    /// name and permissions of the client, keyed by token
+    clients: HashMap<String, (String, Arc<Permissions>)>,
+    anonymous: Option<Arc<Permissions>>,
+}
+
+fn parse_patterns(patterns: &[String], field: &str) -> Result<Vec<Path>, String>
+{
+    patterns.iter().enumerate()
+        .map(|(i, pattern)| pattern.parse().map_err(|e| format!("{}[{}]: {}", field, i, e)))
+        .collect()
+}
+
+/// The token of an `Authorization` header value, e.g. "Bearer 1234".
+pub fn bearer_token(authorization: &str) -> Option<&str>
+{
+    authorization.strip_prefix("Bearer ").map(str::trim)
+}
+
+impl Acl
+{
+    /// Errors start with the field in question, e.g. "roles[1].publish[0]: ...".
+    pub fn new(config: &AclConfig) -> Result<Acl, String>
+    {
+        let mut roles = HashMap::new();
+        for (i, role) in config.roles.iter().enumerate()
+        {
+            let publish = parse_patterns(&role.publish, &format!("roles[{}].publish", i))?;
+            let subscribe = parse_patterns(&role.subscribe, &format!("roles[{}].subscribe", i))?;
+            if roles.insert(role.name.clone(), Arc::new(Permissions::new(&role.name, &publish, &subscribe))).is_some()
+            {
+                return Err(format!("roles[{}].name: '{}' used more than once", i, role.name));
+            }
+        }
+        let mut clients = HashMap::new();
+        for (i, client) in config.clients.iter().enumerate()
+        {
+            if client.token.is_empty()
+            {
+                return Err(format!("clients[{}].token: must not be empty", i));
+            }
+            let permissions = roles.get(&client.role).ok_or(format!("clients[{}].role: no role '{}'", i, client.role))?;
+            if clients.values().any(|(name, _)| *name == client.name)
+            {
+                return Err(format!("clients[{}].name: '{}' used more than once", i, client.name));
+            }
+            if clients.insert(client.token.clone(), (client.name.clone(), permissions.clone())).is_some()
+            {
+                return Err(format!("clients[{}].token: used by another client", i));
+            }
+        }
+        let anonymous = match &config.anonymous_role
+        {
+            Some(role) => Some(roles.get(role).ok_or(format!("anonymous_role: no role '{}'", role))?.clone()),
+            None => None,
+        };
+        Ok(Acl{rules: Some(Arc::new(Rules{clients, anonymous}))})
+    }
+
+    /// The lake as the client with the token (if any) may access it. Fails, logged, for unknown
+    /// tokens, for a client name not matching the token and for clients without token if there
+    /// is no anonymous role.
+    pub fn view(self: &Self, datalake: &TDataLake, client: Option<&str>, token: Option<&str>) -> Result<TDataLake, String>
+    {
+        let rules = match &self.rules
+        {
+            Some(rules) => rules,
+            None => return Ok(datalake.clone()),
+        };
+        let result = match (token, &rules.anonymous)
+        {
+            (Some(token), _) => match rules.clients.get(token)
+            {
+                Some((name, permissions)) if client.is_none_or(|client| client == name) => Ok(datalake.with_permissions(name, permissions.clone())),
+                _ => Err("unknown client or token".to_string()),
+            },
+            (None, Some(permissions)) => Ok(datalake.with_permissions(client.unwrap_or("anonymous"), permissions.clone())),
+            (None, None) => Err("a token is required".to_string()),
+        };
+        if let Err(e) = &result
+        {
+            println!("ACL: rejected client '{}': {}", client.unwrap_or("anonymous"), e);
+        }
+        result
+    }
+}
+
+#[tokio::test]
+async fn clients_get_the_permissions_of_their_role()
+{
+    let config: crate::config::Config = r#"
+        [api.acl]
+        anonymous_role = "guest"
+        [[api.acl.roles]]
+        name = "guest"
+        subscribe = ["/house/**"]
+        [[api.acl.roles]]
+        name = "admin"
+        publish = ["/**"]
+        subscribe = ["/**"]
+        [[api.acl.clients]]
+        name = "wall panel"
+        token = "1234"
+        role = "admin"
+    "#.parse().unwrap();
+    let acl = Acl::new(config.api.acl.as_ref().unwrap()).unwrap();
+    let datalake = TDataLake::new();
+    let door: Path = "/house/eg/door/open".parse().unwrap();
+
+    assert!(acl.view(&datalake, None, Some("1234")).unwrap().may_publish(&door));
+    assert!(acl.view(&datalake, Some("wall panel"), Some("1234")).unwrap().may_publish(&door));
+    assert!(acl.view(&datalake, Some("tablet"), Some("1234")).is_err());
+    assert!(acl.view(&datalake, None, Some("4321")).is_err());
+    let guest = acl.view(&datalake, None, None).unwrap();
+    assert!(!guest.may_publish(&door));
+    assert!(guest.may_subscribe(&door));
+
+    let config = AclConfig::default();
+    assert!(Acl::new(&config).unwrap().view(&datalake, None, None).is_err());
+    assert!(Acl::default().view(&datalake, None, None).unwrap().may_publish(&door));
+
+    let error = "[api.acl]\nanonymous_role = \"guest\"".parse::<crate::config::Config>().unwrap_err();
+    assert!(error.contains("api.acl.anonymous_role"), "{}", error);
+    let error = "[[api.acl.roles]]\nname = \"guest\"\npublish = [\"house\"]".parse::<crate::config::Config>().unwrap_err();
+    assert!(error.contains("api.acl.roles[0].publish[0]"), "{}", error);
+    let error = "[[api.acl.clients]]\nname = \"tablet\"\ntoken = \"1\"\nrole = \"guest\"".parse::<crate::config::Config>().unwrap_err();
+    assert!(error.contains("api.acl.clients[0].role"), "{}", error);
+}
@@ -0,0 +1,324 @@
+use std::net::SocketAddr;
+
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Response, Status};
+
+use crate::data_lake::*;
+use crate::data_lake::path_tree::Path;
+use crate::shutdown::TaskHandle;
+use super::acl::{bearer_token, Acl};
+
+pub mod proto {
+    tonic::include_proto!("datalake");
+}
+use proto::data_lake_server::{DataLake, DataLakeServer};
+use proto::value::Kind;
+
+/// Messages buffered per subscription, a client falling further behind loses its subscription
+const SUBSCRIPTION_BUFFER: usize = 256;
+
+pub fn value_to_proto(value: &Value) -> proto::Value
+{
+    let kind = match value
+    {
+        Value::Nil => Kind::NilValue(true),
+        Value::Bool(b) => Kind::BoolValue(*b),
+        Value::Integer(i) => Kind::IntegerValue(*i),
+        Value::Float(f) => Kind::FloatValue(*f),
+        Value::String(s) => Kind::StringValue(s.clone()),
+        Value::Binary(b) => Kind::BinaryValue(b.clone()),
+        Value::Array(a) => Kind::ArrayValue(proto::ValueArray{values: a.iter().map(value_to_proto).collect()}),
+        Value::Map(m) => Kind::MapValue(proto::ValueMap{entries: m.iter()
+            .map(|(key, value)| proto::ValueMapEntry{key: Some(value_to_proto(key)), value: Some(value_to_proto(value))})
+            .collect()}),
+    };
+    proto::Value{kind: Some(kind)}
+}
+
+/// Missing values (unset message fields) are taken as nil.
+pub fn value_from_proto(value: Option<proto::Value>) -> Value
+{
+    match value.and_then(|value| value.kind)
+    {
+        None | Some(Kind::NilValue(_)) => Value::Nil,
+        Some(Kind::BoolValue(b)) => Value::Bool(b),
+        Some(Kind::IntegerValue(i)) => Value::Integer(i),
+        Some(Kind::FloatValue(f)) => Value::Float(f),
+        Some(Kind::StringValue(s)) => Value::String(s),
+        Some(Kind::BinaryValue(b)) => Value::Binary(b),
+        Some(Kind::ArrayValue(a)) => Value::Array(a.values.into_iter().map(|value| value_from_proto(Some(value))).collect()),
+        Some(Kind::MapValue(m)) => Value::Map(m.entries.into_iter().map(|entry| (value_from_proto(entry.key), value_from_proto(entry.value))).collect()),
+    }
+}
+
+fn path_value(path: &Path, value: &Value) -> proto::PathValue
+{
+    proto::PathValue{path: path.to_string(), value: Some(value_to_proto(value))}
+}
+
+// Status is what the service methods return anyway
+#[allow(clippy::result_large_err)]
+fn parse_path(path: &str) -> Result<Path, Status>
+{
+    path.parse().map_err(|e| Status::invalid_argument(format!("Invalid path '{}': {}", path, e)))
+}
+
+struct DataLakeService
+{
+    datalake: TDataLake,
+    acl: Acl,
+}
+
+impl DataLakeService
+{
+    /// The lake as the client may access it, identified by the bearer token in the `authorization` metadata.
+    #[allow(clippy::result_large_err)]
+    fn client_view<T>(self: &Self, request: &tonic::Request<T>) -> Result<TDataLake, Status>
+    {
+        let token = request.metadata().get("authorization").and_then(|value| value.to_str().ok()).and_then(bearer_token);
+        self.acl.view(&self.datalake, None, token).map_err(Status::unauthenticated)
+    }
+}
+
+#[tonic::async_trait]
+impl DataLake for DataLakeService
+{
+    async fn publish(self: &Self, request: tonic::Request<proto::PublishRequest>) -> Result<Response<proto::PublishReply>, Status>
+    {
+        let datalake = self.client_view(&request)?;
+        let request = request.into_inner();
+        let path = parse_path(&request.path)?;
+        if path.has_wildcards()
+        {
+            return Err(Status::invalid_argument(format!("Cannot publish to '{}', wildcards are not allowed", request.path)));
+        }
+        if !datalake.may_publish(&path)
+        {
+            return Err(Status::permission_denied(format!("Publishing to '{}' is not allowed", request.path)));
+        }
+        let value = value_from_proto(request.value);
+        match request.retain
+        {
+            true => datalake.publish_retained(&path, value).await,
+            false => datalake.publish(&path, value).await,
+        }
+        Ok(Response::new(proto::PublishReply{}))
+    }
+
+    type SubscribeStream = ReceiverStream<Result<proto::PathValue, Status>>;
+
+    async fn subscribe(self: &Self, request: tonic::Request<proto::SubscribeRequest>) -> Result<Response<Self::SubscribeStream>, Status>
+    {
+        let mut datalake = self.client_view(&request)?;
+        let request = request.into_inner();
+        let pattern = parse_path(&request.pattern)?;
+        if !datalake.may_subscribe(&pattern)
+        {
+            return Err(Status::permission_denied(format!("Subscribing to '{}' is not allowed", request.pattern)));
+        }
+        let mut fisher = datalake.subscribe_with_path::<Value>(&pattern).await;
+
+        let (sender, receiver) = tokio::sync::mpsc::channel(SUBSCRIPTION_BUFFER);
+        tokio::task::spawn(async move {
+            if request.send_retained
+            {
+                // NOTE: values published meanwhile may be sent twice, but none is missed
+                for (path, value) in datalake.get_matching::<Value>(&pattern).await
+                {
+                    if sender.send(Ok(path_value(&path, &value))).await.is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+            loop
+            {
+                tokio::select!
+                {
+                    message = fisher.receive() =>
+                    {
+                        let (path, value) = match message
+                        {
+                            Some(message) => message,
+                            None => break,
+                        };
+                        // waiting for a slow client would block the publishers
+                        match sender.try_send(Ok(path_value(&path, &value)))
+                        {
+                            Ok(()) => (),
+                            Err(tokio::sync::mpsc::error::TrySendError::Full(_)) =>
+                            {
+                                let _ = sender.send(Err(Status::resource_exhausted("Subscriber too slow, subscribe again"))).await;
+                                break;
+                            }
+                            Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => break,
+                        }
+                    }
+                    _ = sender.closed() =>
+                    {
+                        // client went away
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn get(self: &Self, request: tonic::Request<proto::GetRequest>) -> Result<Response<proto::GetReply>, Status>
+    {
+        // values the client may not receive are left out
+        let datalake = self.client_view(&request)?;
+        let pattern = parse_path(&request.into_inner().pattern)?;
+        let mut values = datalake.get_matching::<Value>(&pattern).await;
+        values.sort_by_key(|(path, _)| path.to_string());
+        let values = values.iter().map(|(path, value)| path_value(path, value)).collect();
+        Ok(Response::new(proto::GetReply{values}))
+    }
+
+    async fn browse(self: &Self, request: tonic::Request<proto::BrowseRequest>) -> Result<Response<proto::BrowseReply>, Status>
+    {
+        let datalake = self.client_view(&request)?;
+        let path = parse_path(&request.into_inner().path)?;
+        let mut entries = Vec::new();
+        for (child, has_children) in datalake.browse(&path).await
+        {
+            let has_value = datalake.get::<Value>(&child).await.is_some();
+            entries.push(proto::BrowseEntry{path: child.to_string(), has_value, has_children});
+        }
+        Ok(Response::new(proto::BrowseReply{entries}))
+    }
+}
+
+/// Serves the `DataLake` gRPC service at the given address until stopped.
+pub fn create(datalake: TDataLake, acl: Acl, listen: SocketAddr) -> TaskHandle
+{
+    TaskHandle::spawn(|stop_receiver| async move {
+        match tokio::net::TcpListener::bind(listen).await
+        {
+            Ok(listener) => serve(datalake, acl, listener, stop_receiver).await,
+            Err(e) => println!("gRPC API: cannot listen on {}: {}", listen, e),
+        }
+    })
+}
+
+async fn serve(datalake: TDataLake, acl: Acl, listener: tokio::net::TcpListener, stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    let result = tonic::transport::Server::builder()
+        .add_service(DataLakeServer::new(DataLakeService{datalake, acl}))
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async { stop_receiver.await.ok(); })
+        .await;
+    if let Err(e) = result
+    {
+        println!("gRPC API failed: {}", e);
+    }
+}
+
+#[test]
+fn test_value_conversion()
+{
+    let value = Value::Map(vec![
+        (Value::String("on".into()), Value::Bool(true)),
+        (Value::Integer(2), Value::Array(vec![Value::Nil, Value::Float(0.5), Value::Binary(vec![1])])),
+    ]);
+    assert_eq!(value_from_proto(Some(value_to_proto(&value))), value);
+    assert_eq!(value_from_proto(None), Value::Nil);
+}
+
+#[tokio::test]
+async fn publish_subscribe_get_browse()
+{
+    use proto::data_lake_client::DataLakeClient;
+    use tokio_stream::StreamExt;
+
+    let datalake = TDataLake::new();
+    datalake.publish_retained(&"/house/eg/light".parse().unwrap(), Value::Bool(false)).await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = TaskHandle::spawn(|stop_receiver| serve(datalake.clone(), Acl::default(), listener, stop_receiver));
+    let mut client = DataLakeClient::connect(url).await.unwrap();
+
+    let mut stream = client.subscribe(proto::SubscribeRequest{pattern: "/house/**".into(), send_retained: true}).await.unwrap().into_inner();
+    let first = stream.next().await.unwrap().unwrap();
+    assert_eq!(first.path, "/house/eg/light");
+    assert_eq!(value_from_proto(first.value), Value::Bool(false));
+
+    client.publish(proto::PublishRequest{path: "/house/og/temperature".into(), value: Some(value_to_proto(&Value::Float(21.5))), retain: true}).await.unwrap();
+    let published = stream.next().await.unwrap().unwrap();
+    assert_eq!(published.path, "/house/og/temperature");
+    assert_eq!(value_from_proto(published.value), Value::Float(21.5));
+    assert_eq!(datalake.get::<Value>(&"/house/og/temperature".parse().unwrap()).await, Some(Value::Float(21.5)));
+
+    let values = client.get(proto::GetRequest{pattern: "/house/*/*".into()}).await.unwrap().into_inner().values;
+    assert_eq!(values.iter().map(|value| value.path.as_str()).collect::<Vec<_>>(), vec!["/house/eg/light", "/house/og/temperature"]);
+
+    let entries = client.browse(proto::BrowseRequest{path: "/house".into()}).await.unwrap().into_inner().entries;
+    assert_eq!(entries, vec![
+        proto::BrowseEntry{path: "/house/eg".into(), has_value: false, has_children: true},
+        proto::BrowseEntry{path: "/house/og".into(), has_value: false, has_children: true},
+    ]);
+
+    let error = client.publish(proto::PublishRequest{path: "/house/*".into(), value: None, retain: false}).await.unwrap_err();
+    assert_eq!(error.code(), tonic::Code::InvalidArgument);
+    let error = client.get(proto::GetRequest{pattern: "house".into()}).await.unwrap_err();
+    assert_eq!(error.code(), tonic::Code::InvalidArgument);
+
+    drop(stream);
+    drop(client);
+    server.stop().await;
+}
+
+#[tokio::test]
+async fn clients_are_limited_by_the_acl()
+{
+    use proto::data_lake_client::DataLakeClient;
+
+    let config: crate::config::Config = r#"
+        [api.acl]
+        anonymous_role = "guest"
+        [[api.acl.roles]]
+        name = "guest"
+        publish = ["/house/*/light"]
+        subscribe = ["/house/**"]
+        [[api.acl.roles]]
+        name = "admin"
+        publish = ["/**"]
+        subscribe = ["/**"]
+        [[api.acl.clients]]
+        name = "wall panel"
+        token = "1234"
+        role = "admin"
+    "#.parse().unwrap();
+    let acl = Acl::new(config.api.acl.as_ref().unwrap()).unwrap();
+    let datalake = TDataLake::new();
+    datalake.publish_retained(&"/bus/ug/rx/5".parse().unwrap(), Value::Bool(false)).await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = TaskHandle::spawn(|stop_receiver| serve(datalake.clone(), acl, listener, stop_receiver));
+    let mut client = DataLakeClient::connect(url).await.unwrap();
+
+    let publish = |path: &str, token: Option<&str>| {
+        let mut request = tonic::Request::new(proto::PublishRequest{path: path.into(), value: None, retain: true});
+        if let Some(token) = token
+        {
+            request.metadata_mut().insert("authorization", format!("Bearer {}", token).parse().unwrap());
+        }
+        request
+    };
+    client.publish(publish("/house/eg/light", None)).await.unwrap();
+    let error = client.publish(publish("/house/eg/door/open", None)).await.unwrap_err();
+    assert_eq!(error.code(), tonic::Code::PermissionDenied);
+    client.publish(publish("/house/eg/door/open", Some("1234"))).await.unwrap();
+    let error = client.publish(publish("/house/eg/light", Some("4321"))).await.unwrap_err();
+    assert_eq!(error.code(), tonic::Code::Unauthenticated);
+
+    let values = client.get(proto::GetRequest{pattern: "/**".into()}).await.unwrap().into_inner().values;
+    assert_eq!(values.iter().map(|value| value.path.as_str()).collect::<Vec<_>>(), vec!["/house/eg/door/open", "/house/eg/light"]);
+    let error = client.subscribe(proto::SubscribeRequest{pattern: "/bus/**".into(), send_retained: true}).await.unwrap_err();
+    assert_eq!(error.code(), tonic::Code::PermissionDenied);
+
+    drop(client);
+    server.stop().await;
+}
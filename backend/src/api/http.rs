@@ -0,0 +1,187 @@
+use std::net::SocketAddr;
+
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Json, Response};
+
+use crate::data_lake::*;
+use crate::data_lake::path_tree::Path;
+use crate::shutdown::TaskHandle;
+use super::acl::{bearer_token, Acl};
+
+// Lake paths are mapped below /api/lake, values are exchanged as JSON:
+//   GET  /api/lake/house/eg/light   -> retained value, 404 if there is none
+//   GET  /api/lake/house/*/light    -> object of all matching retained values, keyed by path
+//   PUT  /api/lake/house/eg/light   -> publishes the request body as retained value (state)
+//   POST /api/lake/house/eg/bell    -> publishes the request body without retaining it (event)
+// Invalid paths or bodies are answered with 400 and a plain text message.
+// With access control, clients send "Authorization: Bearer <token>", unknown tokens are answered
+// with 401, paths the client may not access with 403.
+
+type HttpError = (StatusCode, String);
+
+#[derive(Clone)]
+struct ApiState
+{
+    datalake: TDataLake,
+    acl: Acl,
+}
+
+/// The lake as the client may access it, identified by the bearer token of the `Authorization` header.
+fn client_view(state: &ApiState, headers: &HeaderMap) -> Result<TDataLake, HttpError>
+{
+    let token = headers.get(axum::http::header::AUTHORIZATION).and_then(|value| value.to_str().ok()).and_then(bearer_token);
+    state.acl.view(&state.datalake, None, token).map_err(|e| (StatusCode::UNAUTHORIZED, format!("{}\n", e)))
+}
+
+/// Parses the part of the URL following /api/lake
+fn parse_path(path: &str) -> Result<Path, HttpError>
+{
+    let path = format!("/{}", path);
+    path.parse().map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid path '{}': {}\n", path, e)))
+}
+
+async fn get_value(State(state): State<ApiState>, headers: HeaderMap, axum::extract::Path(path): axum::extract::Path<String>) -> Result<Response, HttpError>
+{
+    let datalake = client_view(&state, &headers)?;
+    let path = parse_path(&path)?;
+    if path.has_wildcards()
+    {
+        // keys of JSON objects are kept sorted, values the client may not receive are left out
+        let values: serde_json::Map<String, serde_json::Value> = datalake.get_matching::<Value>(&path).await.iter()
+            .map(|(path, value)| (path.to_string(), value.to_serde_json()))
+            .collect();
+        return Ok(Json(serde_json::Value::Object(values)).into_response());
+    }
+    if !datalake.may_subscribe(&path)
+    {
+        return Err((StatusCode::FORBIDDEN, format!("Reading '{}' is not allowed\n", path)));
+    }
+    match datalake.get::<Value>(&path).await
+    {
+        Some(value) => Ok(Json(value.to_serde_json()).into_response()),
+        None => Err((StatusCode::NOT_FOUND, format!("No value at '{}'\n", path))),
+    }
+}
+
+async fn publish(datalake: &TDataLake, path: &str, body: &str, retain: bool) -> Result<StatusCode, HttpError>
+{
+    let path = parse_path(path)?;
+    if path.has_wildcards()
+    {
+        return Err((StatusCode::BAD_REQUEST, format!("Cannot publish to '{}', wildcards are not allowed\n", path)));
+    }
+    if !datalake.may_publish(&path)
+    {
+        return Err((StatusCode::FORBIDDEN, format!("Publishing to '{}' is not allowed\n", path)));
+    }
+    let value = Value::from_json(body).map_err(|e| (StatusCode::BAD_REQUEST, format!("{}\n", e)))?;
+    match retain
+    {
+        true => datalake.publish_retained(&path, value).await,
+        false => datalake.publish(&path, value).await,
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn put_value(State(state): State<ApiState>, headers: HeaderMap, axum::extract::Path(path): axum::extract::Path<String>, body: String) -> Result<StatusCode, HttpError>
+{
+    publish(&client_view(&state, &headers)?, &path, &body, true).await
+}
+
+async fn post_value(State(state): State<ApiState>, headers: HeaderMap, axum::extract::Path(path): axum::extract::Path<String>, body: String) -> Result<StatusCode, HttpError>
+{
+    publish(&client_view(&state, &headers)?, &path, &body, false).await
+}
+
+fn router(datalake: TDataLake, acl: Acl) -> axum::Router
+{
+    axum::Router::new()
+        .route("/api/lake/*path", axum::routing::get(get_value).put(put_value).post(post_value))
+        .with_state(ApiState{datalake, acl})
+}
+
+/// Serves the REST API at the given address until stopped.
+pub fn create(datalake: TDataLake, acl: Acl, listen: SocketAddr) -> TaskHandle
+{
+    TaskHandle::spawn(|stop_receiver| async move {
+        let listener = match std::net::TcpListener::bind(listen)
+        {
+            Ok(listener) => listener,
+            Err(e) => return println!("HTTP API: cannot listen on {}: {}", listen, e),
+        };
+        if let Err(e) = super::serve_router(router(datalake, acl), listener, async { stop_receiver.await.ok(); }).await
+        {
+            println!("HTTP API failed: {}", e);
+        }
+    })
+}
+
+#[tokio::test]
+async fn get_put_post()
+{
+    use tower::ServiceExt;
+
+    async fn request(datalake: &TDataLake, method: &str, uri: &str, body: &str) -> (StatusCode, String)
+    {
+        let request = axum::http::Request::builder().method(method).uri(uri).body(axum::body::Body::from(body.to_string())).unwrap();
+        let response = router(datalake.clone(), Acl::default()).oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    let datalake = TDataLake::new();
+    let mut fisher = datalake.clone().subscribe::<Value>(&"/house/bell".parse().unwrap()).await;
+
+    assert_eq!(request(&datalake, "PUT", "/api/lake/house/eg/light", "true").await.0, StatusCode::NO_CONTENT);
+    assert_eq!(request(&datalake, "PUT", "/api/lake/house/og/light", r#"{"level": 3}"#).await.0, StatusCode::NO_CONTENT);
+    assert_eq!(request(&datalake, "GET", "/api/lake/house/eg/light", "").await, (StatusCode::OK, "true".to_string()));
+    assert_eq!(request(&datalake, "GET", "/api/lake/house/*/light", "").await, (StatusCode::OK, r#"{"/house/eg/light":true,"/house/og/light":{"level":3}}"#.to_string()));
+    assert_eq!(request(&datalake, "GET", "/api/lake/house/**/missing", "").await, (StatusCode::OK, "{}".to_string()));
+
+    // events are delivered but not retained
+    assert_eq!(request(&datalake, "POST", "/api/lake/house/bell", r#""ring""#).await.0, StatusCode::NO_CONTENT);
+    assert_eq!(fisher.receive().await, Some(Value::String("ring".into())));
+    assert_eq!(request(&datalake, "GET", "/api/lake/house/bell", "").await.0, StatusCode::NOT_FOUND);
+
+    assert_eq!(request(&datalake, "PUT", "/api/lake/house/*", "1").await.0, StatusCode::BAD_REQUEST);
+    assert_eq!(request(&datalake, "PUT", "/api/lake/house/eg/light", "on").await.0, StatusCode::BAD_REQUEST);
+    assert_eq!(request(&datalake, "GET", "/api/lake/house/*x", "").await.0, StatusCode::BAD_REQUEST);
+    assert_eq!(request(&datalake, "DELETE", "/api/lake/house/eg/light", "").await.0, StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[tokio::test]
+async fn clients_are_limited_by_the_acl()
+{
+    use tower::ServiceExt;
+
+    let config: crate::config::Config = r#"
+        [[api.acl.roles]]
+        name = "guest"
+        publish = ["/house/*/light"]
+        subscribe = ["/house/**"]
+        [[api.acl.clients]]
+        name = "tablet"
+        token = "1234"
+        role = "guest"
+    "#.parse().unwrap();
+    let router = router(TDataLake::new(), Acl::new(config.api.acl.as_ref().unwrap()).unwrap());
+    let request = |method: &str, uri: &str, token: Option<&str>| {
+        let mut request = axum::http::Request::builder().method(method).uri(uri);
+        if let Some(token) = token
+        {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        }
+        let request = request.body(axum::body::Body::from("true")).unwrap();
+        let router = router.clone();
+        async move { router.oneshot(request).await.unwrap().status() }
+    };
+
+    assert_eq!(request("PUT", "/api/lake/house/eg/light", None).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(request("PUT", "/api/lake/house/eg/light", Some("4321")).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(request("PUT", "/api/lake/house/eg/light", Some("1234")).await, StatusCode::NO_CONTENT);
+    assert_eq!(request("POST", "/api/lake/house/eg/door/open", Some("1234")).await, StatusCode::FORBIDDEN);
+    assert_eq!(request("GET", "/api/lake/house/eg/light", Some("1234")).await, StatusCode::OK);
+    assert_eq!(request("GET", "/api/lake/bus/ug/rx/5", Some("1234")).await, StatusCode::FORBIDDEN);
+}
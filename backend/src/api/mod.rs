@@ -0,0 +1,16 @@
+pub mod acl;
+pub mod grpc;
+pub mod http;
+pub mod mqtt;
+pub mod websocket;
+
+/// Serves the router on the listener until `shutdown` completes.
+async fn serve_router(router: axum::Router, listener: std::net::TcpListener, shutdown: impl std::future::Future<Output = ()>) -> Result<(), String>
+{
+    listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+    axum::Server::from_tcp(listener).map_err(|e| e.to_string())?
+        .serve(router.into_make_service())
+        .with_graceful_shutdown(shutdown)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,721 @@
+//! MQTT 3.1.1 and 5 broker on top of the data lake.
+//! Topics map to lake paths ("house/eg/light" is "/house/eg/light", "+" is "*" and "#" is "**"),
+//! payloads are exchanged as `Value`s, see `mqtt_bridge::decode_payload`. Retained messages are
+//! retained values of the lake, subscriptions are lake subscriptions.
+//! Messages are delivered with QoS 0, received QoS 1 and 2 messages are acknowledged.
+//! With access control, clients log in with their name as user name and their token as password.
+//! Publishing to topics the client may not publish to is dropped, subscribing to filters it may
+//! not receive anything of fails.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use rumqttc::mqttbytes::v4;
+use rumqttc::mqttbytes::QoS;
+use rumqttc::v5::mqttbytes::v5;
+use rumqttc::v5::mqttbytes::QoS as V5QoS;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::data_lake::*;
+use crate::data_lake::path_tree::Path;
+use crate::shutdown::TaskHandle;
+use super::acl::Acl;
+
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+/// Time a new connection has to send CONNECT
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Messages buffered per client, further messages are dropped (as QoS 0 allows)
+const CLIENT_BUFFER: usize = 256;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Message
+{
+    topic: String,
+    payload: Bytes,
+    retain: bool,
+}
+
+/// Packets from clients, independent of the protocol version
+#[derive(Debug)]
+enum Incoming
+{
+    /// login is user name and password
+    Connect{client_id: String, keep_alive: u16, will: Option<Message>, login: Option<(String, String)>},
+    Publish{message: Message, qos: QoS, pkid: u16},
+    PubRel{pkid: u16},
+    Subscribe{pkid: u16, filters: Vec<String>},
+    Unsubscribe{pkid: u16, filters: Vec<String>},
+    PingReq,
+    Disconnect,
+    /// acknowledgements of messages sent with QoS > 0, which are not sent
+    Ignored,
+}
+
+/// Packets to clients, independent of the protocol version
+enum Outgoing
+{
+    /// false if the client is not authorized
+    ConnAck(bool),
+    PubAck(u16),
+    PubRec(u16),
+    PubComp(u16),
+    /// one entry per filter, false if the subscription failed
+    SubAck{pkid: u16, granted: Vec<bool>},
+    /// one entry per filter, false if there was no such subscription
+    UnsubAck{pkid: u16, removed: Vec<bool>},
+    PingResp,
+    Publish(Message),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Version
+{
+    V311,
+    V5,
+}
+
+impl Version
+{
+    /// Reads the next packet, None if the buffer does not hold a complete one yet.
+    fn read(self: Self, input: &mut BytesMut) -> Result<Option<Incoming>, String>
+    {
+        match self
+        {
+            Version::V311 => match v4::read(input, MAX_PACKET_SIZE)
+            {
+                Ok(packet) => Ok(Some(from_v4(packet))),
+                Err(rumqttc::mqttbytes::Error::InsufficientBytes(_)) => Ok(None),
+                Err(e) => Err(format!("{:?}", e)),
+            },
+            Version::V5 =>
+            {
+                // the decoder does not accept the short form of DISCONNECT without reason code
+                if input.starts_with(&[0xe0, 0x00])
+                {
+                    let _ = input.split_to(2);
+                    return Ok(Some(Incoming::Disconnect));
+                }
+                match v5::Packet::read(input, Some(MAX_PACKET_SIZE))
+                {
+                    Ok(packet) => Ok(Some(from_v5(packet))),
+                    Err(rumqttc::v5::mqttbytes::Error::InsufficientBytes(_)) => Ok(None),
+                    Err(e) => Err(format!("{:?}", e)),
+                }
+            }
+        }
+    }
+
+    fn write(self: Self, packet: Outgoing, output: &mut BytesMut) -> Result<(), String>
+    {
+        let result = match self
+        {
+            Version::V311 => match packet
+            {
+                Outgoing::ConnAck(true) => v4::ConnAck::new(v4::ConnectReturnCode::Success, false).write(output),
+                Outgoing::ConnAck(false) => v4::ConnAck::new(v4::ConnectReturnCode::NotAuthorized, false).write(output),
+                Outgoing::PubAck(pkid) => v4::PubAck::new(pkid).write(output),
+                Outgoing::PubRec(pkid) => v4::PubRec::new(pkid).write(output),
+                Outgoing::PubComp(pkid) => v4::PubComp::new(pkid).write(output),
+                Outgoing::SubAck{pkid, granted} =>
+                {
+                    let codes = granted.iter().map(|granted| match granted
+                    {
+                        true => v4::SubscribeReasonCode::Success(QoS::AtMostOnce),
+                        false => v4::SubscribeReasonCode::Failure,
+                    }).collect();
+                    v4::SubAck::new(pkid, codes).write(output)
+                }
+                Outgoing::UnsubAck{pkid, ..} => v4::UnsubAck::new(pkid).write(output),
+                Outgoing::PingResp => v4::PingResp.write(output),
+                Outgoing::Publish(message) =>
+                {
+                    let mut publish = v4::Publish::from_bytes(message.topic, QoS::AtMostOnce, message.payload);
+                    publish.retain = message.retain;
+                    publish.write(output)
+                }
+            }.map_err(|e| format!("{:?}", e)),
+            Version::V5 =>
+            {
+                let packet = match packet
+                {
+                    Outgoing::ConnAck(accepted) =>
+                    {
+                        let code = match accepted
+                        {
+                            true => v5::ConnectReturnCode::Success,
+                            false => v5::ConnectReturnCode::NotAuthorized,
+                        };
+                        v5::Packet::ConnAck(v5::ConnAck{session_present: false, code, properties: None})
+                    }
+                    Outgoing::PubAck(pkid) => v5::Packet::PubAck(v5::PubAck::new(pkid, None)),
+                    Outgoing::PubRec(pkid) => v5::Packet::PubRec(v5::PubRec::new(pkid, None)),
+                    Outgoing::PubComp(pkid) => v5::Packet::PubComp(v5::PubComp::new(pkid, None)),
+                    Outgoing::SubAck{pkid, granted} =>
+                    {
+                        let return_codes = granted.iter().map(|granted| match granted
+                        {
+                            true => v5::SubscribeReasonCode::Success(V5QoS::AtMostOnce),
+                            false => v5::SubscribeReasonCode::TopicFilterInvalid,
+                        }).collect();
+                        v5::Packet::SubAck(v5::SubAck{pkid, return_codes, properties: None})
+                    }
+                    Outgoing::UnsubAck{pkid, removed} =>
+                    {
+                        let reasons = removed.iter().map(|removed| match removed
+                        {
+                            true => v5::UnsubAckReason::Success,
+                            false => v5::UnsubAckReason::NoSubscriptionExisted,
+                        }).collect();
+                        v5::Packet::UnsubAck(v5::UnsubAck{pkid, reasons, properties: None})
+                    }
+                    Outgoing::PingResp => v5::Packet::PingResp(v5::PingResp),
+                    Outgoing::Publish(message) =>
+                    {
+                        let mut publish = v5::Publish::new(message.topic, V5QoS::AtMostOnce, message.payload, None);
+                        publish.retain = message.retain;
+                        v5::Packet::Publish(publish)
+                    }
+                };
+                packet.write(output).map_err(|e| format!("{:?}", e))
+            }
+        };
+        result.map(|_| ())
+    }
+}
+
+/// Reads the CONNECT packet, which tells the protocol version.
+fn read_connect(input: &mut BytesMut) -> Result<Option<(Version, Incoming)>, String>
+{
+    // the 3.1.1 decoder consumes the packet even if it turns out to be MQTT 5, keep a copy
+    let mut probe = input.clone();
+    let result = match v4::read(&mut probe, MAX_PACKET_SIZE)
+    {
+        Err(rumqttc::mqttbytes::Error::InsufficientBytes(_)) => return Ok(None),
+        Ok(v4::Packet::Connect(connect)) if connect.protocol == rumqttc::mqttbytes::Protocol::V4 =>
+        {
+            *input = probe;
+            (Version::V311, from_v4(v4::Packet::Connect(connect)))
+        }
+        // MQTT 5, or garbage which the MQTT 5 decoder rejects as well
+        _ => match Version::V5.read(input)?
+        {
+            Some(packet) => (Version::V5, packet),
+            None => return Ok(None),
+        },
+    };
+    match result.1
+    {
+        Incoming::Connect{..} => Ok(Some(result)),
+        _ => Err("first packet is no CONNECT".into()),
+    }
+}
+
+fn from_v4(packet: v4::Packet) -> Incoming
+{
+    match packet
+    {
+        v4::Packet::Connect(connect) => Incoming::Connect{
+            client_id: connect.client_id,
+            keep_alive: connect.keep_alive,
+            will: connect.last_will.map(|will| Message{topic: will.topic, payload: will.message, retain: will.retain}),
+            login: connect.login.map(|login| (login.username, login.password)),
+        },
+        v4::Packet::Publish(publish) => Incoming::Publish{
+            message: Message{topic: publish.topic, payload: publish.payload, retain: publish.retain},
+            qos: publish.qos,
+            pkid: publish.pkid,
+        },
+        v4::Packet::PubRel(pubrel) => Incoming::PubRel{pkid: pubrel.pkid},
+        v4::Packet::Subscribe(subscribe) => Incoming::Subscribe{pkid: subscribe.pkid, filters: subscribe.filters.into_iter().map(|filter| filter.path).collect()},
+        v4::Packet::Unsubscribe(unsubscribe) => Incoming::Unsubscribe{pkid: unsubscribe.pkid, filters: unsubscribe.topics},
+        v4::Packet::PingReq => Incoming::PingReq,
+        v4::Packet::Disconnect => Incoming::Disconnect,
+        _ => Incoming::Ignored,
+    }
+}
+
+fn from_v5(packet: v5::Packet) -> Incoming
+{
+    let topic = |topic: Bytes| String::from_utf8_lossy(&topic).into_owned();
+    match packet
+    {
+        v5::Packet::Connect(connect, will, login) => Incoming::Connect{
+            client_id: connect.client_id,
+            keep_alive: connect.keep_alive,
+            will: will.map(|will| Message{topic: topic(will.topic), payload: will.message, retain: will.retain}),
+            login: login.map(|login| (login.username, login.password)),
+        },
+        v5::Packet::Publish(publish) => Incoming::Publish{
+            message: Message{topic: topic(publish.topic), payload: publish.payload, retain: publish.retain},
+            qos: match publish.qos
+            {
+                V5QoS::AtMostOnce => QoS::AtMostOnce,
+                V5QoS::AtLeastOnce => QoS::AtLeastOnce,
+                V5QoS::ExactlyOnce => QoS::ExactlyOnce,
+            },
+            pkid: publish.pkid,
+        },
+        v5::Packet::PubRel(pubrel) => Incoming::PubRel{pkid: pubrel.pkid},
+        v5::Packet::Subscribe(subscribe) => Incoming::Subscribe{pkid: subscribe.pkid, filters: subscribe.filters.into_iter().map(|filter| filter.path).collect()},
+        v5::Packet::Unsubscribe(unsubscribe) => Incoming::Unsubscribe{pkid: unsubscribe.pkid, filters: unsubscribe.filters},
+        v5::Packet::PingReq(_) => Incoming::PingReq,
+        v5::Packet::Disconnect(_) => Incoming::Disconnect,
+        _ => Incoming::Ignored,
+    }
+}
+
+/// Lake path of a topic, None for topics which cannot be published to
+fn path_of_topic(topic: &str) -> Option<Path>
+{
+    // "$SYS/..." and the like are broker internal in MQTT
+    if topic.starts_with('$') || topic.contains(['+', '#', '*'])
+    {
+        return None;
+    }
+    format!("/{}", topic).parse().ok()
+}
+
+/// Lake pattern of a topic filter, None for filters which are invalid or cannot be expressed
+fn pattern_of_filter(filter: &str) -> Option<Path>
+{
+    if !rumqttc::valid_filter(filter) || filter.starts_with('$') || filter.contains('*')
+    {
+        return None;
+    }
+    let elements: Vec<&str> = filter.split('/')
+        .map(|element| match element
+        {
+            "+" => "*",
+            "#" => "**",
+            _ => element,
+        })
+        .collect();
+    format!("/{}", elements.join("/")).parse().ok()
+}
+
+fn topic_of_path(path: &Path) -> String
+{
+    path.to_string().trim_start_matches('/').to_string()
+}
+
+fn message(path: &Path, value: &Value, retain: bool) -> Message
+{
+    Message{topic: topic_of_path(path), payload: Bytes::from(value.to_json()), retain}
+}
+
+/// will forward the values published to the pattern to the client
+async fn run_forwarder(datalake: TDataLake, pattern: Path, mut fisher: Fisher<(Path, Value)>, sender: tokio::sync::mpsc::Sender<Message>)
+{
+    // NOTE: values published meanwhile may be sent twice, but none is missed
+    for (path, value) in datalake.get_matching::<Value>(&pattern).await
+    {
+        if sender.send(message(&path, &value, true)).await.is_err()
+        {
+            return;
+        }
+    }
+    while let Some((path, value)) = fisher.receive().await
+    {
+        // waiting for a slow client would block the publishers
+        if let Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) = sender.try_send(message(&path, &value, false))
+        {
+            break;
+        }
+    }
+}
+
+/// State of one client connection
+struct Session
+{
+    datalake: TDataLake,
+    client_id: String,
+    will: Option<Message>,
+    /// forwarders keyed by topic filter
+    subscriptions: HashMap<String, tokio::task::JoinHandle<()>>,
+    sender: tokio::sync::mpsc::Sender<Message>,
+}
+
+impl Drop for Session
+{
+    fn drop(&mut self)
+    {
+        for forwarder in self.subscriptions.values()
+        {
+            forwarder.abort();
+        }
+    }
+}
+
+impl Session
+{
+    async fn publish(self: &Self, message: Message)
+    {
+        let path = match path_of_topic(&message.topic)
+        {
+            Some(path) => path,
+            None =>
+            {
+                println!("MQTT API: client '{}' published to invalid topic '{}'", self.client_id, message.topic);
+                return;
+            }
+        };
+        let value = crate::mqtt_bridge::decode_payload(&message.payload);
+        match message.retain
+        {
+            true => self.datalake.publish_retained(&path, value).await,
+            false => self.datalake.publish(&path, value).await,
+        }
+    }
+
+    /// Handles one packet, returns the answer to send, if any.
+    async fn handle(self: &mut Self, packet: Incoming) -> Option<Outgoing>
+    {
+        match packet
+        {
+            // a second CONNECT is a protocol violation, treat it like a disconnect
+            Incoming::Connect{..} | Incoming::Disconnect => None,
+            Incoming::Publish{message, qos, pkid} =>
+            {
+                self.publish(message).await;
+                match qos
+                {
+                    QoS::AtMostOnce => None,
+                    QoS::AtLeastOnce => Some(Outgoing::PubAck(pkid)),
+                    QoS::ExactlyOnce => Some(Outgoing::PubRec(pkid)),
+                }
+            }
+            Incoming::PubRel{pkid} => Some(Outgoing::PubComp(pkid)),
+            Incoming::Subscribe{pkid, filters} =>
+            {
+                let mut granted = Vec::new();
+                for filter in filters
+                {
+                    let pattern = match pattern_of_filter(&filter)
+                    {
+                        Some(pattern) if self.datalake.may_subscribe(&pattern) => pattern,
+                        _ =>
+                        {
+                            granted.push(false);
+                            continue;
+                        }
+                    };
+                    let mut datalake = self.datalake.clone();
+                    let fisher = datalake.subscribe_with_path::<Value>(&pattern).await;
+                    let forwarder = tokio::task::spawn(run_forwarder(datalake, pattern, fisher, self.sender.clone()));
+                    // subscribing again replaces the subscription
+                    if let Some(previous) = self.subscriptions.insert(filter, forwarder)
+                    {
+                        previous.abort();
+                    }
+                    granted.push(true);
+                }
+                Some(Outgoing::SubAck{pkid, granted})
+            }
+            Incoming::Unsubscribe{pkid, filters} =>
+            {
+                let removed = filters.iter()
+                    .map(|filter| self.subscriptions.remove(filter).map(|forwarder| forwarder.abort()).is_some())
+                    .collect();
+                Some(Outgoing::UnsubAck{pkid, removed})
+            }
+            Incoming::PingReq => Some(Outgoing::PingResp),
+            Incoming::Ignored => None,
+        }
+    }
+}
+
+/// will serve one client until it disconnects, times out or the broker is stopped
+async fn run_connection(datalake: TDataLake, acl: Acl, mut stream: tokio::net::TcpStream, mut stop: tokio::sync::watch::Receiver<bool>)
+{
+    let mut input = BytesMut::new();
+    let mut output = BytesMut::new();
+
+    // CONNECT first, it tells the protocol version
+    let connect = tokio::time::timeout(CONNECT_TIMEOUT, async {
+        loop
+        {
+            if let Some(connect) = read_connect(&mut input)?
+            {
+                return Ok(connect);
+            }
+            match stream.read_buf(&mut input).await
+            {
+                Ok(0) => return Err("connection closed".to_string()),
+                Ok(_) => (),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }).await;
+    let (version, client_id, keep_alive, will, login) = match connect
+    {
+        Ok(Ok((version, Incoming::Connect{client_id, keep_alive, will, login}))) => (version, client_id, keep_alive, will, login),
+        Ok(Ok(_)) => return,
+        Ok(Err(e)) => return println!("MQTT API: invalid connection attempt: {}", e),
+        Err(_) => return println!("MQTT API: no CONNECT within {:?}", CONNECT_TIMEOUT),
+    };
+    let datalake = match acl.view(&datalake, login.as_ref().map(|(username, _)| username.as_str()), login.as_ref().map(|(_, password)| password.as_str()))
+    {
+        Ok(datalake) => datalake,
+        Err(_) =>
+        {
+            // denial logged by the acl
+            if version.write(Outgoing::ConnAck(false), &mut output).is_ok()
+            {
+                let _ = stream.write_all(&output).await;
+            }
+            return;
+        }
+    };
+    let (sender, mut outgoing) = tokio::sync::mpsc::channel(CLIENT_BUFFER);
+    let mut session = Session{datalake, client_id, will, subscriptions: HashMap::new(), sender};
+    // the client is disconnected after one and a half keep alive intervals without a packet
+    let keep_alive = match keep_alive
+    {
+        0 => Duration::from_secs(365 * 24 * 3600),
+        seconds => Duration::from_millis(seconds as u64 * 1500),
+    };
+
+    let mut result = version.write(Outgoing::ConnAck(true), &mut output);
+    let mut last_packet = tokio::time::Instant::now();
+    let mut disconnected = false;
+    while result.is_ok() && !disconnected
+    {
+        loop
+        {
+            let packet = match version.read(&mut input)
+            {
+                Ok(Some(packet)) => packet,
+                Ok(None) => break,
+                Err(e) =>
+                {
+                    result = Err(e);
+                    break;
+                }
+            };
+            last_packet = tokio::time::Instant::now();
+            if let Incoming::Connect{..} | Incoming::Disconnect = packet
+            {
+                disconnected = matches!(packet, Incoming::Disconnect);
+                if !disconnected
+                {
+                    result = Err("CONNECT sent twice".into());
+                }
+                break;
+            }
+            if let Some(answer) = session.handle(packet).await
+            {
+                if let Err(e) = version.write(answer, &mut output)
+                {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        if !output.is_empty()
+        {
+            if let Err(e) = stream.write_all(&output.split()).await
+            {
+                result = Err(e.to_string());
+            }
+        }
+        if result.is_err() || disconnected
+        {
+            break;
+        }
+        tokio::select!
+        {
+            read = stream.read_buf(&mut input) =>
+            {
+                match read
+                {
+                    Ok(0) => result = Err("connection closed".into()),
+                    Ok(_) => (),
+                    Err(e) => result = Err(e.to_string()),
+                }
+            }
+            Some(message) = outgoing.recv() =>
+            {
+                result = version.write(Outgoing::Publish(message), &mut output);
+            }
+            _ = tokio::time::sleep_until(last_packet + keep_alive) =>
+            {
+                result = Err("keep alive timeout".into());
+            }
+            _ = stop.changed() =>
+            {
+                // Quit (broker stopped), as if the client disconnected
+                disconnected = true;
+            }
+        }
+    }
+    // the will is published if the connection ended without DISCONNECT
+    if let (Err(e), Some(will)) = (&result, session.will.take())
+    {
+        println!("MQTT API: client '{}' lost ({}), publishing its will", session.client_id, e);
+        session.publish(will).await;
+    }
+}
+
+/// Accepts MQTT clients at the given address until stopped.
+pub fn create(datalake: TDataLake, acl: Acl, listen: SocketAddr) -> TaskHandle
+{
+    TaskHandle::spawn(|stop_receiver| async move {
+        match tokio::net::TcpListener::bind(listen).await
+        {
+            Ok(listener) => serve(datalake, acl, listener, stop_receiver).await,
+            Err(e) => println!("MQTT API: cannot listen on {}: {}", listen, e),
+        }
+    })
+}
+
+async fn serve(datalake: TDataLake, acl: Acl, listener: tokio::net::TcpListener, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    let (stop_sender, stop) = tokio::sync::watch::channel(false);
+    loop
+    {
+        tokio::select!
+        {
+            accepted = listener.accept() =>
+            {
+                match accepted
+                {
+                    Ok((stream, _)) => { tokio::task::spawn(run_connection(datalake.clone(), acl.clone(), stream, stop.clone())); }
+                    Err(e) => println!("MQTT API: accepting connection failed: {}", e),
+                }
+            }
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped)
+                break;
+            }
+        }
+    }
+    let _ = stop_sender.send(true);
+}
+
+#[test]
+fn test_topic_translation()
+{
+    assert_eq!(path_of_topic("house/eg/light").map(|path| path.to_string()), Some("/house/eg/light".into()));
+    assert!(path_of_topic("house//light").is_none());
+    assert!(path_of_topic("house/*").is_none());
+    assert!(path_of_topic("$SYS/uptime").is_none());
+    assert_eq!(pattern_of_filter("house/+/light").map(|path| path.to_string()), Some("/house/*/light".into()));
+    assert_eq!(pattern_of_filter("house/#"), "/house/**".parse().ok());
+    assert!(pattern_of_filter("house/#/light").is_none());
+    assert!(pattern_of_filter("house/*").is_none());
+    assert_eq!(topic_of_path(&"/house/eg".parse().unwrap()), "house/eg");
+}
+
+#[tokio::test]
+async fn clients_publish_and_subscribe()
+{
+    use rumqttc::{AsyncClient, Event, MqttOptions, Packet};
+
+    let datalake = TDataLake::new();
+    datalake.publish_retained(&"/house/eg/light".parse().unwrap(), Value::Bool(true)).await;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let broker = TaskHandle::spawn(|stop_receiver| serve(datalake.clone(), Acl::default(), listener, stop_receiver));
+
+    // MQTT 3.1.1 sensor with a will
+    let mut options = MqttOptions::new("sensor", "127.0.0.1", port);
+    options.set_last_will(rumqttc::LastWill::new("sensors/garden/status", "offline", QoS::AtMostOnce, true));
+    let (sensor, mut sensor_events) = AsyncClient::new(options, 10);
+    sensor.publish("sensors/garden/temperature", QoS::AtLeastOnce, true, "12.5").await.unwrap();
+    loop
+    {
+        if let Event::Incoming(Packet::PubAck(_)) = sensor_events.poll().await.unwrap()
+        {
+            break;
+        }
+    }
+    assert_eq!(datalake.get::<Value>(&"/sensors/garden/temperature".parse().unwrap()).await, Some(Value::Float(12.5)));
+
+    // MQTT 5 client receives retained values on subscribe, then live ones
+    let (client, mut client_events) = rumqttc::v5::AsyncClient::new(rumqttc::v5::MqttOptions::new("display", "127.0.0.1", port), 10);
+    client.subscribe("house/#", V5QoS::AtMostOnce).await.unwrap();
+    let mut received = Vec::new();
+    while received.len() < 2
+    {
+        match client_events.poll().await.unwrap()
+        {
+            rumqttc::v5::Event::Incoming(v5::Packet::SubAck(_)) =>
+            {
+                datalake.publish(&"/house/og/light".parse().unwrap(), Value::Bool(false)).await;
+            }
+            rumqttc::v5::Event::Incoming(v5::Packet::Publish(publish)) =>
+            {
+                received.push((String::from_utf8(publish.topic.to_vec()).unwrap(), publish.payload, publish.retain));
+            }
+            _ => (),
+        }
+    }
+    assert_eq!(received, vec![
+        ("house/eg/light".to_string(), Bytes::from("true"), true),
+        ("house/og/light".to_string(), Bytes::from("false"), false),
+    ]);
+
+    // the will is published when the sensor is lost
+    drop(sensor_events);
+    drop(sensor);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(datalake.get::<Value>(&"/sensors/garden/status".parse().unwrap()).await, Some(Value::String("offline".into())));
+
+    drop(client_events);
+    broker.stop().await;
+}
+
+#[tokio::test]
+async fn clients_are_limited_by_the_acl()
+{
+    use rumqttc::{AsyncClient, ConnectReturnCode, Event, MqttOptions, Packet};
+
+    let config: crate::config::Config = r#"
+        [[api.acl.roles]]
+        name = "sensor"
+        publish = ["/sensors/**"]
+        subscribe = ["/sensors/**"]
+        [[api.acl.clients]]
+        name = "garden"
+        token = "1234"
+        role = "sensor"
+    "#.parse().unwrap();
+    let acl = Acl::new(config.api.acl.as_ref().unwrap()).unwrap();
+    let datalake = TDataLake::new();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let broker = TaskHandle::spawn(|stop_receiver| serve(datalake.clone(), acl, listener, stop_receiver));
+
+    // wrong password
+    let mut options = MqttOptions::new("garden", "127.0.0.1", port);
+    options.set_credentials("garden", "4321");
+    let (_client, mut events) = AsyncClient::new(options, 10);
+    match events.poll().await
+    {
+        Err(rumqttc::ConnectionError::ConnectionRefused(code)) => assert_eq!(code, ConnectReturnCode::NotAuthorized),
+        other => panic!("connection not refused: {:?}", other),
+    }
+
+    let mut options = MqttOptions::new("garden", "127.0.0.1", port);
+    options.set_credentials("garden", "1234");
+    let (client, mut events) = AsyncClient::new(options, 10);
+    client.publish("house/eg/door/open", QoS::AtLeastOnce, true, "true").await.unwrap();
+    client.publish("sensors/garden/temperature", QoS::AtLeastOnce, true, "12.5").await.unwrap();
+    client.subscribe("house/#", QoS::AtMostOnce).await.unwrap();
+    loop
+    {
+        if let Event::Incoming(Packet::SubAck(suback)) = events.poll().await.unwrap()
+        {
+            assert_eq!(suback.return_codes, vec![v4::SubscribeReasonCode::Failure]);
+            break;
+        }
+    }
+    assert_eq!(datalake.get::<Value>(&"/sensors/garden/temperature".parse().unwrap()).await, Some(Value::Float(12.5)));
+    assert_eq!(datalake.get::<Value>(&"/house/eg/door/open".parse().unwrap()).await, None);
+
+    drop(events);
+    broker.stop().await;
+}
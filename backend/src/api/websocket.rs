@@ -0,0 +1,475 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use serde::{Deserialize, Serialize};
+
+use crate::data_lake::*;
+use crate::data_lake::path_tree::Path;
+use crate::shutdown::TaskHandle;
+use super::acl::{bearer_token, Acl};
+
+// Protocol: one JSON object per text message.
+// Requests carry an "op" and an optional "id", which is repeated in the reply:
+//   {"op": "subscribe", "id": 1, "pattern": "/house/**", "send_retained": true}
+//       -> {"type": "subscribed", "id": 1, "subscription": 1}
+//       then {"type": "message", "subscription": 1, "path": "/house/eg/light", "value": true} per value
+//   {"op": "unsubscribe", "id": 2, "subscription": 1}   -> {"type": "unsubscribed", "id": 2, "subscription": 1}
+//   {"op": "publish", "id": 3, "path": "/house/eg/light", "value": false, "retain": true} -> {"type": "published", "id": 3}
+//   {"op": "get", "id": 4, "pattern": "/house/*/light"} -> {"type": "values", "id": 4, "values": [{"path": .., "value": ..}]}
+//   {"op": "browse", "id": 5, "path": "/house"}         -> {"type": "entries", "id": 5, "entries": [{"path": .., "has_value": .., "has_children": ..}]}
+//   {"op": "ping", "id": 6}                             -> {"type": "pong", "id": 6}
+// Failed requests are answered with {"type": "error", "id": .., "message": ..}.
+// With access control, clients connect with "Authorization: Bearer <token>" or "/?token=<token>",
+// connections with unknown tokens are refused (401). Values the client may not receive are left out.
+
+/// Interval of the WebSocket pings sent by the server
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+/// Connections silent for longer than this (not even answering pings) are closed
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Messages buffered per connection, a subscription falling further behind is cancelled
+const CONNECTION_BUFFER: usize = 256;
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+enum Request
+{
+    Subscribe{id: Option<u64>, pattern: String, #[serde(default)] send_retained: bool},
+    Unsubscribe{id: Option<u64>, subscription: u64},
+    Publish{id: Option<u64>, path: String, value: serde_json::Value, #[serde(default)] retain: bool},
+    Get{id: Option<u64>, pattern: String},
+    Browse{id: Option<u64>, path: String},
+    Ping{id: Option<u64>},
+}
+
+#[derive(Serialize, Debug)]
+struct PathValue
+{
+    path: String,
+    value: serde_json::Value,
+}
+
+#[derive(Serialize, Debug)]
+struct BrowseEntry
+{
+    path: String,
+    has_value: bool,
+    has_children: bool,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply
+{
+    Subscribed{id: Option<u64>, subscription: u64},
+    Unsubscribed{id: Option<u64>, subscription: u64},
+    Published{id: Option<u64>},
+    Values{id: Option<u64>, values: Vec<PathValue>},
+    Entries{id: Option<u64>, entries: Vec<BrowseEntry>},
+    Pong{id: Option<u64>},
+    Message{subscription: u64, path: String, value: serde_json::Value},
+    Error
+    {
+        id: Option<u64>,
+        /// set if a subscription was cancelled
+        #[serde(skip_serializing_if = "Option::is_none")]
+        subscription: Option<u64>,
+        message: String,
+    },
+}
+
+fn error(id: Option<u64>, message: String) -> Reply
+{
+    Reply::Error{id, subscription: None, message}
+}
+
+fn parse_path(path: &str) -> Result<Path, String>
+{
+    path.parse().map_err(|e| format!("Invalid path '{}': {}", path, e))
+}
+
+/// Subscriptions of one connection, keyed by the id handed out to the client
+struct Subscriptions
+{
+    next_id: u64,
+    forwarders: HashMap<u64, tokio::task::JoinHandle<()>>,
+}
+
+impl Drop for Subscriptions
+{
+    fn drop(&mut self)
+    {
+        for forwarder in self.forwarders.values()
+        {
+            forwarder.abort();
+        }
+    }
+}
+
+/// will forward all values published to the pattern to the connection
+async fn run_forwarder(datalake: TDataLake, pattern: Path, send_retained: bool, subscription: u64, mut fisher: Fisher<(Path, Value)>, sender: tokio::sync::mpsc::Sender<Reply>)
+{
+    let message = |path: &Path, value: &Value| Reply::Message{subscription, path: path.to_string(), value: value.to_serde_json()};
+    if send_retained
+    {
+        // NOTE: values published meanwhile may be sent twice, but none is missed
+        for (path, value) in datalake.get_matching::<Value>(&pattern).await
+        {
+            if sender.send(message(&path, &value)).await.is_err()
+            {
+                return;
+            }
+        }
+    }
+    while let Some((path, value)) = fisher.receive().await
+    {
+        // waiting for a slow client would block the publishers
+        match sender.try_send(message(&path, &value))
+        {
+            Ok(()) => (),
+            Err(tokio::sync::mpsc::error::TrySendError::Full(_)) =>
+            {
+                let message = "Subscriber too slow, subscription cancelled".to_string();
+                let _ = sender.send(Reply::Error{id: None, subscription: Some(subscription), message}).await;
+                break;
+            }
+            Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => break,
+        }
+    }
+}
+
+async fn handle_request(datalake: &TDataLake, request: Request, subscriptions: &mut Subscriptions, sender: &tokio::sync::mpsc::Sender<Reply>) -> Result<Reply, String>
+{
+    match request
+    {
+        Request::Subscribe{id, pattern, send_retained} =>
+        {
+            let pattern = parse_path(&pattern)?;
+            if !datalake.may_subscribe(&pattern)
+            {
+                return Err(format!("Subscribing to '{}' is not allowed", pattern));
+            }
+            let mut datalake = datalake.clone();
+            // subscribe before replying, so the client does not miss anything published after the reply
+            let fisher = datalake.subscribe_with_path::<Value>(&pattern).await;
+            let subscription = subscriptions.next_id;
+            subscriptions.next_id += 1;
+            let forwarder = tokio::task::spawn(run_forwarder(datalake, pattern, send_retained, subscription, fisher, sender.clone()));
+            subscriptions.forwarders.insert(subscription, forwarder);
+            Ok(Reply::Subscribed{id, subscription})
+        }
+        Request::Unsubscribe{id, subscription} =>
+        {
+            let forwarder = subscriptions.forwarders.remove(&subscription)
+                .ok_or(format!("Unknown subscription {}", subscription))?;
+            forwarder.abort();
+            Ok(Reply::Unsubscribed{id, subscription})
+        }
+        Request::Publish{id, path, value, retain} =>
+        {
+            let parsed = parse_path(&path)?;
+            if parsed.has_wildcards()
+            {
+                return Err(format!("Cannot publish to '{}', wildcards are not allowed", path));
+            }
+            if !datalake.may_publish(&parsed)
+            {
+                return Err(format!("Publishing to '{}' is not allowed", path));
+            }
+            let value = Value::from_serde_json(value);
+            match retain
+            {
+                true => datalake.publish_retained(&parsed, value).await,
+                false => datalake.publish(&parsed, value).await,
+            }
+            Ok(Reply::Published{id})
+        }
+        Request::Get{id, pattern} =>
+        {
+            let mut values = datalake.get_matching::<Value>(&parse_path(&pattern)?).await;
+            values.sort_by_key(|(path, _)| path.to_string());
+            let values = values.iter().map(|(path, value)| PathValue{path: path.to_string(), value: value.to_serde_json()}).collect();
+            Ok(Reply::Values{id, values})
+        }
+        Request::Browse{id, path} =>
+        {
+            let mut entries = Vec::new();
+            for (child, has_children) in datalake.browse(&parse_path(&path)?).await
+            {
+                let has_value = datalake.get::<Value>(&child).await.is_some();
+                entries.push(BrowseEntry{path: child.to_string(), has_value, has_children});
+            }
+            Ok(Reply::Entries{id, entries})
+        }
+        Request::Ping{id} => Ok(Reply::Pong{id}),
+    }
+}
+
+async fn handle_text(datalake: &TDataLake, text: &str, subscriptions: &mut Subscriptions, sender: &tokio::sync::mpsc::Sender<Reply>) -> Reply
+{
+    let request: serde_json::Value = match serde_json::from_str(text)
+    {
+        Ok(request) => request,
+        Err(e) => return error(None, format!("Invalid JSON: {}", e)),
+    };
+    // the id is needed for the error reply even if the rest of the request is invalid
+    let id = request.get("id").and_then(|id| id.as_u64());
+    let request = match serde_json::from_value(request)
+    {
+        Ok(request) => request,
+        Err(e) => return error(id, format!("Invalid request: {}", e)),
+    };
+    match handle_request(datalake, request, subscriptions, sender).await
+    {
+        Ok(reply) => reply,
+        Err(e) => error(id, e),
+    }
+}
+
+async fn send(socket: &mut WebSocket, reply: &Reply) -> Result<(), axum::Error>
+{
+    // serializing plain data structures does not fail
+    socket.send(Message::Text(serde_json::to_string(reply).unwrap())).await
+}
+
+/// will serve one client until it disconnects, stops answering or the server is stopped
+async fn run_connection(datalake: TDataLake, mut socket: WebSocket, mut stop: tokio::sync::watch::Receiver<bool>)
+{
+    let (sender, mut outgoing) = tokio::sync::mpsc::channel(CONNECTION_BUFFER);
+    let mut subscriptions = Subscriptions{next_id: 1, forwarders: HashMap::new()};
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    let mut last_seen = Instant::now();
+    loop
+    {
+        tokio::select!
+        {
+            message = socket.recv() =>
+            {
+                let reply = match message
+                {
+                    Some(Ok(Message::Text(text))) => handle_text(&datalake, &text, &mut subscriptions, &sender).await,
+                    Some(Ok(Message::Binary(_))) => error(None, "Binary messages are not supported".into()),
+                    // pings are answered by the WebSocket implementation
+                    Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) =>
+                    {
+                        last_seen = Instant::now();
+                        continue;
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                };
+                last_seen = Instant::now();
+                if send(&mut socket, &reply).await.is_err()
+                {
+                    break;
+                }
+            }
+            Some(reply) = outgoing.recv() =>
+            {
+                if send(&mut socket, &reply).await.is_err()
+                {
+                    break;
+                }
+            }
+            _ = heartbeat.tick() =>
+            {
+                if last_seen.elapsed() > HEARTBEAT_TIMEOUT || socket.send(Message::Ping(Vec::new())).await.is_err()
+                {
+                    break;
+                }
+            }
+            _ = stop.changed() =>
+            {
+                // Quit (server stopped)
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ServerState
+{
+    datalake: TDataLake,
+    acl: Acl,
+    stop: tokio::sync::watch::Receiver<bool>,
+}
+
+async fn upgrade(upgrade: WebSocketUpgrade, State(state): State<ServerState>, headers: axum::http::HeaderMap,
+    axum::extract::Query(query): axum::extract::Query<HashMap<String, String>>) -> axum::response::Response
+{
+    use axum::response::IntoResponse;
+
+    // browsers cannot set headers of WebSocket connections, they pass the token in the URL
+    let token = headers.get(axum::http::header::AUTHORIZATION).and_then(|value| value.to_str().ok()).and_then(bearer_token)
+        .or(query.get("token").map(String::as_str));
+    match state.acl.view(&state.datalake, None, token)
+    {
+        Ok(datalake) => upgrade.on_upgrade(move |socket| run_connection(datalake, socket, state.stop)),
+        Err(e) => (axum::http::StatusCode::UNAUTHORIZED, e).into_response(),
+    }
+}
+
+/// Serves the WebSocket JSON API at the given address until stopped.
+pub fn create(datalake: TDataLake, acl: Acl, listen: SocketAddr) -> TaskHandle
+{
+    TaskHandle::spawn(|stop_receiver| async move {
+        match std::net::TcpListener::bind(listen)
+        {
+            Ok(listener) => serve(datalake, acl, listener, stop_receiver).await,
+            Err(e) => println!("WebSocket API: cannot listen on {}: {}", listen, e),
+        }
+    })
+}
+
+async fn serve(datalake: TDataLake, acl: Acl, listener: std::net::TcpListener, stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    // upgraded connections are not tracked by the server, they are stopped separately
+    let (stop_sender, stop) = tokio::sync::watch::channel(false);
+    let app = axum::Router::new()
+        .route("/", axum::routing::get(upgrade))
+        .with_state(ServerState{datalake, acl, stop});
+    let shutdown = async move {
+        stop_receiver.await.ok();
+        let _ = stop_sender.send(true);
+    };
+    let result = super::serve_router(app, listener, shutdown).await;
+    if let Err(e) = result
+    {
+        println!("WebSocket API failed: {}", e);
+    }
+}
+
+#[tokio::test]
+async fn subscribe_publish_get_browse()
+{
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    let datalake = TDataLake::new();
+    datalake.publish_retained(&"/house/eg/light".parse().unwrap(), Value::Bool(false)).await;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}/", listener.local_addr().unwrap());
+    let server = TaskHandle::spawn(|stop_receiver| serve(datalake.clone(), Acl::default(), listener, stop_receiver));
+    let (mut client, _) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+    type Client = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+    /// sends the request (if any), returns the next JSON message received
+    async fn request(client: &mut Client, request: &str) -> serde_json::Value
+    {
+        if !request.is_empty()
+        {
+            client.send(Message::Text(request.into())).await.unwrap();
+        }
+        loop
+        {
+            match client.next().await.unwrap().unwrap()
+            {
+                Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+                _ => continue,
+            }
+        }
+    }
+
+    let reply = request(&mut client, r#"{"op": "subscribe", "id": 1, "pattern": "/house/**", "send_retained": true}"#).await;
+    assert_eq!(reply, serde_json::json!({"type": "subscribed", "id": 1, "subscription": 1}));
+    let reply = request(&mut client, "").await;
+    assert_eq!(reply, serde_json::json!({"type": "message", "subscription": 1, "path": "/house/eg/light", "value": false}));
+
+    let reply = request(&mut client, r#"{"op": "publish", "id": 2, "path": "/house/og/temperature", "value": 21.5, "retain": true}"#).await;
+    assert_eq!(reply, serde_json::json!({"type": "published", "id": 2}));
+    let reply = request(&mut client, "").await;
+    assert_eq!(reply, serde_json::json!({"type": "message", "subscription": 1, "path": "/house/og/temperature", "value": 21.5}));
+    assert_eq!(datalake.get::<Value>(&"/house/og/temperature".parse().unwrap()).await, Some(Value::Float(21.5)));
+
+    let reply = request(&mut client, r#"{"op": "get", "id": 3, "pattern": "/house/*/*"}"#).await;
+    assert_eq!(reply["values"], serde_json::json!([
+        {"path": "/house/eg/light", "value": false},
+        {"path": "/house/og/temperature", "value": 21.5},
+    ]));
+    let reply = request(&mut client, r#"{"op": "browse", "id": 4, "path": "/house"}"#).await;
+    assert_eq!(reply["entries"], serde_json::json!([
+        {"path": "/house/eg", "has_value": false, "has_children": true},
+        {"path": "/house/og", "has_value": false, "has_children": true},
+    ]));
+
+    let reply = request(&mut client, r#"{"op": "unsubscribe", "id": 5, "subscription": 1}"#).await;
+    assert_eq!(reply, serde_json::json!({"type": "unsubscribed", "id": 5, "subscription": 1}));
+    datalake.publish(&"/house/eg/light".parse().unwrap(), Value::Bool(true)).await;
+    let reply = request(&mut client, r#"{"op": "ping", "id": 6}"#).await;
+    assert_eq!(reply, serde_json::json!({"type": "pong", "id": 6}));
+
+    let reply = request(&mut client, r#"{"op": "publish", "id": 7, "path": "/house/*", "value": 1}"#).await;
+    assert_eq!(reply["type"], "error");
+    assert_eq!(reply["id"], 7);
+    let reply = request(&mut client, r#"{"op": "unsubscribe", "id": 8, "subscription": 1}"#).await;
+    assert_eq!(reply["message"], "Unknown subscription 1");
+    let reply = request(&mut client, r#"{"op": "jump", "id": 9}"#).await;
+    assert_eq!(reply["id"], 9);
+    let reply = request(&mut client, "{").await;
+    assert_eq!(reply["id"], serde_json::Value::Null);
+
+    // stopping the server closes open connections
+    server.stop().await;
+    loop
+    {
+        match client.next().await
+        {
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+            Some(Ok(_)) => continue,
+        }
+    }
+}
+
+#[tokio::test]
+async fn clients_are_limited_by_the_acl()
+{
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    let config: crate::config::Config = r#"
+        [[api.acl.roles]]
+        name = "guest"
+        publish = ["/house/*/light"]
+        subscribe = ["/house/**"]
+        [[api.acl.clients]]
+        name = "tablet"
+        token = "1234"
+        role = "guest"
+    "#.parse().unwrap();
+    let acl = Acl::new(config.api.acl.as_ref().unwrap()).unwrap();
+    let datalake = TDataLake::new();
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = TaskHandle::spawn(|stop_receiver| serve(datalake.clone(), acl, listener, stop_receiver));
+
+    assert!(tokio_tungstenite::connect_async(format!("ws://{}/", address)).await.is_err());
+    assert!(tokio_tungstenite::connect_async(format!("ws://{}/?token=4321", address)).await.is_err());
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{}/?token=1234", address)).await.unwrap();
+
+    type Client = tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+    async fn request(client: &mut Client, request: &str) -> serde_json::Value
+    {
+        client.send(Message::Text(request.into())).await.unwrap();
+        loop
+        {
+            if let Message::Text(text) = client.next().await.unwrap().unwrap()
+            {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    let reply = request(&mut client, r#"{"op": "publish", "id": 1, "path": "/house/eg/light", "value": true, "retain": true}"#).await;
+    assert_eq!(reply, serde_json::json!({"type": "published", "id": 1}));
+    let reply = request(&mut client, r#"{"op": "publish", "id": 2, "path": "/house/eg/door/open", "value": true}"#).await;
+    assert_eq!(reply, serde_json::json!({"type": "error", "id": 2, "message": "Publishing to '/house/eg/door/open' is not allowed"}));
+    let reply = request(&mut client, r#"{"op": "subscribe", "id": 3, "pattern": "/bus/**"}"#).await;
+    assert_eq!(reply["type"], "error");
+    assert_eq!(datalake.get::<Value>(&"/house/eg/light".parse().unwrap()).await, Some(Value::Bool(true)));
+
+    server.stop().await;
+}
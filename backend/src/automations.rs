@@ -0,0 +1,512 @@
+//! Runs the `[[automations]]` of the configuration: whenever a `Value` is published to a path
+//! matching the trigger of an automation and all its conditions hold, its actions are run.
+//!
+//! Every automation has its state below `AUTOMATIONS_PATH/<name>`:
+//!   enabled: `Value::Bool`, publish to it to enable or disable the automation at runtime
+//!   trace:   `Value::Map` describing the latest run, or why the automation did not run
+//!
+//! An automation running more than `LOOP_MAX_RUNS` times within `LOOP_WINDOW` (triggered with all
+//! conditions holding) is assumed to trigger itself (directly or through others) and is disabled.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::bus_access::{TransmitRequest, ROUTED_TX_PATH};
+use crate::config::{ActionConfig, AutomationConfig, ConditionConfig, ConditionOperator};
+use crate::data_lake::*;
+use crate::data_lake::path_tree::Path;
+use crate::shutdown::TaskHandle;
+
+pub const AUTOMATIONS_PATH: &str = "/system/automations";
+
+/// Path of the given state of an automation, fails if the name cannot be used as a path element.
+pub fn state_path(name: &str, state: &str) -> Result<Path, String>
+{
+    AUTOMATIONS_PATH.parse::<Path>()?.child(name)?.child(state)
+}
+
+const LOOP_WINDOW: Duration = Duration::from_secs(1);
+const LOOP_MAX_RUNS: usize = 20;
+
+enum Action
+{
+    Publish(Path, Value),
+    Transmit(String, Value),
+    Delay(Duration),
+}
+
+impl fmt::Display for Action
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            Action::Publish(path, value) => write!(f, "publish {} to {}", value, path),
+            Action::Transmit(device, value) => write!(f, "transmit {} to device {}", value, device),
+            Action::Delay(duration) => write!(f, "delay {} ms", duration.as_millis()),
+        }
+    }
+}
+
+struct Condition
+{
+    /// None for the triggering value
+    path: Option<Path>,
+    op: ConditionOperator,
+    value: Value,
+}
+
+impl fmt::Display for Condition
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let path = self.path.as_ref().map(|path| path.to_string()).unwrap_or("trigger value".into());
+        write!(f, "{} {:?} {}", path, self.op, self.value)
+    }
+}
+
+fn number(value: &Value) -> Option<f64>
+{
+    match value
+    {
+        Value::Integer(i) => Some(*i as f64),
+        Value::Float(f) => Some(*f),
+        _ => None,
+    }
+}
+
+impl Condition
+{
+    /// A missing value only satisfies `ne`
+    fn holds(self: &Self, actual: Option<&Value>) -> bool
+    {
+        let actual = match actual
+        {
+            Some(actual) => actual,
+            None => return self.op == ConditionOperator::Ne,
+        };
+        let ordering = match (number(actual), number(&self.value), actual, &self.value)
+        {
+            (Some(a), Some(b), _, _) => a.partial_cmp(&b),
+            (_, _, Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            _ if actual == &self.value => Some(std::cmp::Ordering::Equal),
+            _ => None,
+        };
+        match self.op
+        {
+            ConditionOperator::Eq => ordering == Some(std::cmp::Ordering::Equal),
+            ConditionOperator::Ne => ordering != Some(std::cmp::Ordering::Equal),
+            ConditionOperator::Lt => ordering == Some(std::cmp::Ordering::Less),
+            ConditionOperator::Le => matches!(ordering, Some(std::cmp::Ordering::Less | std::cmp::Ordering::Equal)),
+            ConditionOperator::Gt => ordering == Some(std::cmp::Ordering::Greater),
+            ConditionOperator::Ge => matches!(ordering, Some(std::cmp::Ordering::Greater | std::cmp::Ordering::Equal)),
+        }
+    }
+}
+
+struct Automation
+{
+    name: String,
+    enabled: bool,
+    trigger: Path,
+    conditions: Vec<Condition>,
+    actions: std::sync::Arc<Vec<Action>>,
+    /// start times of the runs within `LOOP_WINDOW`
+    recent_runs: VecDeque<Instant>,
+}
+
+impl Automation
+{
+    // paths were validated with the configuration
+    fn new(config: &AutomationConfig) -> Self
+    {
+        let conditions = config.conditions.iter().map(|condition: &ConditionConfig| Condition{
+            path: condition.path.as_ref().map(|path| path.parse().unwrap()),
+            op: condition.op,
+            value: Value::from_toml(&condition.value),
+        }).collect();
+        let actions = config.actions.iter().map(|action| match action
+        {
+            ActionConfig::Publish{path, value} => Action::Publish(path.parse().unwrap(), Value::from_toml(value)),
+            ActionConfig::Transmit{device, value} => Action::Transmit(device.clone(), Value::from_toml(value)),
+            ActionConfig::Delay{milliseconds} => Action::Delay(Duration::from_millis(*milliseconds)),
+        }).collect();
+        Automation{
+            name: config.name.clone(),
+            enabled: config.enabled,
+            trigger: config.trigger.parse().unwrap(),
+            conditions,
+            actions: std::sync::Arc::new(actions),
+            recent_runs: VecDeque::new(),
+        }
+    }
+
+    fn state_path(self: &Self, state: &str) -> Path
+    {
+        // names were checked with `state_path` with the configuration
+        state_path(&self.name, state).unwrap()
+    }
+
+    /// Returns true if the automation ran too often recently, counts this run otherwise.
+    fn is_looping(self: &mut Self, now: Instant) -> bool
+    {
+        while self.recent_runs.front().is_some_and(|start| now.duration_since(*start) > LOOP_WINDOW)
+        {
+            self.recent_runs.pop_front();
+        }
+        if self.recent_runs.len() >= LOOP_MAX_RUNS
+        {
+            return true;
+        }
+        self.recent_runs.push_back(now);
+        false
+    }
+
+    /// Returns the first condition not holding.
+    async fn failed_condition(self: &Self, datalake: &TDataLake, trigger_value: &Value) -> Option<&Condition>
+    {
+        for condition in self.conditions.iter()
+        {
+            let holds = match &condition.path
+            {
+                Some(path) => condition.holds(datalake.get::<Value>(path).await.as_ref()),
+                None => condition.holds(Some(trigger_value)),
+            };
+            if !holds
+            {
+                return Some(condition);
+            }
+        }
+        None
+    }
+}
+
+fn trace(trigger: &Path, value: &Value, result: String, actions: Vec<String>) -> Value
+{
+    let at = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|at| at.as_secs() as i64).unwrap_or(0);
+    Value::Map(vec![
+        (Value::String("trigger".into()), Value::String(trigger.to_string())),
+        (Value::String("value".into()), value.clone()),
+        (Value::String("at".into()), Value::Integer(at)),
+        (Value::String("result".into()), Value::String(result)),
+        (Value::String("actions".into()), Value::Array(actions.into_iter().map(Value::String).collect())),
+    ])
+}
+
+/// will run the actions one after the other and publish the trace afterwards
+async fn run_actions(datalake: TDataLake, actions: std::sync::Arc<Vec<Action>>, trace_path: Path, trigger: Path, value: Value)
+{
+    let mut executed = Vec::new();
+    for action in actions.iter()
+    {
+        match action
+        {
+            Action::Publish(path, value) => datalake.publish_retained(path, value.clone()).await,
+            Action::Transmit(device, value) =>
+            {
+                let request = TransmitRequest{device_id: device.clone(), payload: value.clone()};
+                datalake.publish(&ROUTED_TX_PATH.parse().unwrap(), request).await;
+            }
+            Action::Delay(duration) => tokio::time::sleep(*duration).await,
+        }
+        executed.push(action.to_string());
+    }
+    datalake.publish_retained(&trace_path, trace(&trigger, &value, "done".into(), executed)).await;
+}
+
+enum Event
+{
+    Triggered(usize, Path, Value),
+    EnabledChanged(Path, Value),
+}
+
+/// Runs the automations until stopped.
+pub fn create(datalake: TDataLake, automations: Vec<AutomationConfig>) -> TaskHandle
+{
+    TaskHandle::spawn(|rx| run_automations(datalake, automations, rx))
+}
+
+async fn run_automations(mut datalake: TDataLake, configs: Vec<AutomationConfig>, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    let mut automations: Vec<Automation> = configs.iter().map(Automation::new).collect();
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut forwarders = Vec::new();
+    for (i, automation) in automations.iter().enumerate()
+    {
+        let mut fisher = datalake.subscribe_with_path::<Value>(&automation.trigger).await;
+        let sender = sender.clone();
+        forwarders.push(tokio::task::spawn(async move {
+            while let Some((path, value)) = fisher.receive().await
+            {
+                if sender.send(Event::Triggered(i, path, value)).is_err()
+                {
+                    break;
+                }
+            }
+        }));
+    }
+    let mut fisher = datalake.subscribe_with_path::<Value>(&format!("{}/*/enabled", AUTOMATIONS_PATH).parse().unwrap()).await;
+    forwarders.push(tokio::task::spawn(async move {
+        while let Some((path, value)) = fisher.receive().await
+        {
+            if sender.send(Event::EnabledChanged(path, value)).is_err()
+            {
+                break;
+            }
+        }
+    }));
+    for automation in automations.iter()
+    {
+        datalake.publish_retained(&automation.state_path("enabled"), Value::Bool(automation.enabled)).await;
+    }
+
+    let own_state: Path = AUTOMATIONS_PATH.parse().unwrap();
+    let mut runs: Vec<tokio::task::JoinHandle<()>> = Vec::new();
+    loop
+    {
+        tokio::select!
+        {
+            Some(event) = receiver.recv() =>
+            {
+                match event
+                {
+                    Event::EnabledChanged(path, value) =>
+                    {
+                        let automation = match automations.iter_mut().find(|automation| automation.state_path("enabled") == path)
+                        {
+                            Some(automation) => automation,
+                            None => continue,
+                        };
+                        match value
+                        {
+                            Value::Bool(enabled) if enabled != automation.enabled =>
+                            {
+                                automation.enabled = enabled;
+                                automation.recent_runs.clear();
+                                println!("Automation '{}' {}", automation.name, if enabled { "enabled" } else { "disabled" });
+                            }
+                            Value::Bool(_) => (),
+                            other => println!("Automation '{}': ignoring {} published to {}, expected true or false", automation.name, other, path),
+                        }
+                    }
+                    Event::Triggered(i, path, value) =>
+                    {
+                        // the automations' own state must not trigger them
+                        if path.starts_with(&own_state)
+                        {
+                            continue;
+                        }
+                        let automation = &mut automations[i];
+                        if !automation.enabled
+                        {
+                            continue;
+                        }
+                        if let Some(condition) = automation.failed_condition(&datalake, &value).await
+                        {
+                            let result = format!("condition not met: {}", condition);
+                            datalake.publish_retained(&automation.state_path("trace"), trace(&path, &value, result, Vec::new())).await;
+                            continue;
+                        }
+                        // only runs count, busy triggers whose conditions do not hold are fine
+                        if automation.is_looping(Instant::now())
+                        {
+                            println!("Automation '{}' ran more than {} times within {:?}, disabled", automation.name, LOOP_MAX_RUNS, LOOP_WINDOW);
+                            automation.enabled = false;
+                            let result = format!("loop detected, more than {} runs within {:?}, disabled", LOOP_MAX_RUNS, LOOP_WINDOW);
+                            datalake.publish_retained(&automation.state_path("trace"), trace(&path, &value, result, Vec::new())).await;
+                            datalake.publish_retained(&automation.state_path("enabled"), Value::Bool(false)).await;
+                            continue;
+                        }
+                        runs.retain(|run| !run.is_finished());
+                        runs.push(tokio::task::spawn(run_actions(datalake.clone(), automation.actions.clone(), automation.state_path("trace"), path, value)));
+                    }
+                }
+            }
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped), runs still delaying are cancelled
+                break;
+            }
+        }
+    }
+    for task in forwarders.iter().chain(runs.iter())
+    {
+        task.abort();
+    }
+}
+
+#[cfg(test)]
+async fn wait_for_trace(datalake: &TDataLake, name: &str, result: &str) -> Value
+{
+    let path = state_path(name, "trace").unwrap();
+    for _ in 0..500
+    {
+        if let Some(trace) = datalake.get::<Value>(&path).await
+        {
+            if trace.get("result") == Some(&Value::String(result.into()))
+            {
+                return trace;
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("no trace '{}' for automation '{}'", result, name);
+}
+
+#[test]
+fn test_conditions()
+{
+    let condition = |op, value| Condition{path: None, op, value};
+    assert!(condition(ConditionOperator::Eq, Value::Integer(3)).holds(Some(&Value::Float(3.0))));
+    assert!(condition(ConditionOperator::Gt, Value::Integer(20)).holds(Some(&Value::Float(21.5))));
+    assert!(!condition(ConditionOperator::Le, Value::Integer(20)).holds(Some(&Value::Float(21.5))));
+    assert!(condition(ConditionOperator::Lt, Value::String("b".into())).holds(Some(&Value::String("a".into()))));
+    assert!(condition(ConditionOperator::Eq, Value::Bool(true)).holds(Some(&Value::Bool(true))));
+    assert!(!condition(ConditionOperator::Gt, Value::Bool(true)).holds(Some(&Value::Bool(false))));
+    assert!(condition(ConditionOperator::Ne, Value::String("night".into())).holds(Some(&Value::Integer(1))));
+    assert!(condition(ConditionOperator::Ne, Value::String("night".into())).holds(None));
+    assert!(!condition(ConditionOperator::Eq, Value::Nil).holds(None));
+}
+
+#[tokio::test]
+async fn automations_run_actions_when_conditions_hold()
+{
+    let config: crate::config::Config = r#"
+        [[gateways]]
+        devices = ["7"]
+        [[automations]]
+        name = "door bell"
+        trigger = "/bus/ug/rx/*"
+        conditions = [{path = "/house/mode", op = "ne", value = "night"}, {value = {pressed = true}}]
+        actions = [
+            {type = "publish", path = "/house/bell", value = true},
+            {type = "delay", milliseconds = 10},
+            {type = "transmit", device = "7", value = {on = true}},
+        ]
+        [[automations]]
+        name = "echo"
+        trigger = "/echo"
+        actions = [{type = "publish", path = "/echo", value = 1}]
+    "#.parse().unwrap();
+    let mut datalake = TDataLake::new();
+    let mut transmitted = datalake.subscribe::<TransmitRequest>(&ROUTED_TX_PATH.parse().unwrap()).await;
+    let handle = create(datalake.clone(), config.automations);
+    let bell: Path = "/house/bell".parse().unwrap();
+    let pressed = Value::from_json(r#"{"pressed": true}"#).unwrap();
+    // wait for the engine to subscribe
+    while datalake.get::<Value>(&"/system/automations/echo/enabled".parse().unwrap()).await.is_none()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    datalake.publish_retained(&"/house/mode".parse().unwrap(), Value::String("night".into())).await;
+    datalake.publish(&"/bus/ug/rx/5".parse().unwrap(), pressed.clone()).await;
+    wait_for_trace(&datalake, "door bell", "condition not met: /house/mode Ne \"night\"").await;
+    assert_eq!(datalake.get::<Value>(&bell).await, None);
+
+    datalake.publish_retained(&"/house/mode".parse().unwrap(), Value::String("day".into())).await;
+    datalake.publish(&"/bus/ug/rx/5".parse().unwrap(), pressed.clone()).await;
+    let trace = wait_for_trace(&datalake, "door bell", "done").await;
+    assert_eq!(trace.get("trigger"), Some(&Value::String("/bus/ug/rx/5".into())));
+    assert_eq!(trace.get("actions").map(|actions| actions.to_json()), Some(r#"["publish true to /house/bell","delay 10 ms","transmit {\"on\":true} to device 7"]"#.into()));
+    assert_eq!(datalake.get::<Value>(&bell).await, Some(Value::Bool(true)));
+    assert_eq!(transmitted.receive().await.map(|request| request.device_id), Some("7".into()));
+
+    // disabled at runtime
+    datalake.publish_retained(&bell, Value::Bool(false)).await;
+    datalake.publish_retained(&"/system/automations/door bell/enabled".parse().unwrap(), Value::Bool(false)).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    datalake.publish(&"/bus/ug/rx/5".parse().unwrap(), pressed.clone()).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(datalake.get::<Value>(&bell).await, Some(Value::Bool(false)));
+
+    // an automation triggering itself is stopped
+    datalake.publish(&"/echo".parse().unwrap(), Value::Integer(0)).await;
+    wait_for_trace(&datalake, "echo", "loop detected, more than 20 runs within 1s, disabled").await;
+    assert_eq!(datalake.get::<Value>(&"/system/automations/echo/enabled".parse().unwrap()).await, Some(Value::Bool(false)));
+
+    handle.stop().await;
+}
+
+#[tokio::test]
+async fn own_state_does_not_trigger()
+{
+    let config: crate::config::Config = r#"
+        [[automations]]
+        name = "watch"
+        trigger = "/system/**"
+        actions = [{type = "publish", path = "/seen", value = true}]
+    "#.parse().unwrap();
+    let datalake = TDataLake::new();
+    let handle = create(datalake.clone(), config.automations);
+    while datalake.get::<Value>(&"/system/automations/watch/enabled".parse().unwrap()).await.is_none()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    // only the path element matters, not the text
+    datalake.publish(&"/system/automationsX/state".parse().unwrap(), Value::Integer(1)).await;
+    let trace = wait_for_trace(&datalake, "watch", "done").await;
+    assert_eq!(trace.get("trigger"), Some(&Value::String("/system/automationsX/state".into())));
+
+    handle.stop().await;
+}
+
+#[tokio::test]
+async fn failed_conditions_do_not_count_as_runs()
+{
+    let config: crate::config::Config = r#"
+        [[automations]]
+        name = "alarm"
+        trigger = "/sensor"
+        conditions = [{value = 1}]
+        actions = [{type = "publish", path = "/alarm", value = true}]
+    "#.parse().unwrap();
+    let datalake = TDataLake::new();
+    let handle = create(datalake.clone(), config.automations);
+    let enabled: Path = "/system/automations/alarm/enabled".parse().unwrap();
+    while datalake.get::<Value>(&enabled).await.is_none()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    for _ in 0..LOOP_MAX_RUNS * 2
+    {
+        datalake.publish(&"/sensor".parse().unwrap(), Value::Integer(0)).await;
+    }
+    datalake.publish(&"/sensor".parse().unwrap(), Value::Integer(1)).await;
+    wait_for_trace(&datalake, "alarm", "done").await;
+    assert_eq!(datalake.get::<Value>(&enabled).await, Some(Value::Bool(true)));
+
+    handle.stop().await;
+}
+
+#[tokio::test]
+async fn transmit_via_router_to_only_gateway()
+{
+    let config: crate::config::Config = r#"
+        [[gateways]]
+        base_path = "/bus/ug"
+        devices = []
+        [[automations]]
+        name = "door bell"
+        trigger = "/bell"
+        actions = [{type = "transmit", device = "7", value = {on = true}}]
+    "#.parse().unwrap();
+    let mut datalake = TDataLake::new();
+    let mut transmitted = datalake.subscribe::<TransmitRequest>(&"/bus/ug/tx".parse().unwrap()).await;
+    let _router = crate::bus_access::router::create(datalake.clone(), &config.gateways);
+    let handle = create(datalake.clone(), config.automations);
+    while datalake.get::<Value>(&"/system/automations/door bell/enabled".parse().unwrap()).await.is_none()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    datalake.publish(&"/bell".parse().unwrap(), Value::Bool(true)).await;
+    let request = transmitted.receive().await.unwrap();
+    assert_eq!(request.device_id, "7");
+    assert_eq!(request.payload.to_json(), r#"{"on":true}"#);
+
+    handle.stop().await;
+}
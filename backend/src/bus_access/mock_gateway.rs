@@ -0,0 +1,634 @@
+//! In-process stand-in for a bus gateway, for tests.
+//! Serves `MessagePackBusAccess`, `RawBusAccess`, `HomeControl` and `Debug` on a free port of
+//! localhost. Tests inject messages "received from the bus" and inspect what was sent to it.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+
+use super::bus;
+use crate::config::{BusAccessMode, BusConfig, PayloadEncoding};
+use crate::data_lake::*;
+
+type JsonSender = tokio::sync::mpsc::Sender<Result<bus::ReceiveJsonMessage, Status>>;
+
+#[derive(Default)]
+struct MockState
+{
+    connection_setups: Vec<bus::ConnectionSetup>,
+    json_receivers: Vec<JsonSender>,
+    sent_json: Vec<bus::SendJsonMessageRequest>,
+    raw_inbox: VecDeque<bus::ReceiveReply>,
+    sent_raw: Vec<bus::SendRequest>,
+    devices: Vec<bus::ScanDevicesReply>,
+    flash_requests: Vec<bus::FlashRequest>,
+    i2c_requests: Vec<bus::I2cViaPjonRequest>,
+    i2c_reply: Vec<u8>,
+    /// senders of flash streams kept open without further progress, see `stall_flashing`
+    stalled_flashes: Option<Vec<tokio::sync::mpsc::Sender<Result<bus::FlashState, Status>>>>,
+}
+
+#[derive(Clone, Default)]
+struct MockServices
+{
+    state: Arc<Mutex<MockState>>,
+}
+
+/// Polls `condition` on the mock state until it returns Some, panics after 5s.
+async fn wait_for<R>(state: &Mutex<MockState>, condition: impl Fn(&MockState) -> Option<R>) -> R
+{
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    loop
+    {
+        if let Some(result) = condition(&state.lock().unwrap())
+        {
+            return result;
+        }
+        if tokio::time::Instant::now() > deadline
+        {
+            panic!("Mock gateway: timeout waiting for condition");
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+pub struct MockGateway
+{
+    pub url: String,
+    services: MockServices,
+    _shutdown: tokio::sync::oneshot::Sender<()>,
+}
+
+impl MockGateway
+{
+    pub async fn start() -> Self
+    {
+        Self::start_on("127.0.0.1:0").await
+    }
+
+    /// Like `start`, but on the given address, e.g. to come back on the port of a stopped mock.
+    pub async fn start_on(address: &str) -> Self
+    {
+        let listener = tokio::net::TcpListener::bind(address).await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let services = MockServices::default();
+        let (shutdown_sender, shutdown_receiver) = tokio::sync::oneshot::channel::<()>();
+
+        let server = tonic::transport::Server::builder()
+            .add_service(bus::message_pack_bus_access_server::MessagePackBusAccessServer::new(services.clone()))
+            .add_service(bus::raw_bus_access_server::RawBusAccessServer::new(services.clone()))
+            .add_service(bus::home_control_server::HomeControlServer::new(services.clone()))
+            .add_service(bus::debug_server::DebugServer::new(services.clone()))
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async { shutdown_receiver.await.ok(); });
+        tokio::task::spawn(server);
+
+        MockGateway{url, services, _shutdown: shutdown_sender}
+    }
+
+    /// Configuration of a gateway talking to this mock.
+    pub fn bus_config(self: &Self, name: &str, base_path: &str, mode: BusAccessMode) -> BusConfig
+    {
+        BusConfig{
+            name: name.into(),
+            gateway_url: self.url.clone(),
+            mode,
+            base_path: base_path.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Waits until `count` clients have opened a `MessagePackBusAccess.receive` stream,
+    /// returns their connection setups.
+    pub async fn wait_for_receivers(self: &Self, count: usize) -> Vec<bus::ConnectionSetup>
+    {
+        wait_for(&self.services.state, |state| (state.json_receivers.len() >= count).then(|| state.connection_setups.clone())).await
+    }
+
+    /// Delivers a message to all open `MessagePackBusAccess.receive` streams.
+    pub async fn inject(self: &Self, remote_address: &str, data: &str)
+    {
+        let receivers = self.services.state.lock().unwrap().json_receivers.clone();
+        for receiver in receivers.iter()
+        {
+            let message = bus::ReceiveJsonMessage{remote_address: remote_address.into(), data: data.into()};
+            receiver.send(Ok(message)).await.unwrap();
+        }
+    }
+
+    /// Queues a message to be returned by `RawBusAccess.receive`.
+    pub fn inject_raw(self: &Self, remote_address: &str, data: &[u8])
+    {
+        let message = bus::ReceiveReply{remote_address: remote_address.into(), data: data.to_vec()};
+        self.services.state.lock().unwrap().raw_inbox.push_back(message);
+    }
+
+    /// Messages sent via `MessagePackBusAccess.send` so far.
+    pub fn sent(self: &Self) -> Vec<bus::SendJsonMessageRequest>
+    {
+        self.services.state.lock().unwrap().sent_json.clone()
+    }
+
+    /// Waits until `count` messages were sent via `MessagePackBusAccess.send`.
+    pub async fn wait_for_sent(self: &Self, count: usize) -> Vec<bus::SendJsonMessageRequest>
+    {
+        wait_for(&self.services.state, |state| (state.sent_json.len() >= count).then(|| state.sent_json.clone())).await
+    }
+
+    /// Waits until `count` messages were sent via `RawBusAccess.send`.
+    pub async fn wait_for_sent_raw(self: &Self, count: usize) -> Vec<bus::SendRequest>
+    {
+        wait_for(&self.services.state, |state| (state.sent_raw.len() >= count).then(|| state.sent_raw.clone())).await
+    }
+
+    /// Adds a device answering scans.
+    pub fn add_device(self: &Self, address: &str, name: &str)
+    {
+        let device = bus::ScanDevicesReply{device_address: address.into(), device_name: name.into()};
+        self.services.state.lock().unwrap().devices.push(device);
+    }
+
+    pub fn devices(self: &Self) -> Vec<bus::ScanDevicesReply>
+    {
+        self.services.state.lock().unwrap().devices.clone()
+    }
+
+    pub fn flash_requests(self: &Self) -> Vec<bus::FlashRequest>
+    {
+        self.services.state.lock().unwrap().flash_requests.clone()
+    }
+
+    pub fn i2c_requests(self: &Self) -> Vec<bus::I2cViaPjonRequest>
+    {
+        self.services.state.lock().unwrap().i2c_requests.clone()
+    }
+
+    /// Lets every following `HomeControl.flash` call report "erasing" and then nothing, forever.
+    pub fn stall_flashing(self: &Self)
+    {
+        self.services.state.lock().unwrap().stalled_flashes = Some(Vec::new());
+    }
+
+    /// Sets the bytes returned by every `HomeControl.i2cViaPjon` call.
+    pub fn set_i2c_reply(self: &Self, data: &[u8])
+    {
+        self.services.state.lock().unwrap().i2c_reply = data.to_vec();
+    }
+}
+
+#[tonic::async_trait]
+impl bus::message_pack_bus_access_server::MessagePackBusAccess for MockServices
+{
+    async fn send(&self, request: Request<bus::SendJsonMessageRequest>) -> Result<Response<bus::Empty>, Status>
+    {
+        self.state.lock().unwrap().sent_json.push(request.into_inner());
+        Ok(Response::new(bus::Empty{}))
+    }
+
+    type receiveStream = ReceiverStream<Result<bus::ReceiveJsonMessage, Status>>;
+
+    async fn receive(&self, request: Request<bus::ConnectionSetup>) -> Result<Response<Self::receiveStream>, Status>
+    {
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        let mut state = self.state.lock().unwrap();
+        state.connection_setups.push(request.into_inner());
+        state.json_receivers.push(tx);
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[tonic::async_trait]
+impl bus::raw_bus_access_server::RawBusAccess for MockServices
+{
+    async fn send(&self, request: Request<bus::SendRequest>) -> Result<Response<bus::Empty>, Status>
+    {
+        self.state.lock().unwrap().sent_raw.push(request.into_inner());
+        Ok(Response::new(bus::Empty{}))
+    }
+
+    async fn receive(&self, request: Request<bus::ReceiveRequest>) -> Result<Response<bus::ReceiveReply>, Status>
+    {
+        let timeout = Duration::from_millis(request.into_inner().timeout_milliseconds as u64);
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop
+        {
+            if let Some(message) = self.state.lock().unwrap().raw_inbox.pop_front()
+            {
+                return Ok(Response::new(message));
+            }
+            if tokio::time::Instant::now() > deadline
+            {
+                return Err(Status::deadline_exceeded("nothing received"));
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+impl Drop for MockGateway
+{
+    fn drop(self: &mut Self)
+    {
+        // ends open receive streams, the server only shuts down once they are closed
+        self.services.state.lock().unwrap().json_receivers.clear();
+    }
+}
+
+type StreamOf<T> = ReceiverStream<Result<T, Status>>;
+
+/// Stream yielding the given items.
+fn stream_of<T: std::fmt::Debug + Send + 'static>(items: Vec<T>) -> StreamOf<T>
+{
+    let (tx, rx) = tokio::sync::mpsc::channel(items.len().max(1));
+    for item in items
+    {
+        tx.try_send(Ok(item)).unwrap();
+    }
+    ReceiverStream::new(rx)
+}
+
+fn flash_states() -> StreamOf<bus::FlashState>
+{
+    stream_of(["erasing", "writing", "verifying", "done"].iter().map(|s| bus::FlashState{status_message: s.to_string()}).collect())
+}
+
+#[tonic::async_trait]
+impl bus::home_control_server::HomeControl for MockServices
+{
+    type scanDevicesStream = StreamOf<bus::ScanDevicesReply>;
+
+    async fn scan_devices(&self, _request: Request<bus::ScanDevicesRequest>) -> Result<Response<Self::scanDevicesStream>, Status>
+    {
+        Ok(Response::new(stream_of(self.state.lock().unwrap().devices.clone())))
+    }
+
+    async fn identify_device(&self, request: Request<bus::IdentifyDeviceRequest>) -> Result<Response<bus::ScanDevicesReply>, Status>
+    {
+        let address = request.into_inner().device_address;
+        let state = self.state.lock().unwrap();
+        match state.devices.iter().find(|device| device.device_address == address)
+        {
+            Some(device) => Ok(Response::new(device.clone())),
+            None => Err(Status::not_found(format!("no device at {}", address)))
+        }
+    }
+
+    type flashStream = StreamOf<bus::FlashState>;
+
+    async fn flash(&self, request: Request<bus::FlashRequest>) -> Result<Response<Self::flashStream>, Status>
+    {
+        let mut state = self.state.lock().unwrap();
+        state.flash_requests.push(request.into_inner());
+        if let Some(stalled) = &mut state.stalled_flashes
+        {
+            let (tx, rx) = tokio::sync::mpsc::channel(1);
+            tx.try_send(Ok(bus::FlashState{status_message: "erasing".into()})).unwrap();
+            stalled.push(tx);
+            return Ok(Response::new(ReceiverStream::new(rx)));
+        }
+        Ok(Response::new(flash_states()))
+    }
+
+    type flashViaI2cStream = StreamOf<bus::FlashState>;
+
+    async fn flash_via_i2c(&self, _request: Request<bus::FlashViaI2cRequest>) -> Result<Response<Self::flashViaI2cStream>, Status>
+    {
+        Ok(Response::new(flash_states()))
+    }
+
+    async fn reboot_device(&self, _request: Request<bus::RebootDeviceRequest>) -> Result<Response<bus::Empty>, Status>
+    {
+        Ok(Response::new(bus::Empty{}))
+    }
+
+    async fn set_device_address(&self, request: Request<bus::SetDeviceAddressRequest>) -> Result<Response<bus::Empty>, Status>
+    {
+        let request = request.into_inner();
+        let mut state = self.state.lock().unwrap();
+        match state.devices.iter_mut().find(|device| device.device_address == request.device_address)
+        {
+            Some(device) => { device.device_address = request.new_device_address; Ok(Response::new(bus::Empty{})) },
+            None => Err(Status::not_found(format!("no device at {}", request.device_address)))
+        }
+    }
+
+    async fn set_device_name(&self, request: Request<bus::SetDeviceNameRequest>) -> Result<Response<bus::Empty>, Status>
+    {
+        let request = request.into_inner();
+        let mut state = self.state.lock().unwrap();
+        match state.devices.iter_mut().find(|device| device.device_address == request.device_address)
+        {
+            Some(device) => { device.device_name = request.new_device_name; Ok(Response::new(bus::Empty{})) },
+            None => Err(Status::not_found(format!("no device at {}", request.device_address)))
+        }
+    }
+
+    async fn i2c_via_pjon(&self, request: Request<bus::I2cViaPjonRequest>) -> Result<Response<bus::I2cViaPjonReply>, Status>
+    {
+        let mut state = self.state.lock().unwrap();
+        state.i2c_requests.push(request.into_inner());
+        Ok(Response::new(bus::I2cViaPjonReply{data_read: state.i2c_reply.clone()}))
+    }
+}
+
+#[tonic::async_trait]
+impl bus::debug_server::Debug for MockServices
+{
+    async fn get_rscom_version(&self, _request: Request<bus::Empty>) -> Result<Response<bus::VersionInformation>, Status>
+    {
+        Ok(Response::new(bus::VersionInformation{
+            commit_date: "2023-01-01".into(),
+            commit_short: "abc1234".into(),
+            commit_sha1: "abc1234".repeat(5) + "abcde",
+            uncommitted_changes: false,
+        }))
+    }
+}
+
+
+#[tokio::test]
+async fn receive_from_bus_and_publish()
+{
+    let mut datalake = TDataLake::new();
+    let gateway = MockGateway::start().await;
+    let config = BusConfig{rx_mask: "0:127".into(), own_address: "0:3".into(), ..gateway.bus_config("test", "/bus/test", BusAccessMode::MessagePack)};
+
+    let mut rx = datalake.subscribe_with_path::<String>(&"/bus/test/rx/*".parse().unwrap()).await;
+    let handle = super::create(datalake.clone(), config);
+
+    let setups = gateway.wait_for_receivers(1).await;
+    assert_eq!(setups[0].rx_mask, "0:127");
+    assert_eq!(setups[0].remote_address, "0:3");
+
+    gateway.inject("12:1", r#"{"on": true}"#).await;
+    let (path, data) = rx.receive().await.unwrap();
+    assert_eq!(path.to_string(), "/bus/test/rx/12");
+    assert_eq!(data, r#"{"on": true}"#);
+    assert_eq!(datalake.get::<String>(&"/bus/test/rx/12".parse().unwrap()).await, Some(data));
+    let value = datalake.get::<Value>(&"/bus/test/rx/12".parse().unwrap()).await.unwrap();
+    assert_eq!(value.get("on"), Some(&Value::Bool(true)));
+
+    handle.stop().await;
+}
+
+#[tokio::test]
+async fn transmit_to_bus()
+{
+    let datalake = TDataLake::new();
+    let gateway = MockGateway::start().await;
+    let config = BusConfig{default_timeout_milliseconds: 1234, ..gateway.bus_config("test", "/bus/test", BusAccessMode::MessagePack)};
+    let handle = super::create(datalake.clone(), config);
+    gateway.wait_for_receivers(1).await;
+
+    datalake.publish(&"/bus/test/tx".parse().unwrap(), super::TransmitRequest{device_id: "7".into(), payload: Value::Map(Vec::new())}).await;
+
+    let sent = gateway.wait_for_sent(1).await;
+    assert_eq!(sent[0].remote_address, "7:1");
+    assert_eq!(sent[0].data, "{}");
+    assert_eq!(sent[0].timeout_milliseconds, 1234);
+
+    handle.stop().await;
+}
+
+#[tokio::test]
+async fn reconnect_to_gateway()
+{
+    let mut datalake = TDataLake::new();
+    let gateway = MockGateway::start().await;
+    let address = gateway.url.trim_start_matches("http://").to_string();
+    let config = gateway.bus_config("test", "/bus/test", BusAccessMode::MessagePack);
+    let mut rx = datalake.subscribe::<String>(&"/bus/test/rx/*".parse().unwrap()).await;
+    let handle = super::create(datalake.clone(), config);
+    gateway.wait_for_receivers(1).await;
+
+    // gateway restarts on the same port
+    drop(gateway);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let gateway = MockGateway::start_on(&address).await;
+    gateway.wait_for_receivers(1).await;
+
+    gateway.inject("5:1", "true").await;
+    assert_eq!(rx.receive().await.unwrap(), "true");
+
+    // stopping does not wait for the next attempt
+    drop(gateway);
+    tokio::time::timeout(Duration::from_secs(1), handle.stop()).await.unwrap();
+}
+
+#[tokio::test]
+async fn raw_receive_and_transmit()
+{
+    let mut datalake = TDataLake::new();
+    let gateway = MockGateway::start().await;
+    let config = BusConfig{default_timeout_milliseconds: 100, ..gateway.bus_config("legacy", "/bus/legacy", BusAccessMode::Raw)};
+
+    let mut rx = datalake.subscribe::<Vec<u8>>(&"/bus/legacy/rx/3".parse().unwrap()).await;
+    let handle = super::create(datalake.clone(), config);
+
+    gateway.inject_raw("3:1", &[0xde, 0xad]);
+    assert_eq!(rx.receive().await.unwrap(), vec![0xde, 0xad]);
+
+    datalake.publish(&"/bus/legacy/tx".parse().unwrap(), super::raw::RawTransmitRequest{device_id: "3".into(), payload: vec![1, 2]}).await;
+    let sent = gateway.wait_for_sent_raw(1).await;
+    assert_eq!(sent[0].remote_address, "3:1");
+    assert_eq!(sent[0].data, vec![1, 2]);
+
+    handle.stop().await;
+}
+
+#[tokio::test]
+async fn raw_gateway_stops_while_receiving_fails()
+{
+    let datalake = TDataLake::new();
+    let gateway = MockGateway::start().await;
+    let config = gateway.bus_config("legacy", "/bus/legacy", BusAccessMode::Raw);
+    drop(gateway);
+    let handle = super::create(datalake.clone(), config);
+
+    // let receiving fail, so the next attempt is delayed
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // stopping does not wait for the next attempt
+    tokio::time::timeout(Duration::from_millis(500), handle.stop()).await.unwrap();
+}
+
+#[tokio::test]
+async fn message_pack_payloads_via_raw_gateway()
+{
+    let mut datalake = TDataLake::new();
+    let gateway = MockGateway::start().await;
+    let config = BusConfig{
+        default_timeout_milliseconds: 100,
+        encodings: [("3".to_string(), PayloadEncoding::MessagePack)].into(),
+        ..gateway.bus_config("legacy", "/bus/legacy", BusAccessMode::Raw)
+    };
+
+    let mut rx = datalake.subscribe::<Value>(&"/bus/legacy/rx/*".parse().unwrap()).await;
+    let handle = super::create(datalake.clone(), config);
+
+    // {"level": 7}
+    gateway.inject_raw("3:1", &[0x81, 0xa5, b'l', b'e', b'v', b'e', b'l', 0x07]);
+    assert_eq!(rx.receive().await.unwrap().get("level"), Some(&Value::Integer(7)));
+
+    // devices without encoding are only published as bytes
+    gateway.inject_raw("4:1", &[0x07]);
+    gateway.inject_raw("3:1", &[0xc3]);
+    assert_eq!(rx.receive().await.unwrap(), Value::Bool(true));
+
+    let payload = Value::Map(vec![(Value::String("on".into()), Value::Bool(false))]);
+    datalake.publish(&"/bus/legacy/tx".parse().unwrap(), super::TransmitRequest{device_id: "4".into(), payload: payload.clone()}).await;
+    datalake.publish(&"/bus/legacy/tx".parse().unwrap(), super::TransmitRequest{device_id: "3".into(), payload: payload.clone()}).await;
+    let sent = gateway.wait_for_sent_raw(1).await;
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].remote_address, "3:1");
+    assert_eq!(Value::from_message_pack(&sent[0].data), Ok(payload));
+
+    handle.stop().await;
+}
+
+#[tokio::test]
+async fn transmit_routed_across_gateways()
+{
+    let datalake = TDataLake::new();
+    let ug = MockGateway::start().await;
+    let eg = MockGateway::start().await;
+    let configs = vec![
+        BusConfig{devices: vec!["1".into()], ..ug.bus_config("ug", "/bus/ug", BusAccessMode::MessagePack)},
+        BusConfig{devices: vec!["2".into()], ..eg.bus_config("eg", "/bus/eg", BusAccessMode::MessagePack)},
+    ];
+    let gateways: Vec<_> = configs.iter().map(|config| super::create(datalake.clone(), config.clone())).collect();
+    let router = super::router::create(datalake.clone(), &configs);
+    ug.wait_for_receivers(1).await;
+    eg.wait_for_receivers(1).await;
+
+    // the router subscribes within its task, repeat a probe until it is forwarded
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    while eg.sent().is_empty()
+    {
+        assert!(tokio::time::Instant::now() < deadline, "router did not forward the probe");
+        datalake.publish(&super::ROUTED_TX_PATH.parse().unwrap(), super::TransmitRequest{device_id: "2".into(), payload: Value::String("probe".into())}).await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    datalake.publish(&super::ROUTED_TX_PATH.parse().unwrap(), super::TransmitRequest{device_id: "2".into(), payload: Value::String("eg".into())}).await;
+    datalake.publish(&super::ROUTED_TX_PATH.parse().unwrap(), super::TransmitRequest{device_id: "1".into(), payload: Value::String("ug".into())}).await;
+
+    wait_for(&eg.services.state, |state| state.sent_json.iter().any(|sent| sent.data == "\"eg\"").then_some(())).await;
+    let sent = ug.wait_for_sent(1).await;
+    assert_eq!(sent[0].data, "\"ug\"");
+    // nothing else was sent, apart from the probes
+    assert_eq!(sent.len(), 1);
+    assert_eq!(eg.sent().iter().filter(|sent| sent.data != "\"probe\"").count(), 1);
+
+    router.stop().await;
+    for gateway in gateways
+    {
+        gateway.stop().await;
+    }
+}
+
+#[tokio::test]
+async fn device_registry_scan_and_rename()
+{
+    use crate::device_registry::DeviceInfo;
+
+    let mut datalake = TDataLake::new();
+    let gateway = MockGateway::start().await;
+    gateway.add_device("5:1", "lamp");
+
+    let mut devices = datalake.subscribe::<DeviceInfo>(&"/devices/*".parse().unwrap()).await;
+    let _registry = crate::device_registry::create(datalake.clone(), Default::default(), &[gateway.bus_config("ug", "/bus/ug", BusAccessMode::MessagePack)]);
+
+    let info = devices.receive().await.unwrap();
+    assert_eq!(info.address, "5:1");
+    assert_eq!(info.name, "lamp");
+    assert_eq!(info.gateway, "ug");
+
+    let reply = datalake.request::<String, Result<DeviceInfo, String>>(&"/devices/5:1/rename".parse().unwrap(), "kitchen".into()).await;
+    assert_eq!(reply.unwrap().unwrap().name, "kitchen");
+    assert_eq!(gateway.devices()[0].device_name, "kitchen");
+    assert_eq!(devices.receive().await.unwrap().name, "kitchen");
+}
+
+#[tokio::test]
+async fn flash_and_i2c_via_home_control()
+{
+    use crate::flashing::*;
+    use crate::i2c_bridge::I2cTransaction;
+
+    let mut datalake = TDataLake::new();
+    let gateway = MockGateway::start().await;
+    gateway.set_i2c_reply(&[0x12, 0x34]);
+    let configs = [gateway.bus_config("ug", "/bus/ug", BusAccessMode::MessagePack)];
+    let _flasher = create(datalake.clone(), &configs);
+    let _i2c_bridge = crate::i2c_bridge::create(datalake.clone(), Vec::new(), &configs);
+    let mut progress = datalake.subscribe::<String>(&"/flash/5:1/progress".parse().unwrap()).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let campaign = FlashCampaign{
+        firmware: Firmware{device_signature: vec![0x1e, 0x95, 0x0f], application: vec![0xaa; 32]},
+        targets: vec![FlashTarget{address: "5:1".into(), gateway: None, expected_signature: None, i2c: None}],
+        skip_reboot: true,
+        stop_on_error: false,
+        progress_timeout_milliseconds: 1000,
+    };
+    let results = datalake.request::<FlashCampaign, Vec<FlashResult>>(&"/flash/campaign".parse().unwrap(), campaign).await.unwrap();
+    assert_eq!(results[0].result, Ok(()));
+    assert_eq!(progress.receive().await.unwrap(), "erasing");
+    let flashed = gateway.flash_requests();
+    assert_eq!(flashed[0].device_signature, vec![0x1e, 0x95, 0x0f]);
+    assert!(flashed[0].skip_reboot);
+
+    gateway.stall_flashing();
+    let campaign = FlashCampaign{
+        firmware: Firmware{device_signature: vec![0x1e, 0x95, 0x0f], application: vec![0xaa; 32]},
+        targets: vec![FlashTarget{address: "5:1".into(), gateway: None, expected_signature: None, i2c: None}],
+        skip_reboot: true,
+        stop_on_error: false,
+        progress_timeout_milliseconds: 100,
+    };
+    let results = datalake.request::<FlashCampaign, Vec<FlashResult>>(&"/flash/campaign".parse().unwrap(), campaign).await.unwrap();
+    assert!(results[0].result.as_ref().unwrap_err().contains("no progress"), "{:?}", results);
+
+    let transaction = I2cTransaction{write: vec![0x00], read: 2};
+    let reply = datalake.request::<I2cTransaction, Result<Vec<u8>, String>>(&"/i2c/5/1/0x48".parse().unwrap(), transaction).await;
+    assert_eq!(reply.unwrap().unwrap(), vec![0x12, 0x34]);
+    let request = &gateway.i2c_requests()[0];
+    assert_eq!(request.remote_address, "5:1");
+    assert_eq!(request.i2c_bus_number, 1);
+    assert_eq!(request.i2c_7bit_device_address, 0x48);
+}
+
+#[tokio::test]
+async fn i2c_polls_publish_values()
+{
+    use crate::config::{I2cDecoding, I2cPollConfig};
+
+    let datalake = TDataLake::new();
+    let gateway = MockGateway::start().await;
+    gateway.set_i2c_reply(&[0x12, 0x34]);
+    let configs = [gateway.bus_config("ug", "/bus/ug", BusAccessMode::MessagePack)];
+    let poll = I2cPollConfig{
+        device: "5".into(), bus: 0, address: 0x48, write: vec![0x00], read: 2,
+        decode: I2cDecoding::U16Be, scale: 0.5, interval_milliseconds: 10000, path: "/house/temperature".into(),
+    };
+    let raw_poll = I2cPollConfig{decode: I2cDecoding::Raw, path: "/house/raw".into(), ..poll.clone()};
+    let _i2c_bridge = crate::i2c_bridge::create(datalake.clone(), vec![poll, raw_poll], &configs);
+
+    let mut value = None;
+    let mut raw = None;
+    for _ in 0..100
+    {
+        value = datalake.get::<Value>(&"/house/temperature".parse().unwrap()).await;
+        raw = datalake.get::<Value>(&"/house/raw".parse().unwrap()).await;
+        if value.is_some() && raw.is_some()
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(value, Some(Value::Float(0x1234 as f64 * 0.5)));
+    assert_eq!(raw, Some(Value::Binary(vec![0x12, 0x34])));
+}
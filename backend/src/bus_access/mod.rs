@@ -0,0 +1,216 @@
+use regex::Regex;
+use std::collections::HashMap;
+use tonic::transport::Channel;
+#[allow(non_camel_case_types)]
+pub mod bus {
+    tonic::include_proto!("_");
+}
+use bus::home_control_client::HomeControlClient;
+use crate::config::{BusAccessMode, BusConfig};
+use crate::data_lake::*;
+use crate::shutdown::TaskHandle;
+use tokio_stream::StreamExt;
+
+pub mod raw;
+pub mod router;
+#[cfg(test)]
+pub mod mock_gateway;
+
+/// Lake path accepting `TransmitRequest`s for any configured device.
+/// Requests are forwarded to the gateway owning the device, see `router`.
+pub const ROUTED_TX_PATH: &str = "/bus/tx";
+
+/// Request to transmit a structured payload to a device.
+/// Sent as JSON on gateways in message_pack mode (the gateway encodes it to MessagePack),
+/// encoded according to the device's `PayloadEncoding` on gateways in raw mode.
+#[derive(Clone, Debug)]
+pub struct TransmitRequest
+{
+    pub device_id : String,
+    pub payload : Value
+}
+
+/// Extracts the device id from a bus address of the form "<device_id>:<port>".
+fn device_id_of(remote_address: &str) -> Option<&str>
+{
+    static DEVICE_ID_REGEX: std::sync::OnceLock<Regex> = std::sync::OnceLock::new();
+    let regex = DEVICE_ID_REGEX.get_or_init(|| Regex::new(r"([0-9]+):").unwrap());
+    regex.captures(remote_address)
+        .and_then(|captures| captures.get(1))
+        .map(|device_id| device_id.as_str())
+}
+
+/// Bus address of the given device as expected by the gateway.
+pub fn remote_address_of(device_id: &str) -> String
+{
+    device_id.to_string() + ":1"
+}
+
+/// Gateway owning the given device. If there is only one gateway, it owns all devices.
+pub fn gateway_of<'a>(gateways: &'a [BusConfig], device_id: &str) -> Option<&'a BusConfig>
+{
+    if gateways.len() == 1
+    {
+        return gateways.first();
+    }
+    gateways.iter().find(|gateway| gateway.devices.iter().any(|device| device == device_id))
+}
+
+/// Creates a channel per gateway, keyed by gateway name.
+/// Channels connect lazily, an unreachable gateway does not affect the others.
+pub fn gateway_channels(gateways: &[BusConfig]) -> HashMap<String, Channel>
+{
+    let mut channels = HashMap::new();
+    for gateway in gateways.iter()
+    {
+        match tonic::transport::Endpoint::from_shared(gateway.gateway_url.clone())
+        {
+            Ok(endpoint) => { channels.insert(gateway.name.clone(), endpoint.connect_lazy()); },
+            Err(e) => println!("Invalid url of gateway '{}': {}", gateway.name, e)
+        }
+    }
+    channels
+}
+
+/// Creates a `HomeControl` client per gateway, keyed by gateway name.
+pub fn home_control_clients(gateways: &[BusConfig]) -> HashMap<String, HomeControlClient<Channel>>
+{
+    gateway_channels(gateways).into_iter().map(|(name, channel)| (name, HomeControlClient::new(channel))).collect()
+}
+
+/// The gateway only sees the lake below its `base_path`, the configuration was validated.
+pub fn create(datalake: TDataLake, config: BusConfig) -> TaskHandle
+{
+    let datalake = datalake.scoped(&config.base_path.parse().unwrap(), true).unwrap();
+    match config.mode
+    {
+        BusAccessMode::MessagePack => TaskHandle::spawn(|rx| receive_from_bus_and_publish(datalake, config, rx)),
+        BusAccessMode::Raw => TaskHandle::spawn(|rx| raw::receive_from_bus_and_publish(datalake, config, rx)),
+    }
+}
+
+/// Delay before connecting to a gateway again after losing the connection, doubled after
+/// every failed attempt up to `MAX_RECONNECT_DELAY`.
+const RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(30);
+
+type MessagePackClient = bus::message_pack_bus_access_client::MessagePackBusAccessClient<Channel>;
+
+/// Connects to the gateway and opens the stream of received messages.
+async fn connect(config: &BusConfig) -> Result<(MessagePackClient, tonic::Streaming<bus::ReceiveJsonMessage>), String>
+{
+    let mut client = MessagePackClient::connect(config.gateway_url.clone()).await.map_err(|e| e.to_string())?;
+    let req = bus::ConnectionSetup {
+        rx_mask: config.rx_mask.clone(),
+        remote_address: config.own_address.clone(),
+    };
+    let stream = client.receive(req).await.map_err(|status| status.to_string())?.into_inner();
+    Ok((client, stream))
+}
+
+/// `datalake` is scoped to the base_path of the gateway
+/// will publish received bus messages to <base_path>/rx/<device_id>, as JSON `String` and decoded as `Value`
+/// will send all messages to the bus which are published to <base_path>/tx
+/// will connect again after losing the connection to the gateway
+async fn receive_from_bus_and_publish(mut datalake: TDataLake, config: BusConfig, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    // receive from lake (data to send to bus)
+    let mut fisher = datalake.subscribe::<TransmitRequest>(&"/tx".parse().unwrap()).await;
+
+    let mut reconnect_delay = RECONNECT_DELAY;
+    loop
+    {
+        match connect(&config).await
+        {
+            Ok((mut client, mut resp_stream)) =>
+            {
+                reconnect_delay = RECONNECT_DELAY;
+                loop
+                {
+                    tokio::select!
+                    {
+                        // receive from bus (data to send to data lake):
+                        grpc_event = resp_stream.next() =>
+                        {
+                            let received = match grpc_event
+                            {
+                                Some(Ok(received)) => received,
+                                Some(Err(status)) =>
+                                {
+                                    println!("Receiving from gateway '{}' failed: {}", config.name, status);
+                                    break;
+                                }
+                                None =>
+                                {
+                                    println!("Gateway '{}' closed the connection", config.name);
+                                    break;
+                                }
+                            };
+                            println!("\treceived message: `{:?}`", received);
+
+                            if let Some(device_id) = device_id_of(received.remote_address.as_str())
+                            {
+                                let path = format!("/rx/{}", device_id).parse().unwrap();
+                                match Value::from_json(&received.data)
+                                {
+                                    Ok(value) => datalake.publish_retained(&path, value).await,
+                                    Err(e) => println!("Message of device '{}' via gateway '{}': {}", device_id, config.name, e)
+                                }
+                                datalake.publish_retained(&path, received.data).await;
+                            }
+                        }
+                        Some(transmit_request) = fisher.receive() =>
+                        {
+                            let req = bus::SendJsonMessageRequest {
+                                remote_address: remote_address_of(&transmit_request.device_id),
+                                data: transmit_request.payload.to_json(),
+                                timeout_milliseconds: config.default_timeout_milliseconds
+                            };
+                            // TODO: this will block the select?
+                            if let Err(status) = client.send(req).await
+                            {
+                                println!("Sending to device '{}' via gateway '{}' failed: {}", transmit_request.device_id, config.name, status);
+                            }
+                        }
+                        _ = &mut stop_receiver =>
+                        {
+                            // Quit (explicit stop or handle dropped)
+                            return;
+                        }
+                    }
+                }
+            }
+            Err(e) => println!("Connecting to gateway '{}' failed: {}", config.name, e),
+        }
+
+        // do not hammer a broken gateway, drop transmit requests meanwhile instead of blocking their publishers:
+        let reconnect = tokio::time::sleep(reconnect_delay);
+        tokio::pin!(reconnect);
+        loop
+        {
+            tokio::select!
+            {
+                _ = &mut reconnect => break,
+                Some(transmit_request) = fisher.receive() =>
+                {
+                    println!("Gateway '{}' is not connected, dropping transmit request to device '{}'", config.name, transmit_request.device_id);
+                }
+                _ = &mut stop_receiver =>
+                {
+                    // Quit (explicit stop or handle dropped)
+                    return;
+                }
+            }
+        }
+        reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+}
+
+#[test]
+fn test_device_addresses()
+{
+    assert_eq!(device_id_of("5:1"), Some("5"));
+    assert_eq!(device_id_of("12:1"), Some("12"));
+    assert_eq!(device_id_of("12"), None);
+    assert_eq!(remote_address_of("12"), "12:1");
+}
@@ -0,0 +1,172 @@
+use crate::config::{BusConfig, PayloadEncoding};
+use crate::data_lake::*;
+use super::bus;
+use super::bus::raw_bus_access_client::RawBusAccessClient;
+use super::TransmitRequest;
+
+/// Request to transmit plain bytes to a device on a gateway in raw mode.
+/// Published to <base_path>/tx of the gateway (or `ROUTED_TX_PATH`).
+#[derive(Clone, Debug)]
+pub struct RawTransmitRequest
+{
+    pub device_id : String,
+    pub payload : Vec<u8>
+}
+
+/// Decodes a received payload, None for devices with raw encoding.
+fn decode_payload(encoding: PayloadEncoding, data: &[u8]) -> Option<Result<Value, String>>
+{
+    match encoding
+    {
+        PayloadEncoding::Raw => None,
+        PayloadEncoding::Json => Some(std::str::from_utf8(data)
+            .map_err(|e| format!("Invalid JSON payload: {}", e))
+            .and_then(Value::from_json)),
+        PayloadEncoding::MessagePack => Some(Value::from_message_pack(data)),
+    }
+}
+
+fn encode_payload(encoding: PayloadEncoding, value: &Value) -> Result<Vec<u8>, String>
+{
+    match encoding
+    {
+        PayloadEncoding::Raw => Err("Device has no payload encoding configured, use a RawTransmitRequest".into()),
+        PayloadEncoding::Json => Ok(value.to_json().into_bytes()),
+        PayloadEncoding::MessagePack => Ok(value.to_message_pack()),
+    }
+}
+
+/// Wait time before receiving again after receiving failed, so a broken gateway is not hammered
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Waits `delay`, then for the next message from the bus.
+/// `RawBusAccess.receive` is a unary call, so it has to be issued again after every reply.
+async fn receive_once(mut client: RawBusAccessClient<tonic::transport::Channel>, request: bus::ReceiveRequest, delay: std::time::Duration) -> Result<bus::ReceiveReply, tonic::Status>
+{
+    tokio::time::sleep(delay).await;
+    client.receive(request).await.map(|response| response.into_inner())
+}
+
+/// Raw counterpart of `bus_access::receive_from_bus_and_publish`, `datalake` is scoped to the base_path:
+/// will publish received bytes as `Vec<u8>` to <base_path>/rx/<device_id>, additionally
+/// decoded as `Value` if an encoding is configured for the device
+/// will send all `RawTransmitRequest`s to the bus which are published to <base_path>/tx
+/// will send all `TransmitRequest`s published to <base_path>/tx encoded as configured for the device
+pub async fn receive_from_bus_and_publish(mut datalake: TDataLake, config: BusConfig, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    // connects lazily, and again after errors
+    let mut client = match tonic::transport::Endpoint::from_shared(config.gateway_url.clone())
+    {
+        Ok(endpoint) => RawBusAccessClient::new(endpoint.connect_lazy()),
+        Err(e) =>
+        {
+            println!("Invalid url of gateway '{}': {}", config.name, e);
+            return;
+        }
+    };
+
+    let receive_request = bus::ReceiveRequest {
+        remote_mask: config.rx_mask.clone(),
+        remote_address: config.own_address.clone(),
+        timeout_milliseconds: config.default_timeout_milliseconds,
+    };
+    // receive from bus (data to send to data lake):
+    let pending_receive = receive_once(client.clone(), receive_request.clone(), std::time::Duration::ZERO);
+    tokio::pin!(pending_receive);
+
+    // receive from lake (data to send to bus)
+    let mut fisher = datalake.subscribe::<RawTransmitRequest>(&"/tx".parse().unwrap()).await;
+    let mut value_fisher = datalake.subscribe::<TransmitRequest>(&"/tx".parse().unwrap()).await;
+
+    loop
+    {
+        tokio::select!
+        {
+            reply = &mut pending_receive =>
+            {
+                let mut delay = std::time::Duration::ZERO;
+                match reply
+                {
+                    Ok(received) =>
+                    {
+                        println!("\treceived raw message: `{:?}`", received);
+                        if let Some(device_id) = super::device_id_of(received.remote_address.as_str())
+                        {
+                            let path = format!("/rx/{}", device_id).parse().unwrap();
+                            match decode_payload(config.encoding_of(device_id), &received.data)
+                            {
+                                Some(Ok(value)) => datalake.publish_retained(&path, value).await,
+                                Some(Err(e)) => println!("Message of device '{}' via gateway '{}': {}", device_id, config.name, e),
+                                None => ()
+                            }
+                            datalake.publish_retained(&path, received.data).await;
+                        }
+                    }
+                    Err(status) if status.code() == tonic::Code::DeadlineExceeded =>
+                    {
+                        // nothing received within timeout
+                    }
+                    Err(status) =>
+                    {
+                        println!("Receiving from gateway '{}' failed: {}", config.name, status);
+                        // waiting is part of the next receive, so stop and transmit requests are still served
+                        delay = RETRY_DELAY;
+                    }
+                }
+                pending_receive.set(receive_once(client.clone(), receive_request.clone(), delay));
+            }
+            Some(transmit_request) = fisher.receive() =>
+            {
+                let req = bus::SendRequest {
+                    remote_address: super::remote_address_of(&transmit_request.device_id),
+                    data: transmit_request.payload,
+                    timeout_milliseconds: config.default_timeout_milliseconds
+                };
+                if let Err(status) = client.send(req).await
+                {
+                    println!("Sending to device '{}' via gateway '{}' failed: {}", transmit_request.device_id, config.name, status);
+                }
+            }
+            Some(transmit_request) = value_fisher.receive() =>
+            {
+                let data = match encode_payload(config.encoding_of(&transmit_request.device_id), &transmit_request.payload)
+                {
+                    Ok(data) => data,
+                    Err(e) =>
+                    {
+                        println!("Sending to device '{}' via gateway '{}' failed: {}", transmit_request.device_id, config.name, e);
+                        continue;
+                    }
+                };
+                let req = bus::SendRequest {
+                    remote_address: super::remote_address_of(&transmit_request.device_id),
+                    data,
+                    timeout_milliseconds: config.default_timeout_milliseconds
+                };
+                if let Err(status) = client.send(req).await
+                {
+                    println!("Sending to device '{}' via gateway '{}' failed: {}", transmit_request.device_id, config.name, status);
+                }
+            }
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped)
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_payload_encodings()
+{
+    let value = Value::Map(vec![(Value::String("on".into()), Value::Bool(true))]);
+    assert!(decode_payload(PayloadEncoding::Raw, b"{}").is_none());
+    assert_eq!(decode_payload(PayloadEncoding::Json, b"{\"on\": true}"), Some(Ok(value.clone())));
+    assert!(decode_payload(PayloadEncoding::Json, &[0xff]).unwrap().is_err());
+    assert_eq!(decode_payload(PayloadEncoding::MessagePack, &[0x81, 0xa2, b'o', b'n', 0xc3]), Some(Ok(value.clone())));
+
+    assert_eq!(encode_payload(PayloadEncoding::MessagePack, &value), Ok(vec![0x81, 0xa2, b'o', b'n', 0xc3]));
+    assert_eq!(encode_payload(PayloadEncoding::Json, &value), Ok(b"{\"on\":true}".to_vec()));
+    assert!(encode_payload(PayloadEncoding::Raw, &value).is_err());
+}
@@ -0,0 +1,98 @@
+use crate::config::BusConfig;
+use crate::data_lake::*;
+use crate::shutdown::TaskHandle;
+use super::TransmitRequest;
+use super::raw::RawTransmitRequest;
+
+/// Tx path of the gateway owning the device, see `gateway_of`.
+fn tx_path_of(gateways: &[BusConfig], device_id: &str) -> Option<path_tree::Path>
+{
+    // base paths were validated with the configuration
+    super::gateway_of(gateways, device_id).map(|gateway| (gateway.base_path.clone() + "/tx").parse().unwrap())
+}
+
+pub fn create(datalake: TDataLake, gateways: &[BusConfig]) -> TaskHandle
+{
+    let gateways = gateways.to_vec();
+    TaskHandle::spawn(|rx| route_transmit_requests(datalake, gateways, rx))
+}
+
+/// will forward all transmit requests (JSON and raw) published to `ROUTED_TX_PATH`
+/// to <base_path>/tx of the gateway owning the addressed device, the only gateway owns all devices.
+async fn route_transmit_requests(mut datalake: TDataLake, gateways: Vec<BusConfig>, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    let mut fisher = datalake.subscribe::<TransmitRequest>(&super::ROUTED_TX_PATH.parse().unwrap()).await;
+    let mut raw_fisher = datalake.subscribe::<RawTransmitRequest>(&super::ROUTED_TX_PATH.parse().unwrap()).await;
+    loop
+    {
+        tokio::select!
+        {
+            Some(transmit_request) = fisher.receive() =>
+            {
+                match tx_path_of(&gateways, &transmit_request.device_id)
+                {
+                    Some(tx_path) => datalake.publish(&tx_path, transmit_request).await,
+                    None => println!("No gateway owns device '{}', dropping transmit request", transmit_request.device_id)
+                }
+            }
+            Some(transmit_request) = raw_fisher.receive() =>
+            {
+                match tx_path_of(&gateways, &transmit_request.device_id)
+                {
+                    Some(tx_path) => datalake.publish(&tx_path, transmit_request).await,
+                    None => println!("No gateway owns device '{}', dropping raw transmit request", transmit_request.device_id)
+                }
+            }
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped)
+                break;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn routes_to_owning_gateway()
+{
+    let mut datalake = TDataLake::new();
+    let gateways = vec![
+        BusConfig{name: "ug".into(), base_path: "/bus/ug".into(), devices: vec!["1".into()], ..Default::default()},
+        BusConfig{name: "eg".into(), base_path: "/bus/eg".into(), devices: vec!["2".into(), "3".into()], ..Default::default()},
+    ];
+    let mut ug = datalake.subscribe::<TransmitRequest>(&"/bus/ug/tx".parse().unwrap()).await;
+    let mut eg = datalake.subscribe::<TransmitRequest>(&"/bus/eg/tx".parse().unwrap()).await;
+    let _router = create(datalake.clone(), &gateways);
+
+    // give the router task a chance to subscribe
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    for device_id in ["3", "unknown", "1"]
+    {
+        datalake.publish(&super::ROUTED_TX_PATH.parse().unwrap(), TransmitRequest{device_id: device_id.into(), payload: Value::Nil}).await;
+    }
+
+    let received = eg.receive().await.unwrap();
+    assert_eq!(received.device_id, "3");
+    let received = ug.receive().await.unwrap();
+    assert_eq!(received.device_id, "1");
+    assert!(eg.receiver.try_recv().is_err());
+    assert!(ug.receiver.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn routes_raw_requests()
+{
+    let mut datalake = TDataLake::new();
+    let gateways = vec![
+        BusConfig{name: "kg".into(), base_path: "/bus/kg".into(), devices: vec!["9".into()], ..Default::default()},
+    ];
+    let mut kg = datalake.subscribe::<RawTransmitRequest>(&"/bus/kg/tx".parse().unwrap()).await;
+    let _router = create(datalake.clone(), &gateways);
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    datalake.publish(&super::ROUTED_TX_PATH.parse().unwrap(), RawTransmitRequest{device_id: "9".into(), payload: vec![1, 2, 3]}).await;
+
+    let received = kg.receive().await.unwrap();
+    assert_eq!(received.payload, vec![1, 2, 3]);
+}
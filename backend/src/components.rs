@@ -0,0 +1,388 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::config::{BusConfig, Config};
+use crate::data_lake::*;
+use crate::shutdown::{ShutdownCoordinator, TaskHandle};
+
+/// All long running parts of the backend, created from one configuration.
+/// On reload only the parts affected by a configuration change are restarted,
+/// all others keep their connections and subscriptions.
+pub struct Components
+{
+    datalake: TDataLake,
+    config: Config,
+    persistence: Option<TaskHandle>,
+    printer: Option<TaskHandle>,
+    /// keyed by gateway name
+    gateways: BTreeMap<String, TaskHandle>,
+    router: Option<TaskHandle>,
+    device_registry: Option<TaskHandle>,
+    flasher: Option<TaskHandle>,
+    health_monitor: Option<TaskHandle>,
+    i2c_bridge: Option<TaskHandle>,
+    device_model: Option<TaskHandle>,
+    automations: Option<TaskHandle>,
+    scheduler: Option<TaskHandle>,
+    scenes: Option<TaskHandle>,
+    scripting: Option<TaskHandle>,
+    grpc_api: Option<TaskHandle>,
+    websocket_api: Option<TaskHandle>,
+    http_api: Option<TaskHandle>,
+    mqtt_api: Option<TaskHandle>,
+    mqtt_bridge: Option<TaskHandle>,
+}
+
+async fn stop(handle: &mut Option<TaskHandle>)
+{
+    if let Some(handle) = handle.take()
+    {
+        handle.stop().await;
+    }
+}
+
+/// will print all `String`s published to one of the paths
+fn create_printer(mut datalake: TDataLake, paths: Vec<String>) -> TaskHandle
+{
+    TaskHandle::spawn(|mut stop_receiver| async move {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut forwarders = Vec::new();
+        for path in paths.iter()
+        {
+            // paths were validated with the configuration
+            let mut fisher = datalake.subscribe_with_path::<String>(&path.parse().unwrap()).await;
+            let sender = sender.clone();
+            forwarders.push(tokio::task::spawn(async move {
+                while let Some(message) = fisher.receive().await
+                {
+                    if sender.send(message).is_err()
+                    {
+                        break;
+                    }
+                }
+            }));
+        }
+        loop
+        {
+            tokio::select!
+            {
+                Some((path, data)) = receiver.recv() =>
+                {
+                    println!("{}: {}", path, data);
+                }
+                _ = &mut stop_receiver =>
+                {
+                    // Quit (explicit stop or handle dropped)
+                    break;
+                }
+            }
+        }
+        for forwarder in forwarders
+        {
+            forwarder.abort();
+        }
+    })
+}
+
+impl Components
+{
+    pub fn start(datalake: TDataLake, config: Config) -> Self
+    {
+        let mut components = Components{
+            datalake,
+            config,
+            persistence: None,
+            printer: None,
+            gateways: BTreeMap::new(),
+            router: None,
+            device_registry: None,
+            flasher: None,
+            health_monitor: None,
+            i2c_bridge: None,
+            device_model: None,
+            automations: None,
+            scheduler: None,
+            scenes: None,
+            scripting: None,
+            grpc_api: None,
+            websocket_api: None,
+            http_api: None,
+            mqtt_api: None,
+            mqtt_bridge: None,
+        };
+        components.add_aliases();
+        components.persistence = Some(crate::persistence::create(components.datalake.clone(), components.config.lake.clone()));
+        components.printer = Some(create_printer(components.datalake.clone(), components.config.lake.print_paths.clone()));
+        for gateway in components.config.gateways.iter()
+        {
+            components.gateways.insert(gateway.name.clone(), crate::bus_access::create(components.datalake.clone(), gateway.clone()));
+        }
+        components.start_gateway_users();
+        components.automations = Some(crate::automations::create(components.datalake.clone(), components.config.automations.clone()));
+        components.scheduler = Some(crate::scheduler::create(components.datalake.clone(), components.config.scheduler.clone()));
+        components.scenes = Some(crate::scenes::create(components.datalake.clone(), components.config.scenes.clone()));
+        components.scripting = components.config.scripting.clone().map(|scripting| crate::scripting::create(components.datalake.clone(), scripting));
+        components.start_api();
+        components.mqtt_bridge = components.config.mqtt_bridge.clone().map(|bridge| crate::mqtt_bridge::create(components.datalake.clone(), bridge));
+        components
+    }
+
+    /// The configuration was validated, adding the aliases only fails if they overlap aliases added by others.
+    fn add_aliases(self: &Self)
+    {
+        for alias in self.config.aliases.iter()
+        {
+            if let Err(e) = self.datalake.add_alias(&alias.path.parse().unwrap(), &alias.target.parse().unwrap())
+            {
+                println!("Cannot add alias: {}", e);
+            }
+        }
+    }
+
+    pub fn config(self: &Self) -> &Config
+    {
+        &self.config
+    }
+
+    /// Starts all components using the gateways.
+    fn start_gateway_users(self: &mut Self)
+    {
+        let datalake = &self.datalake;
+        let config = &self.config;
+        self.router = Some(crate::bus_access::router::create(datalake.clone(), &config.gateways));
+        self.device_registry = Some(crate::device_registry::create(datalake.clone(), config.device_registry.clone(), &config.gateways));
+        self.flasher = Some(crate::flashing::create(datalake.clone(), &config.gateways));
+        self.health_monitor = Some(crate::health_monitor::create(datalake.clone(), config.health_monitor.clone(), &config.gateways));
+        self.i2c_bridge = Some(crate::i2c_bridge::create(datalake.clone(), config.i2c_polls.clone(), &config.gateways));
+        self.device_model = Some(crate::device_model::create(datalake.clone(), &config.devices, &config.gateways));
+    }
+
+    /// Starts the configured API front-ends.
+    fn start_api(self: &mut Self)
+    {
+        // addresses and acl were validated with the configuration
+        let api = &self.config.api;
+        let acl = api.acl.as_ref().map(|acl| crate::api::acl::Acl::new(acl).unwrap()).unwrap_or_default();
+        self.grpc_api = api.grpc_listen.as_ref().map(|listen| crate::api::grpc::create(self.datalake.clone(), acl.clone(), listen.parse().unwrap()));
+        self.websocket_api = api.websocket_listen.as_ref().map(|listen| crate::api::websocket::create(self.datalake.clone(), acl.clone(), listen.parse().unwrap()));
+        self.http_api = api.http_listen.as_ref().map(|listen| crate::api::http::create(self.datalake.clone(), acl.clone(), listen.parse().unwrap()));
+        self.mqtt_api = api.mqtt_listen.as_ref().map(|listen| crate::api::mqtt::create(self.datalake.clone(), acl, listen.parse().unwrap()));
+    }
+
+    async fn stop_api(self: &mut Self)
+    {
+        stop(&mut self.mqtt_api).await;
+        stop(&mut self.http_api).await;
+        stop(&mut self.websocket_api).await;
+        stop(&mut self.grpc_api).await;
+    }
+
+    /// Switches to the new configuration, returns a description of every change made.
+    pub async fn reconfigure(self: &mut Self, new_config: Config) -> Vec<String>
+    {
+        let mut changes = Vec::new();
+        let old_config = std::mem::replace(&mut self.config, new_config);
+        let datalake = self.datalake.clone();
+
+        if old_config.aliases != self.config.aliases
+        {
+            for alias in old_config.aliases.iter()
+            {
+                datalake.remove_alias(&alias.path.parse().unwrap());
+            }
+            self.add_aliases();
+            changes.push("aliases changed".to_string());
+        }
+        if old_config.lake != self.config.lake
+        {
+            stop(&mut self.persistence).await;
+            self.persistence = Some(crate::persistence::create(datalake.clone(), self.config.lake.clone()));
+            stop(&mut self.printer).await;
+            self.printer = Some(create_printer(datalake.clone(), self.config.lake.print_paths.clone()));
+            changes.push("lake persistence and printing restarted".to_string());
+        }
+
+        // gateways are compared by name, every component holding gateway clients is restarted
+        // if any gateway changed
+        let gateways_changed = old_config.gateways != self.config.gateways;
+        if gateways_changed
+        {
+            // users first, they must not send to stopped gateways
+            stop(&mut self.device_model).await;
+            stop(&mut self.i2c_bridge).await;
+            stop(&mut self.health_monitor).await;
+            stop(&mut self.flasher).await;
+            stop(&mut self.device_registry).await;
+            stop(&mut self.router).await;
+
+            let old_gateways: BTreeMap<&str, &BusConfig> = old_config.gateways.iter().map(|gateway| (gateway.name.as_str(), gateway)).collect();
+            let new_gateways: BTreeMap<&str, &BusConfig> = self.config.gateways.iter().map(|gateway| (gateway.name.as_str(), gateway)).collect();
+            for (name, old_gateway) in old_gateways.iter()
+            {
+                let change = match new_gateways.get(name)
+                {
+                    None => "stopped",
+                    Some(new_gateway) if new_gateway != old_gateway => "restarted",
+                    Some(_) => continue,
+                };
+                if let Some(handle) = self.gateways.remove(*name)
+                {
+                    handle.stop().await;
+                }
+                changes.push(format!("gateway '{}' {}", name, change));
+            }
+            for (name, new_gateway) in new_gateways.iter()
+            {
+                if !self.gateways.contains_key(*name)
+                {
+                    self.gateways.insert(name.to_string(), crate::bus_access::create(datalake.clone(), (*new_gateway).clone()));
+                    if !old_gateways.contains_key(name)
+                    {
+                        changes.push(format!("gateway '{}' started", name));
+                    }
+                }
+            }
+            self.start_gateway_users();
+            changes.push("router, device registry, flasher, health monitor, i2c bridge and device drivers restarted".to_string());
+        }
+        else
+        {
+            if old_config.device_registry != self.config.device_registry
+            {
+                stop(&mut self.device_registry).await;
+                self.device_registry = Some(crate::device_registry::create(datalake.clone(), self.config.device_registry.clone(), &self.config.gateways));
+                changes.push("device registry restarted".to_string());
+            }
+            if old_config.health_monitor != self.config.health_monitor
+            {
+                stop(&mut self.health_monitor).await;
+                self.health_monitor = Some(crate::health_monitor::create(datalake.clone(), self.config.health_monitor.clone(), &self.config.gateways));
+                changes.push("health monitor restarted".to_string());
+            }
+            if old_config.i2c_polls != self.config.i2c_polls
+            {
+                stop(&mut self.i2c_bridge).await;
+                self.i2c_bridge = Some(crate::i2c_bridge::create(datalake.clone(), self.config.i2c_polls.clone(), &self.config.gateways));
+                changes.push("i2c bridge restarted".to_string());
+            }
+            if old_config.devices != self.config.devices
+            {
+                stop(&mut self.device_model).await;
+                self.device_model = Some(crate::device_model::create(datalake.clone(), &self.config.devices, &self.config.gateways));
+                changes.push("device drivers restarted".to_string());
+            }
+        }
+
+        if old_config.automations != self.config.automations
+        {
+            stop(&mut self.automations).await;
+            self.automations = Some(crate::automations::create(datalake.clone(), self.config.automations.clone()));
+            changes.push("automations restarted".to_string());
+        }
+        if old_config.scheduler != self.config.scheduler
+        {
+            stop(&mut self.scheduler).await;
+            self.scheduler = Some(crate::scheduler::create(datalake.clone(), self.config.scheduler.clone()));
+            changes.push("scheduler restarted".to_string());
+        }
+        if old_config.scenes != self.config.scenes
+        {
+            stop(&mut self.scenes).await;
+            self.scenes = Some(crate::scenes::create(datalake.clone(), self.config.scenes.clone()));
+            changes.push("scenes restarted".to_string());
+        }
+        if old_config.scripting != self.config.scripting
+        {
+            stop(&mut self.scripting).await;
+            self.scripting = self.config.scripting.clone().map(|scripting| crate::scripting::create(datalake.clone(), scripting));
+            changes.push("scripting restarted".to_string());
+        }
+        if old_config.shutdown != self.config.shutdown
+        {
+            changes.push("shutdown settings changed".to_string());
+        }
+        if old_config.api != self.config.api
+        {
+            self.stop_api().await;
+            self.start_api();
+            changes.push("api front-ends restarted".to_string());
+        }
+        if old_config.mqtt_bridge != self.config.mqtt_bridge
+        {
+            stop(&mut self.mqtt_bridge).await;
+            self.mqtt_bridge = self.config.mqtt_bridge.clone().map(|bridge| crate::mqtt_bridge::create(datalake.clone(), bridge));
+            changes.push("MQTT bridge restarted".to_string());
+        }
+        changes
+    }
+
+    /// Hands all components over for shutdown, they are stopped in reverse order of their start.
+    pub fn into_coordinator(self: Self) -> ShutdownCoordinator
+    {
+        let mut coordinator = ShutdownCoordinator::new(Duration::from_millis(self.config.shutdown.stop_timeout_milliseconds));
+        let mut register = |name: String, handle: Option<TaskHandle>|
+        {
+            if let Some(handle) = handle
+            {
+                coordinator.register(&name, move || handle.stop());
+            }
+        };
+        register("lake persistence".into(), self.persistence);
+        register("printer".into(), self.printer);
+        for (name, handle) in self.gateways
+        {
+            register(format!("gateway '{}'", name), Some(handle));
+        }
+        register("router".into(), self.router);
+        register("device registry".into(), self.device_registry);
+        register("flasher".into(), self.flasher);
+        register("health monitor".into(), self.health_monitor);
+        register("i2c bridge".into(), self.i2c_bridge);
+        register("device drivers".into(), self.device_model);
+        register("automations".into(), self.automations);
+        register("scheduler".into(), self.scheduler);
+        register("scenes".into(), self.scenes);
+        register("scripting".into(), self.scripting);
+        register("gRPC API".into(), self.grpc_api);
+        register("WebSocket API".into(), self.websocket_api);
+        register("HTTP API".into(), self.http_api);
+        register("MQTT API".into(), self.mqtt_api);
+        register("MQTT bridge".into(), self.mqtt_bridge);
+        coordinator
+    }
+}
+
+#[tokio::test]
+async fn reconfigure_restarts_changed_components_only()
+{
+    let config: Config = r#"
+        [[gateways]]
+        name = "ug"
+        gateway_url = "http://127.0.0.1:9"
+        base_path = "/bus/ug"
+        [device_registry]
+        enabled = false
+        [health_monitor]
+        enabled = false
+    "#.parse().unwrap();
+    let mut components = Components::start(TDataLake::new(), config.clone());
+
+    assert!(components.reconfigure(config.clone()).await.is_empty());
+
+    let mut changed = config.clone();
+    changed.health_monitor.interval_seconds = 60;
+    assert_eq!(components.reconfigure(changed.clone()).await, vec!["health monitor restarted".to_string()]);
+
+    changed.gateways.push(BusConfig{name: "eg".into(), gateway_url: "http://127.0.0.1:9".into(), base_path: "/bus/eg".into(), ..Default::default()});
+    let changes = components.reconfigure(changed.clone()).await;
+    assert_eq!(changes[0], "gateway 'eg' started");
+    assert_eq!(components.gateways.len(), 2);
+
+    changed.gateways.remove(0);
+    let changes = components.reconfigure(changed.clone()).await;
+    assert_eq!(changes[0], "gateway 'ug' stopped");
+    assert_eq!(components.gateways.keys().collect::<Vec<_>>(), vec!["eg"]);
+
+    let overdue = components.into_coordinator().shutdown(&TDataLake::new(), Some("/dev/null")).await;
+    assert!(overdue.is_empty());
+}
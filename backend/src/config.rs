@@ -0,0 +1,1411 @@
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// gRPC service used to exchange messages with a gateway.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BusAccessMode
+{
+    /// `MessagePackBusAccess`: payloads are exchanged as JSON strings,
+    /// the gateway converts them from/to MessagePack on the bus
+    #[default]
+    MessagePack,
+
+    /// `RawBusAccess`: payloads are exchanged as plain bytes, for legacy devices
+    /// which do not speak the JSON/MessagePack framing
+    Raw,
+}
+
+/// How payloads of a device are encoded on the bus, see `BusConfig::encodings`.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadEncoding
+{
+    /// plain bytes, only published as `Vec<u8>`
+    #[default]
+    Raw,
+
+    /// UTF-8 JSON text
+    Json,
+
+    /// binary MessagePack
+    MessagePack,
+}
+
+/// Connection parameters of a single bus gateway.
+/// All fields are optional in the configuration file and fall back to the
+/// values of `BusConfig::default()`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BusConfig
+{
+    /// Name of the gateway, used to refer to it in logs and status paths, e.g. "ug"
+    pub name: String,
+
+    /// gRPC endpoint of the gateway, e.g. "http://192.168.0.200:50051"
+    pub gateway_url: String,
+
+    /// Service used to talk to the gateway, "message_pack" or "raw"
+    pub mode: BusAccessMode,
+
+    /// Mask selecting which remote addresses to receive messages from, e.g. "0:255"
+    pub rx_mask: String,
+
+    /// Address of the backend itself on the bus, e.g. "0:1"
+    pub own_address: String,
+
+    /// Timeout used when transmitting messages to the bus
+    pub default_timeout_milliseconds: u32,
+
+    /// Lake path below which the rx/tx paths of this gateway are located, e.g. "/bus/ug"
+    pub base_path: String,
+
+    /// Logical ids of the devices reachable via this gateway.
+    /// Transmit requests to these devices published to `bus_access::ROUTED_TX_PATH`
+    /// are forwarded to this gateway. The only gateway owns all devices.
+    pub devices: Vec<String>,
+
+    /// Payload encoding per device id, only applicable in raw mode: payloads of these devices
+    /// are decoded into a `Value` on receive and `TransmitRequest`s to them are encoded.
+    /// Devices not listed are raw. In message_pack mode the gateway itself converts
+    /// between MessagePack on the bus and JSON.
+    pub encodings: HashMap<String, PayloadEncoding>,
+}
+
+impl BusConfig
+{
+    pub fn encoding_of(self: &Self, device_id: &str) -> PayloadEncoding
+    {
+        self.encodings.get(device_id).copied().unwrap_or_default()
+    }
+}
+
+impl Default for BusConfig
+{
+    fn default() -> Self
+    {
+        BusConfig{
+            name: "ug".into(),
+            gateway_url: "http://192.168.0.200:50051".into(),
+            mode: BusAccessMode::MessagePack,
+            rx_mask: "0:255".into(),
+            own_address: "0:1".into(),
+            default_timeout_milliseconds: 5000,
+            base_path: "/bus/ug".into(),
+            devices: Vec::new(),
+            encodings: HashMap::new(),
+        }
+    }
+}
+
+/// Settings of the device registry, see `device_registry`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceRegistryConfig
+{
+    /// Periodically scan all gateways for devices
+    pub enabled: bool,
+
+    /// Time between two scans
+    pub scan_interval_seconds: u64,
+
+    /// How long a single scan waits for devices to answer
+    pub scan_timeout_milliseconds: u32,
+}
+
+impl Default for DeviceRegistryConfig
+{
+    fn default() -> Self
+    {
+        DeviceRegistryConfig{
+            enabled: true,
+            scan_interval_seconds: 600,
+            scan_timeout_milliseconds: 5000,
+        }
+    }
+}
+
+/// Settings of the gateway health monitor, see `health_monitor`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthMonitorConfig
+{
+    pub enabled: bool,
+
+    /// Time between two probes of a gateway
+    pub interval_seconds: u64,
+
+    /// A gateway not answering within this time is considered unreachable
+    pub timeout_milliseconds: u64,
+}
+
+impl Default for HealthMonitorConfig
+{
+    fn default() -> Self
+    {
+        HealthMonitorConfig{
+            enabled: true,
+            interval_seconds: 30,
+            timeout_milliseconds: 2000,
+        }
+    }
+}
+
+/// Settings of the shutdown on SIGINT/SIGTERM, see `shutdown`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig
+{
+    /// Time each component gets to stop before it is abandoned
+    pub stop_timeout_milliseconds: u64,
+
+    /// File the retained values of the lake are written to after all components stopped.
+    /// Printed to stdout if not given.
+    pub snapshot_path: Option<String>,
+}
+
+impl Default for ShutdownConfig
+{
+    fn default() -> Self
+    {
+        ShutdownConfig{
+            stop_timeout_milliseconds: 5000,
+            snapshot_path: None,
+        }
+    }
+}
+
+/// Persistence of the lake's retained values across restarts, see `persistence`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LakeConfig
+{
+    /// File retained `Value`s are saved to periodically and on shutdown, and restored from on startup.
+    /// Nothing is persisted if not given.
+    pub persistence_path: Option<String>,
+
+    pub save_interval_seconds: u64,
+
+    /// Lake paths (wildcards allowed) whose retained values are persisted
+    pub persisted_paths: Vec<String>,
+
+    /// Lake paths (wildcards allowed) whose `String` messages are printed to stdout
+    pub print_paths: Vec<String>,
+}
+
+impl Default for LakeConfig
+{
+    fn default() -> Self
+    {
+        LakeConfig{
+            persistence_path: None,
+            save_interval_seconds: 60,
+            persisted_paths: vec!["/**".into()],
+            print_paths: vec!["/**/rx/*".into()],
+        }
+    }
+}
+
+/// Listen addresses of the API front-ends, e.g. "0.0.0.0:50052". A front-end without address is not started.
+#[derive(Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig
+{
+    pub grpc_listen: Option<String>,
+    pub websocket_listen: Option<String>,
+    pub http_listen: Option<String>,
+    pub mqtt_listen: Option<String>,
+
+    /// Everyone may access everything if missing
+    pub acl: Option<AclConfig>,
+}
+
+/// Path patterns a role may publish to and receive (subscribe, get, browse) through the API front-ends.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RoleConfig
+{
+    pub name: String,
+
+    #[serde(default)]
+    pub publish: Vec<String>,
+
+    #[serde(default)]
+    pub subscribe: Vec<String>,
+}
+
+/// A client of the API front-ends, identified by its token: a bearer token in the `Authorization`
+/// header (gRPC, HTTP, WebSocket), the `token` query parameter (WebSocket) or the password (MQTT,
+/// the user name being the client name).
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig
+{
+    pub name: String,
+
+    pub token: String,
+
+    pub role: String,
+}
+
+/// Access control of the API front-ends.
+#[derive(Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AclConfig
+{
+    /// Role of clients without token, they are rejected if missing
+    pub anonymous_role: Option<String>,
+
+    /// Written as `[[api.acl.roles]]` tables.
+    pub roles: Vec<RoleConfig>,
+
+    /// Written as `[[api.acl.clients]]` tables.
+    pub clients: Vec<ClientConfig>,
+}
+
+/// Kind of a device, selects the driver of `device_model`.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceKind
+{
+    Switch,
+    Dimmer,
+    RgbLight,
+    DoorOpener,
+    TemperatureSensor,
+    Shutter,
+}
+
+/// A device on the bus. Alternative to listing the device in `BusConfig::devices`
+/// and `BusConfig::encodings`, both are merged when the configuration is read.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig
+{
+    /// Logical id of the device, e.g. "5"
+    pub id: String,
+
+    /// Name of the gateway the device is connected to, may be omitted if there is only one gateway
+    pub gateway: Option<String>,
+
+    /// Human readable name
+    pub name: Option<String>,
+
+    pub encoding: Option<PayloadEncoding>,
+
+    /// Translates between the bus messages of the device and properties below `path`
+    pub kind: Option<DeviceKind>,
+
+    /// Lake path of the device, e.g. "/house/eg/kitchen/lamp", required with `kind`
+    pub path: Option<String>,
+}
+
+/// Step of an automation, written as inline table with a `type` key,
+/// e.g. `{type = "publish", path = "/house/light", value = true}`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ActionConfig
+{
+    /// Publish a retained `Value` to a lake path
+    Publish{path: String, value: toml::Value},
+
+    /// Send a `TransmitRequest` to a device via `bus_access::ROUTED_TX_PATH`
+    Transmit{device: String, value: toml::Value},
+
+    Delay{milliseconds: u64},
+}
+
+/// Comparison of a `ConditionConfig`
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConditionOperator
+{
+    #[default]
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Compares a `Value` with `value`, e.g. `{path = "/house/mode", op = "ne", value = "night"}`.
+/// Numbers compare by value regardless of integer or float, strings compare alphabetically.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConditionConfig
+{
+    /// Lake path of the retained value compared, the value which triggered the automation if omitted
+    pub path: Option<String>,
+
+    #[serde(default)]
+    pub op: ConditionOperator,
+
+    pub value: toml::Value,
+}
+
+/// Actions run whenever a `Value` is published to a path matching `trigger`
+/// and all `conditions` hold.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AutomationConfig
+{
+    pub name: String,
+
+    /// Initial state, automations can be enabled and disabled at runtime, see `automations`
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+
+    /// Lake path, wildcards allowed
+    pub trigger: String,
+
+    #[serde(default)]
+    pub conditions: Vec<ConditionConfig>,
+
+    pub actions: Vec<ActionConfig>,
+}
+
+fn default_enabled() -> bool
+{
+    true
+}
+
+/// Direction values are forwarded by an `MqttMappingConfig`.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MqttDirection
+{
+    #[default]
+    Both,
+    ToMqtt,
+    FromMqtt,
+}
+
+/// Mirrors the lake paths matching `lake` to MQTT topics below `topic`.
+/// The part of `lake` before the first wildcard corresponds to `topic`, the rest is kept,
+/// e.g. `lake = "/house/**"` and `topic = "home"` map `/house/eg/light` to `home/eg/light`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MqttMappingConfig
+{
+    /// Lake path pattern, only `*` and a trailing `**` can be expressed in MQTT
+    pub lake: String,
+
+    /// Defaults to the part of `lake` before the first wildcard, without leading '/'
+    pub topic: Option<String>,
+
+    #[serde(default)]
+    pub direction: MqttDirection,
+
+    /// Values are state: sent to MQTT with retain flag and retained in the lake.
+    /// Otherwise the retain flag of received MQTT messages decides.
+    #[serde(default = "default_enabled")]
+    pub retain: bool,
+}
+
+/// MQTT client connection of the `mqtt_bridge`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MqttBridgeConfig
+{
+    /// "host:port" of the MQTT broker
+    pub broker: String,
+
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+
+    pub username: Option<String>,
+    pub password: Option<String>,
+
+    /// Written as `[[mqtt_bridge.mappings]]` tables.
+    pub mappings: Vec<MqttMappingConfig>,
+}
+
+fn default_mqtt_client_id() -> String
+{
+    "homecentral".into()
+}
+
+/// Event of the sun a `TimerConfig` can be bound to.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SunEvent
+{
+    Sunrise,
+    Sunset,
+}
+
+/// A retained `Value` published to a lake path, by a timer or a scene.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PublishConfig
+{
+    pub path: String,
+    pub value: toml::Value,
+}
+
+/// Publishes values when due, exactly one of `cron`, `at` and `sun` has to be given.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TimerConfig
+{
+    pub name: String,
+
+    /// Recurring, "minute hour day-of-month month day-of-week" in local time,
+    /// e.g. "0 23 * * *" or "30 6 * * mon-fri" (numeric days of week are 1-7 from Sunday)
+    pub cron: Option<String>,
+
+    /// Once, local time as "2026-12-24T18:00:00"
+    pub at: Option<String>,
+
+    /// Daily, at sunrise or sunset of the scheduler's location
+    pub sun: Option<SunEvent>,
+
+    /// Shifts `sun` events, negative for earlier
+    #[serde(default)]
+    pub offset_minutes: i64,
+
+    pub publish: Vec<PublishConfig>,
+}
+
+/// Target state of several paths, activated through the lake, see `scenes`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SceneConfig
+{
+    pub name: String,
+
+    pub targets: Vec<PublishConfig>,
+}
+
+/// Another name of a lake path and everything below it, see `TDataLake::add_alias`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AliasConfig
+{
+    /// e.g. "/house/kg/workshop/light"
+    pub path: String,
+
+    /// e.g. "/bus/ug/rx/5"
+    pub target: String,
+}
+
+/// Timers of the `scheduler`, the location is needed for sunrise and sunset only.
+#[derive(Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig
+{
+    /// Degrees, north positive
+    pub latitude: Option<f64>,
+
+    /// Degrees, east positive
+    pub longitude: Option<f64>,
+
+    /// Written as `[[scheduler.timers]]` tables.
+    pub timers: Vec<TimerConfig>,
+}
+
+/// Rhai scripts run by `scripting`, limits apply to the script's setup and to each call of a handler.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptingConfig
+{
+    /// Directory of the `*.rhai` files, scripts are (re)started whenever their file changes
+    pub directory: String,
+
+    #[serde(default = "default_script_max_operations")]
+    pub max_operations: u64,
+
+    #[serde(default = "default_script_timeout_milliseconds")]
+    pub timeout_milliseconds: u64,
+}
+
+fn default_script_max_operations() -> u64
+{
+    1_000_000
+}
+
+fn default_script_timeout_milliseconds() -> u64
+{
+    1000
+}
+
+/// How the bytes read from an I2C register are turned into a value.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum I2cDecoding
+{
+    /// publish the bytes as they are (`Vec<u8>`)
+    Raw,
+    U8,
+    I8,
+    U16Be,
+    U16Le,
+    I16Be,
+    I16Le,
+    U32Be,
+    U32Le,
+    I32Be,
+    I32Le,
+}
+
+/// A register of an I2C peripheral on a remote node which is read periodically,
+/// see `i2c_bridge`.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct I2cPollConfig
+{
+    /// Logical id of the device the peripheral is attached to
+    pub device: String,
+    pub bus: u32,
+    /// 7 bit I2C address of the peripheral
+    pub address: u32,
+    /// Bytes written before reading, usually the register number
+    #[serde(default)]
+    pub write: Vec<u8>,
+    /// Number of bytes to read
+    pub read: u32,
+    pub decode: I2cDecoding,
+    /// Factor the decoded number is multiplied with before publishing
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    pub interval_milliseconds: u64,
+    /// Lake path the value is published to, as `Value::Float` (or `Value::Binary` for raw decoding)
+    pub path: String,
+}
+
+fn default_scale() -> f64
+{
+    1.0
+}
+
+/// Backend configuration as read from the configuration file (TOML).
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config
+{
+    /// One entry per bus gateway, written as `[[gateways]]` tables.
+    pub gateways: Vec<BusConfig>,
+
+    pub device_registry: DeviceRegistryConfig,
+
+    pub health_monitor: HealthMonitorConfig,
+
+    /// I2C registers polled periodically, written as `[[i2c_polls]]` tables.
+    pub i2c_polls: Vec<I2cPollConfig>,
+
+    pub shutdown: ShutdownConfig,
+
+    pub lake: LakeConfig,
+
+    pub api: ApiConfig,
+
+    /// Devices on the bus, written as `[[devices]]` tables.
+    pub devices: Vec<DeviceConfig>,
+
+    /// Written as `[[automations]]` tables.
+    pub automations: Vec<AutomationConfig>,
+
+    /// Not connected to any MQTT broker if missing
+    pub mqtt_bridge: Option<MqttBridgeConfig>,
+
+    pub scheduler: SchedulerConfig,
+
+    /// No scripts are run if missing
+    pub scripting: Option<ScriptingConfig>,
+
+    /// Written as `[[scenes]]` tables, more scenes can be defined at runtime.
+    pub scenes: Vec<SceneConfig>,
+
+    /// Written as `[[aliases]]` tables.
+    pub aliases: Vec<AliasConfig>,
+}
+
+impl Default for Config
+{
+    fn default() -> Self
+    {
+        Config{
+            gateways: vec![BusConfig::default()],
+            device_registry: DeviceRegistryConfig::default(),
+            health_monitor: HealthMonitorConfig::default(),
+            i2c_polls: Vec::new(),
+            shutdown: ShutdownConfig::default(),
+            lake: LakeConfig::default(),
+            api: ApiConfig::default(),
+            devices: Vec::new(),
+            automations: Vec::new(),
+            mqtt_bridge: None,
+            scheduler: SchedulerConfig::default(),
+            scripting: None,
+            scenes: Vec::new(),
+            aliases: Vec::new(),
+        }
+    }
+}
+
+impl Config
+{
+    /// Reads and parses the configuration file at the given location.
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self, String>
+    {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read configuration file '{}': {}", path.display(), e))?;
+        content.parse()
+    }
+}
+
+impl FromStr for Config
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let mut config: Config = toml::from_str(s).map_err(|e| format!("Invalid configuration: {}", e))?;
+        config.merge_devices().map_err(|e| format!("Invalid configuration: {}", e))?;
+        config.validate().map_err(|e| format!("Invalid configuration: {}", e))?;
+        Ok(config)
+    }
+}
+
+impl Config
+{
+    /// Adds the `[[devices]]` entries to the devices and encodings of their gateways.
+    fn merge_devices(self: &mut Self) -> Result<(), String>
+    {
+        let mut ids = HashSet::new();
+        for (i, device) in self.devices.iter().enumerate()
+        {
+            if !ids.insert(device.id.as_str())
+            {
+                return Err(format!("devices[{}].id: device '{}' listed more than once", i, device.id));
+            }
+            let gateway = match &device.gateway
+            {
+                Some(name) => self.gateways.iter_mut().find(|gateway| gateway.name == *name)
+                    .ok_or(format!("devices[{}].gateway: unknown gateway '{}'", i, name))?,
+                None if self.gateways.len() == 1 => &mut self.gateways[0],
+                None => return Err(format!("devices[{}].gateway: required if more than one gateway is configured", i)),
+            };
+            if !gateway.devices.contains(&device.id)
+            {
+                gateway.devices.push(device.id.clone());
+            }
+            if let Some(encoding) = device.encoding
+            {
+                gateway.encodings.insert(device.id.clone(), encoding);
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_devices(self: &Self) -> Result<(), String>
+    {
+        let mut paths = HashSet::new();
+        for (i, device) in self.devices.iter().enumerate()
+        {
+            match (&device.kind, &device.path)
+            {
+                (Some(_), Some(path)) => match path.parse::<crate::data_lake::path_tree::Path>()
+                {
+                    Err(e) => return Err(format!("devices[{}].path: {}", i, e)),
+                    Ok(parsed) if parsed.has_wildcards() => return Err(format!("devices[{}].path: wildcards are not allowed", i)),
+                    Ok(_) if !paths.insert(path.as_str()) => return Err(format!("devices[{}].path: '{}' used by more than one device", i, path)),
+                    Ok(_) => ()
+                },
+                (None, None) => (),
+                (Some(_), None) => return Err(format!("devices[{}].path: required with kind", i)),
+                (None, Some(_)) => return Err(format!("devices[{}].kind: required with path", i)),
+            }
+        }
+        Ok(())
+    }
+
+    fn validate(self: &Self) -> Result<(), String>
+    {
+        let mut names = HashSet::new();
+        let mut base_paths = HashSet::new();
+        let mut device_owners = HashMap::new();
+        for gateway in self.gateways.iter()
+        {
+            if !names.insert(gateway.name.as_str())
+            {
+                return Err(format!("gateways: name '{}' used more than once", gateway.name));
+            }
+            match gateway.base_path.parse::<crate::data_lake::path_tree::Path>()
+            {
+                Err(e) => return Err(format!("gateways.{}.base_path: {}", gateway.name, e)),
+                Ok(path) if path.has_wildcards() => return Err(format!("gateways.{}.base_path: wildcards are not allowed", gateway.name)),
+                Ok(_) => ()
+            }
+            if gateway.base_path.clone() + "/tx" == crate::bus_access::ROUTED_TX_PATH
+            {
+                return Err(format!("gateways.{}.base_path: '{}/tx' is reserved for routed transmissions", gateway.name, gateway.base_path));
+            }
+            if !base_paths.insert(gateway.base_path.as_str())
+            {
+                return Err(format!("gateways.{}.base_path: '{}' used by more than one gateway", gateway.name, gateway.base_path));
+            }
+            if gateway.mode != BusAccessMode::Raw && !gateway.encodings.is_empty()
+            {
+                return Err(format!("gateways.{}.encodings: only applicable with mode = \"raw\"", gateway.name));
+            }
+            for device in gateway.devices.iter()
+            {
+                if let Some(owner) = device_owners.insert(device.as_str(), gateway.name.as_str())
+                {
+                    return Err(format!("gateways.{}.devices: device '{}' is already owned by gateway '{}'", gateway.name, device, owner));
+                }
+            }
+        }
+        if self.device_registry.scan_interval_seconds == 0
+        {
+            return Err("device_registry.scan_interval_seconds: must be greater than 0".into());
+        }
+        if self.health_monitor.interval_seconds == 0
+        {
+            return Err("health_monitor.interval_seconds: must be greater than 0".into());
+        }
+        if self.health_monitor.timeout_milliseconds == 0
+        {
+            return Err("health_monitor.timeout_milliseconds: must be greater than 0".into());
+        }
+        for (i, poll) in self.i2c_polls.iter().enumerate()
+        {
+            if let Err(e) = poll.path.parse::<crate::data_lake::path_tree::Path>()
+            {
+                return Err(format!("i2c_polls[{}].path: {}", i, e));
+            }
+            if poll.address > crate::i2c_bridge::MAX_ADDRESS
+            {
+                return Err(format!("i2c_polls[{}].address: 0x{:x} is not a 7 bit address", i, poll.address));
+            }
+            if poll.interval_milliseconds == 0
+            {
+                return Err(format!("i2c_polls[{}].interval_milliseconds: must be greater than 0", i));
+            }
+            if (poll.read as usize) < crate::i2c_bridge::decoded_size(poll.decode)
+            {
+                return Err(format!("i2c_polls[{}].read: {} bytes are not enough for decoding as {:?}", i, poll.read, poll.decode));
+            }
+            if crate::bus_access::gateway_of(&self.gateways, &poll.device).is_none()
+            {
+                return Err(format!("i2c_polls[{}].device: no gateway owns device '{}'", i, poll.device));
+            }
+        }
+        self.validate_devices()?;
+        self.validate_lake()?;
+        self.validate_api()?;
+        self.validate_automations()?;
+        self.validate_mqtt_bridge()?;
+        self.validate_scheduler()?;
+        self.validate_scenes()?;
+        self.validate_aliases()?;
+        if let Some(scripting) = &self.scripting
+        {
+            if scripting.max_operations == 0
+            {
+                return Err("scripting.max_operations: must be greater than 0".into());
+            }
+            if scripting.timeout_milliseconds == 0
+            {
+                return Err("scripting.timeout_milliseconds: must be greater than 0".into());
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_lake(self: &Self) -> Result<(), String>
+    {
+        if self.lake.persistence_path.is_some() && self.lake.save_interval_seconds == 0
+        {
+            return Err("lake.save_interval_seconds: must be greater than 0".into());
+        }
+        for (name, paths) in [("persisted_paths", &self.lake.persisted_paths), ("print_paths", &self.lake.print_paths)]
+        {
+            for (i, path) in paths.iter().enumerate()
+            {
+                if let Err(e) = path.parse::<crate::data_lake::path_tree::Path>()
+                {
+                    return Err(format!("lake.{}[{}]: {}", name, i, e));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_api(self: &Self) -> Result<(), String>
+    {
+        let listeners = [
+            ("grpc_listen", &self.api.grpc_listen),
+            ("websocket_listen", &self.api.websocket_listen),
+            ("http_listen", &self.api.http_listen),
+            ("mqtt_listen", &self.api.mqtt_listen),
+        ];
+        let mut addresses = HashMap::new();
+        for (name, address) in listeners
+        {
+            if let Some(address) = address
+            {
+                let address: std::net::SocketAddr = address.parse()
+                    .map_err(|e| format!("api.{}: '{}' is no valid socket address: {}", name, address, e))?;
+                if let Some(other) = addresses.insert(address, name)
+                {
+                    return Err(format!("api.{}: {} is already used by api.{}", name, address, other));
+                }
+            }
+        }
+        if let Some(acl) = &self.api.acl
+        {
+            crate::api::acl::Acl::new(acl).map_err(|e| format!("api.acl.{}", e))?;
+        }
+        Ok(())
+    }
+
+    fn validate_automations(self: &Self) -> Result<(), String>
+    {
+        let mut names = HashSet::new();
+        for (i, automation) in self.automations.iter().enumerate()
+        {
+            if !names.insert(automation.name.as_str())
+            {
+                return Err(format!("automations[{}].name: '{}' used more than once", i, automation.name));
+            }
+            crate::automations::state_path(&automation.name, "enabled").map_err(|e| format!("automations[{}].name: {}", i, e))?;
+            if let Err(e) = automation.trigger.parse::<crate::data_lake::path_tree::Path>()
+            {
+                return Err(format!("automations[{}].trigger: {}", i, e));
+            }
+            for (j, condition) in automation.conditions.iter().enumerate()
+            {
+                match condition.path.as_ref().map(|path| path.parse::<crate::data_lake::path_tree::Path>())
+                {
+                    Some(Err(e)) => return Err(format!("automations[{}].conditions[{}].path: {}", i, j, e)),
+                    Some(Ok(path)) if path.has_wildcards() => return Err(format!("automations[{}].conditions[{}].path: wildcards are not allowed", i, j)),
+                    _ => ()
+                }
+            }
+            for (j, action) in automation.actions.iter().enumerate()
+            {
+                match action
+                {
+                    ActionConfig::Publish{path, ..} => match path.parse::<crate::data_lake::path_tree::Path>()
+                    {
+                        Err(e) => return Err(format!("automations[{}].actions[{}].path: {}", i, j, e)),
+                        Ok(path) if path.has_wildcards() => return Err(format!("automations[{}].actions[{}].path: wildcards are not allowed", i, j)),
+                        Ok(_) => ()
+                    },
+                    ActionConfig::Transmit{device, ..} => if crate::bus_access::gateway_of(&self.gateways, device).is_none()
+                    {
+                        return Err(format!("automations[{}].actions[{}].device: no gateway owns device '{}'", i, j, device));
+                    },
+                    ActionConfig::Delay{..} => ()
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_mqtt_bridge(self: &Self) -> Result<(), String>
+    {
+        let bridge = match &self.mqtt_bridge
+        {
+            Some(bridge) => bridge,
+            None => return Ok(()),
+        };
+        crate::mqtt_bridge::parse_broker(&bridge.broker).map_err(|e| format!("mqtt_bridge.broker: {}", e))?;
+        for (i, mapping) in bridge.mappings.iter().enumerate()
+        {
+            crate::mqtt_bridge::TopicMapping::new(mapping).map_err(|e| format!("mqtt_bridge.mappings[{}]: {}", i, e))?;
+        }
+        Ok(())
+    }
+
+    fn validate_scenes(self: &Self) -> Result<(), String>
+    {
+        let mut names = HashSet::new();
+        for (i, scene) in self.scenes.iter().enumerate()
+        {
+            if !names.insert(scene.name.as_str())
+            {
+                return Err(format!("scenes[{}].name: '{}' used more than once", i, scene.name));
+            }
+            crate::scenes::scene_path(&scene.name, "targets").map_err(|e| format!("scenes[{}].name: {}", i, e))?;
+            for (j, target) in scene.targets.iter().enumerate()
+            {
+                match target.path.parse::<crate::data_lake::path_tree::Path>()
+                {
+                    Err(e) => return Err(format!("scenes[{}].targets[{}].path: {}", i, j, e)),
+                    Ok(path) if path.has_wildcards() => return Err(format!("scenes[{}].targets[{}].path: wildcards are not allowed", i, j)),
+                    Ok(_) => ()
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_aliases(self: &Self) -> Result<(), String>
+    {
+        let mut aliases = crate::data_lake::aliases::Aliases::new();
+        for (i, alias) in self.aliases.iter().enumerate()
+        {
+            let path = alias.path.parse().map_err(|e| format!("aliases[{}].path: {}", i, e))?;
+            let target = alias.target.parse().map_err(|e| format!("aliases[{}].target: {}", i, e))?;
+            aliases.add(&path, &target).map_err(|e| format!("aliases[{}]: {}", i, e))?;
+        }
+        Ok(())
+    }
+
+    fn validate_scheduler(self: &Self) -> Result<(), String>
+    {
+        let scheduler = &self.scheduler;
+        if scheduler.latitude.is_some_and(|latitude| !(-90.0..=90.0).contains(&latitude))
+        {
+            return Err("scheduler.latitude: must be within -90 and 90".into());
+        }
+        if scheduler.longitude.is_some_and(|longitude| !(-180.0..=180.0).contains(&longitude))
+        {
+            return Err("scheduler.longitude: must be within -180 and 180".into());
+        }
+        let mut names = HashSet::new();
+        for (i, timer) in scheduler.timers.iter().enumerate()
+        {
+            if !names.insert(timer.name.as_str())
+            {
+                return Err(format!("scheduler.timers[{}].name: '{}' used more than once", i, timer.name));
+            }
+            crate::scheduler::state_path(&timer.name, "next_run").map_err(|e| format!("scheduler.timers[{}].name: {}", i, e))?;
+            match (&timer.cron, &timer.at, &timer.sun)
+            {
+                (Some(cron), None, None) => crate::scheduler::parse_cron(cron).map(|_| ())
+                    .map_err(|e| format!("scheduler.timers[{}].cron: {}", i, e))?,
+                (None, Some(at), None) => crate::scheduler::parse_at(at).map(|_| ())
+                    .map_err(|e| format!("scheduler.timers[{}].at: {}", i, e))?,
+                (None, None, Some(_)) => if scheduler.latitude.is_none() || scheduler.longitude.is_none()
+                {
+                    return Err(format!("scheduler.timers[{}].sun: scheduler.latitude and scheduler.longitude are required", i));
+                },
+                _ => return Err(format!("scheduler.timers[{}]: exactly one of cron, at and sun is required", i)),
+            }
+            if timer.sun.is_none() && timer.offset_minutes != 0
+            {
+                return Err(format!("scheduler.timers[{}].offset_minutes: only applicable with sun", i));
+            }
+            for (j, publish) in timer.publish.iter().enumerate()
+            {
+                match publish.path.parse::<crate::data_lake::path_tree::Path>()
+                {
+                    Err(e) => return Err(format!("scheduler.timers[{}].publish[{}].path: {}", i, j, e)),
+                    Ok(path) if path.has_wildcards() => return Err(format!("scheduler.timers[{}].publish[{}].path: wildcards are not allowed", i, j)),
+                    Ok(_) => ()
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse_bus_config()
+{
+    let config: Config = r#"
+        [[gateways]]
+        name = "eg"
+        gateway_url = "http://10.0.0.1:50051"
+        rx_mask = "0:127"
+        own_address = "0:2"
+        default_timeout_milliseconds = 1000
+        base_path = "/bus/eg"
+        devices = ["5", "7"]
+    "#.parse().unwrap();
+    assert_eq!(config.gateways.len(), 1);
+    let bus = &config.gateways[0];
+    assert_eq!(bus.name, "eg");
+    assert_eq!(bus.gateway_url, "http://10.0.0.1:50051");
+    assert_eq!(bus.rx_mask, "0:127");
+    assert_eq!(bus.own_address, "0:2");
+    assert_eq!(bus.default_timeout_milliseconds, 1000);
+    assert_eq!(bus.base_path, "/bus/eg");
+    assert_eq!(bus.devices, vec!["5".to_string(), "7".to_string()]);
+    assert_eq!(bus.mode, BusAccessMode::MessagePack);
+
+    let config: Config = "[[gateways]]\nmode = \"raw\"".parse().unwrap();
+    assert_eq!(config.gateways[0].mode, BusAccessMode::Raw);
+    assert!("[[gateways]]\nmode = \"morse\"".parse::<Config>().is_err());
+}
+
+#[test]
+fn test_parse_encodings()
+{
+    let config: Config = "[[gateways]]\nmode = \"raw\"\nencodings = {5 = \"message_pack\", 7 = \"json\"}".parse().unwrap();
+    let bus = &config.gateways[0];
+    assert_eq!(bus.encoding_of("5"), PayloadEncoding::MessagePack);
+    assert_eq!(bus.encoding_of("7"), PayloadEncoding::Json);
+    assert_eq!(bus.encoding_of("9"), PayloadEncoding::Raw);
+
+    // the message_pack gateway converts payloads itself
+    assert!("[[gateways]]\nencodings = {5 = \"message_pack\"}".parse::<Config>().is_err());
+    assert!("[[gateways]]\nmode = \"raw\"\nencodings = {5 = \"bson\"}".parse::<Config>().is_err());
+}
+
+#[test]
+fn test_parse_bus_config_defaults()
+{
+    let config: Config = "[[gateways]]\nbase_path = \"/bus/og\"".parse().unwrap();
+    assert_eq!(config.gateways[0].base_path, "/bus/og");
+    assert_eq!(config.gateways[0].rx_mask, BusConfig::default().rx_mask);
+
+    let config: Config = "".parse().unwrap();
+    assert_eq!(config, Config::default());
+}
+
+#[test]
+fn test_parse_bus_config_invalid()
+{
+    assert!("[[gateways]]\nbase_path = \"bus/og\"".parse::<Config>().is_err());
+    assert!("[[gateways]]\nrx_mask = 5".parse::<Config>().is_err());
+    assert!("[[gateways]]\nunknown = 5".parse::<Config>().is_err());
+    assert!("[[gateways]]\nbase_path = \"/bus\"".parse::<Config>().is_err());
+    assert!("[[gateways]]\nbase_path = \"/bus/*\"".parse::<Config>().is_err());
+}
+
+#[test]
+fn test_parse_multiple_gateways()
+{
+    let config: Config = r#"
+        [[gateways]]
+        name = "ug"
+        base_path = "/bus/ug"
+        devices = ["1"]
+        [[gateways]]
+        name = "eg"
+        base_path = "/bus/eg"
+        devices = ["2", "3"]
+    "#.parse().unwrap();
+    assert_eq!(config.gateways.len(), 2);
+    assert_eq!(config.gateways[1].name, "eg");
+
+    // same device owned by two gateways:
+    assert!(r#"
+        [[gateways]]
+        name = "ug"
+        base_path = "/bus/ug"
+        devices = ["1"]
+        [[gateways]]
+        name = "eg"
+        base_path = "/bus/eg"
+        devices = ["1"]
+    "#.parse::<Config>().is_err());
+
+    // duplicate names and base paths:
+    assert!("[[gateways]]\nbase_path = \"/bus/a\"\n[[gateways]]\nbase_path = \"/bus/b\"".parse::<Config>().is_err());
+    assert!("[[gateways]]\nname = \"a\"\n[[gateways]]\nname = \"b\"".parse::<Config>().is_err());
+}
+
+#[test]
+fn test_parse_device_registry_config()
+{
+    let config: Config = "[device_registry]\nscan_interval_seconds = 60".parse().unwrap();
+    assert!(config.device_registry.enabled);
+    assert_eq!(config.device_registry.scan_interval_seconds, 60);
+    assert!("[device_registry]\nscan_interval_seconds = 0".parse::<Config>().is_err());
+}
+
+#[test]
+fn test_parse_health_monitor_config()
+{
+    let config: Config = "[health_monitor]\ntimeout_milliseconds = 500".parse().unwrap();
+    assert_eq!(config.health_monitor.timeout_milliseconds, 500);
+    assert_eq!(config.health_monitor.interval_seconds, 30);
+    assert!("[health_monitor]\ninterval_seconds = 0".parse::<Config>().is_err());
+    let error = "[health_monitor]\ntimeout_milliseconds = 0".parse::<Config>().unwrap_err();
+    assert!(error.contains("health_monitor.timeout_milliseconds"), "{}", error);
+}
+
+#[test]
+fn test_parse_i2c_polls()
+{
+    let config: Config = r#"
+        [[i2c_polls]]
+        device = "5"
+        bus = 0
+        address = 0x48
+        write = [0x00]
+        read = 2
+        decode = "i16_be"
+        scale = 0.0078125
+        interval_milliseconds = 10000
+        path = "/house/eg/kitchen/temperature"
+    "#.parse().unwrap();
+    assert_eq!(config.i2c_polls[0].address, 0x48);
+    assert_eq!(config.i2c_polls[0].decode, I2cDecoding::I16Be);
+
+    // no 7 bit address:
+    let error = r#"
+        [[i2c_polls]]
+        device = "5"
+        bus = 0
+        address = 0x90
+        read = 2
+        decode = "u16_le"
+        interval_milliseconds = 10000
+        path = "/house/eg/kitchen/temperature"
+    "#.parse::<Config>().unwrap_err();
+    assert!(error.contains("i2c_polls[0].address"), "{}", error);
+
+    // too few bytes for decoding:
+    assert!(r#"
+        [[i2c_polls]]
+        device = "5"
+        bus = 0
+        address = 0x48
+        read = 1
+        decode = "u16_le"
+        interval_milliseconds = 10000
+        path = "/house/eg/kitchen/temperature"
+    "#.parse::<Config>().is_err());
+}
+
+#[test]
+fn test_parse_devices()
+{
+    let config: Config = r#"
+        [[gateways]]
+        name = "ug"
+        base_path = "/bus/ug"
+        [[gateways]]
+        name = "kg"
+        mode = "raw"
+        base_path = "/bus/kg"
+        devices = ["9"]
+        [[devices]]
+        id = "5"
+        gateway = "ug"
+        name = "kitchen light"
+        [[devices]]
+        id = "9"
+        gateway = "kg"
+        encoding = "message_pack"
+    "#.parse().unwrap();
+    assert_eq!(config.gateways[0].devices, vec!["5".to_string()]);
+    assert_eq!(config.gateways[1].devices, vec!["9".to_string()]);
+    assert_eq!(config.gateways[1].encoding_of("9"), PayloadEncoding::MessagePack);
+
+    let config: Config = "[[devices]]\nid = \"5\"".parse().unwrap();
+    assert_eq!(config.gateways[0].devices, vec!["5".to_string()]);
+
+    let error = "[[devices]]\nid = \"5\"\ngateway = \"og\"".parse::<Config>().unwrap_err();
+    assert!(error.contains("devices[0].gateway"), "{}", error);
+    let error = "[[gateways]]\nname = \"a\"\nbase_path = \"/a\"\n[[gateways]]\nname = \"b\"\nbase_path = \"/b\"\n[[devices]]\nid = \"5\"".parse::<Config>().unwrap_err();
+    assert!(error.contains("devices[0].gateway"), "{}", error);
+    // message_pack gateways take no encodings
+    assert!("[[devices]]\nid = \"5\"\nencoding = \"json\"".parse::<Config>().is_err());
+
+    let config: Config = "[[devices]]\nid = \"5\"\nkind = \"rgb_light\"\npath = \"/house/eg/kitchen/lamp\"".parse().unwrap();
+    assert_eq!(config.devices[0].kind, Some(DeviceKind::RgbLight));
+    let error = "[[devices]]\nid = \"5\"\nkind = \"dimmer\"".parse::<Config>().unwrap_err();
+    assert!(error.contains("devices[0].path"), "{}", error);
+    let error = "[[devices]]\nid = \"5\"\nkind = \"dimmer\"\npath = \"/house/*\"".parse::<Config>().unwrap_err();
+    assert!(error.contains("devices[0].path"), "{}", error);
+    let error = "[[devices]]\nid = \"5\"\nkind = \"switch\"\npath = \"/a\"\n[[devices]]\nid = \"6\"\nkind = \"switch\"\npath = \"/a\"".parse::<Config>().unwrap_err();
+    assert!(error.contains("devices[1].path"), "{}", error);
+}
+
+#[test]
+fn test_parse_lake_and_api()
+{
+    let config: Config = r#"
+        [lake]
+        persistence_path = "/var/lib/homecentral/lake"
+        persisted_paths = ["/house/**"]
+        [api]
+        grpc_listen = "0.0.0.0:50052"
+        http_listen = "127.0.0.1:8080"
+    "#.parse().unwrap();
+    assert_eq!(config.lake.persistence_path.as_deref(), Some("/var/lib/homecentral/lake"));
+    assert_eq!(config.lake.save_interval_seconds, 60);
+    assert_eq!(config.api.http_listen.as_deref(), Some("127.0.0.1:8080"));
+    assert_eq!(config.api.mqtt_listen, None);
+
+    let error = "[api]\nhttp_listen = \"localhost\"".parse::<Config>().unwrap_err();
+    assert!(error.contains("api.http_listen"), "{}", error);
+    let error = "[api]\nhttp_listen = \"0.0.0.0:80\"\nmqtt_listen = \"0.0.0.0:80\"".parse::<Config>().unwrap_err();
+    assert!(error.contains("api.mqtt_listen"), "{}", error);
+    let error = "[lake]\npersisted_paths = [\"house\"]".parse::<Config>().unwrap_err();
+    assert!(error.contains("lake.persisted_paths[0]"), "{}", error);
+}
+
+#[test]
+fn test_parse_automations()
+{
+    let config: Config = r#"
+        [[automations]]
+        name = "door bell"
+        trigger = "/bus/ug/rx/5"
+        conditions = [
+            {path = "/house/mode", op = "ne", value = "night"},
+            {value = {pressed = true}},
+        ]
+        actions = [
+            {type = "publish", path = "/house/bell", value = true},
+            {type = "delay", milliseconds = 500},
+            {type = "transmit", device = "7", value = {on = true}},
+        ]
+    "#.parse().unwrap();
+    let automation = &config.automations[0];
+    assert!(automation.enabled);
+    assert_eq!(automation.actions.len(), 3);
+    assert_eq!(automation.actions[1], ActionConfig::Delay{milliseconds: 500});
+    assert_eq!(automation.conditions[0].op, ConditionOperator::Ne);
+    assert_eq!(automation.conditions[1].path, None);
+    assert_eq!(automation.conditions[1].op, ConditionOperator::Eq);
+
+    let error = "[[automations]]\nname = \"a\"\ntrigger = \"/a\"\nconditions = [{path = \"/b/**\", value = 1}]\nactions = []".parse::<Config>().unwrap_err();
+    assert!(error.contains("automations[0].conditions[0].path"), "{}", error);
+    for name in ["", "a/b", "*x", "**"]
+    {
+        let error = format!("[[automations]]\nname = \"{}\"\ntrigger = \"/a\"\nactions = []", name).parse::<Config>().unwrap_err();
+        assert!(error.contains("automations[0].name"), "{}", error);
+    }
+
+    let error = "[[automations]]\nname = \"a\"\ntrigger = \"/a\"\nactions = [{type = \"publish\", path = \"/b/*\", value = 1}]".parse::<Config>().unwrap_err();
+    assert!(error.contains("automations[0].actions[0].path"), "{}", error);
+    let error = "[[automations]]\nname = \"a\"\ntrigger = \"/a\"\nactions = [{type = \"blink\"}]".parse::<Config>().unwrap_err();
+    assert!(error.contains("blink"), "{}", error);
+    // toml reports the location of syntax errors
+    let error = "[[automations]]\nname = ".parse::<Config>().unwrap_err();
+    assert!(error.contains("line 2"), "{}", error);
+}
+
+#[test]
+fn test_parse_mqtt_bridge()
+{
+    let config: Config = r#"
+        [mqtt_bridge]
+        broker = "mqtt.local:1883"
+        [[mqtt_bridge.mappings]]
+        lake = "/house/**"
+        topic = "home"
+        [[mqtt_bridge.mappings]]
+        lake = "/sensors/*/temperature"
+        direction = "from_mqtt"
+        retain = false
+    "#.parse().unwrap();
+    let bridge = config.mqtt_bridge.unwrap();
+    assert_eq!(bridge.client_id, "homecentral");
+    assert_eq!(bridge.mappings[0].direction, MqttDirection::Both);
+    assert!(bridge.mappings[0].retain);
+    assert_eq!(bridge.mappings[1].direction, MqttDirection::FromMqtt);
+
+    let error = "[mqtt_bridge]\nbroker = \"mqtt.local\"\nmappings = []".parse::<Config>().unwrap_err();
+    assert!(error.contains("mqtt_bridge.broker"), "{}", error);
+    let error = "[mqtt_bridge]\nbroker = \"mqtt.local:1883\"\nmappings = [{lake = \"/a/**/b\"}]".parse::<Config>().unwrap_err();
+    assert!(error.contains("mqtt_bridge.mappings[0]"), "{}", error);
+}
+
+#[test]
+fn test_parse_scheduler()
+{
+    let config: Config = r#"
+        [scheduler]
+        latitude = 48.14
+        longitude = 11.58
+        [[scheduler.timers]]
+        name = "lights off"
+        cron = "0 23 * * *"
+        publish = [{path = "/house/eg/light", value = false}, {path = "/house/og/light", value = false}]
+        [[scheduler.timers]]
+        name = "shutters up"
+        sun = "sunrise"
+        offset_minutes = 30
+        publish = [{path = "/house/shutters", value = "up"}]
+        [[scheduler.timers]]
+        name = "christmas"
+        at = "2026-12-24T18:00:00"
+        publish = [{path = "/house/tree", value = true}]
+    "#.parse().unwrap();
+    let timers = &config.scheduler.timers;
+    assert_eq!(timers[0].publish.len(), 2);
+    assert_eq!(timers[1].sun, Some(SunEvent::Sunrise));
+    assert_eq!(timers[1].offset_minutes, 30);
+    assert_eq!(timers[2].at.as_deref(), Some("2026-12-24T18:00:00"));
+
+    let error = "[[scheduler.timers]]\nname = \"t\"\ncron = \"0 23 * *\"\npublish = []".parse::<Config>().unwrap_err();
+    assert!(error.contains("scheduler.timers[0].cron"), "{}", error);
+    let error = "[[scheduler.timers]]\nname = \"t\"\nat = \"tomorrow\"\npublish = []".parse::<Config>().unwrap_err();
+    assert!(error.contains("scheduler.timers[0].at"), "{}", error);
+    let error = "[[scheduler.timers]]\nname = \"t\"\nsun = \"sunset\"\npublish = []".parse::<Config>().unwrap_err();
+    assert!(error.contains("scheduler.latitude"), "{}", error);
+    let error = "[[scheduler.timers]]\nname = \"t\"\ncron = \"0 23 * * *\"\nat = \"2026-12-24T18:00:00\"\npublish = []".parse::<Config>().unwrap_err();
+    assert!(error.contains("exactly one"), "{}", error);
+    let error = "[[scheduler.timers]]\nname = \"t\"\ncron = \"0 23 * * *\"\noffset_minutes = 5\npublish = []".parse::<Config>().unwrap_err();
+    assert!(error.contains("offset_minutes"), "{}", error);
+    let error = "[[scheduler.timers]]\nname = \"t\"\ncron = \"0 23 * * *\"\npublish = [{path = \"/house/*\", value = 1}]".parse::<Config>().unwrap_err();
+    assert!(error.contains("scheduler.timers[0].publish[0].path"), "{}", error);
+    for name in ["", "a/b", "*x", "**"]
+    {
+        let error = format!("[[scheduler.timers]]\nname = \"{}\"\ncron = \"0 23 * * *\"\npublish = []", name).parse::<Config>().unwrap_err();
+        assert!(error.contains("scheduler.timers[0].name"), "{}", error);
+    }
+    let error = "[scheduler]\nlatitude = 91".parse::<Config>().unwrap_err();
+    assert!(error.contains("scheduler.latitude"), "{}", error);
+}
+
+#[test]
+fn test_parse_scripting()
+{
+    let config: Config = "[scripting]\ndirectory = \"scripts\"".parse().unwrap();
+    let scripting = config.scripting.unwrap();
+    assert_eq!(scripting.directory, "scripts");
+    assert_eq!(scripting.max_operations, 1_000_000);
+    assert_eq!(scripting.timeout_milliseconds, 1000);
+    assert_eq!("".parse::<Config>().unwrap().scripting, None);
+
+    let error = "[scripting]\ndirectory = \"scripts\"\ntimeout_milliseconds = 0".parse::<Config>().unwrap_err();
+    assert!(error.contains("scripting.timeout_milliseconds"), "{}", error);
+}
+
+#[test]
+fn test_parse_scenes()
+{
+    let config: Config = r#"
+        [[scenes]]
+        name = "all off"
+        targets = [{path = "/house/eg/light", value = false}, {path = "/house/og/light", value = false}]
+    "#.parse().unwrap();
+    assert_eq!(config.scenes[0].name, "all off");
+    assert_eq!(config.scenes[0].targets[1].path, "/house/og/light");
+
+    let error = "[[scenes]]\nname = \"a/b\"\ntargets = []".parse::<Config>().unwrap_err();
+    assert!(error.contains("scenes[0].name"), "{}", error);
+    let error = "[[scenes]]\nname = \"*\"\ntargets = []".parse::<Config>().unwrap_err();
+    assert!(error.contains("scenes[0].name"), "{}", error);
+    let error = "[[scenes]]\nname = \"a\"\ntargets = []\n[[scenes]]\nname = \"a\"\ntargets = []".parse::<Config>().unwrap_err();
+    assert!(error.contains("scenes[1].name"), "{}", error);
+    let error = "[[scenes]]\nname = \"a\"\ntargets = [{path = \"/house/**\", value = 0}]".parse::<Config>().unwrap_err();
+    assert!(error.contains("scenes[0].targets[0].path"), "{}", error);
+}
+
+#[test]
+fn test_parse_aliases()
+{
+    let config: Config = r#"
+        [[aliases]]
+        path = "/house/kg/workshop/light"
+        target = "/bus/ug/rx/5"
+    "#.parse().unwrap();
+    assert_eq!(config.aliases[0].target, "/bus/ug/rx/5");
+
+    let error = "[[aliases]]
+path = \"/house/*\"
+target = \"/bus\"".parse::<Config>().unwrap_err();
+    assert!(error.contains("aliases[0]"), "{}", error);
+    let error = "[[aliases]]
+path = \"/a\"
+target = \"/b\"
+[[aliases]]
+path = \"/b\"
+target = \"/a/c\"".parse::<Config>().unwrap_err();
+    assert!(error.contains("aliases[1]"), "{}", error);
+}
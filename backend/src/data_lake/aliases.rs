@@ -0,0 +1,125 @@
+use super::path_tree::Path;
+
+/// Upper bound of aliases resolved in a row and of alternative paths of a single path,
+/// guards against cycles.
+const MAX_DEPTH: usize = 10;
+
+/// Alternative names of lake paths, see `TDataLake::add_alias`.
+/// An alias stands for its target and everything below it, e.g. with the alias
+/// /house/kg -> /bus/kg/rx the path /house/kg/5 is /bus/kg/rx/5.
+#[derive(Default, Debug, Clone)]
+pub struct Aliases
+{
+    /// (alias, target), aliases do not overlap
+    aliases: Vec<(Path, Path)>,
+}
+
+impl Aliases
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    /// Fails for wildcards, for aliases overlapping existing ones and for cycles.
+    pub fn add(self: &mut Self, alias: &Path, target: &Path) -> Result<(), String>
+    {
+        if alias.has_wildcards() || target.has_wildcards()
+        {
+            return Err(format!("alias '{}' -> '{}': wildcards are not allowed", alias, target));
+        }
+        if alias.elements().len() < 2
+        {
+            return Err("the root cannot be an alias".into());
+        }
+        if let Some((existing, _)) = self.aliases.iter().find(|(existing, _)| alias.starts_with(existing) || existing.starts_with(alias))
+        {
+            return Err(format!("alias '{}' overlaps alias '{}'", alias, existing));
+        }
+        let resolved = self.resolve(target);
+        if resolved.starts_with(alias)
+        {
+            return Err(format!("alias '{}' -> '{}' refers to itself via '{}'", alias, target, resolved));
+        }
+        self.aliases.push((alias.clone(), target.clone()));
+        Ok(())
+    }
+
+    /// Returns false if there was no such alias.
+    pub fn remove(self: &mut Self, alias: &Path) -> bool
+    {
+        let count = self.aliases.len();
+        self.aliases.retain(|(existing, _)| existing != alias);
+        self.aliases.len() != count
+    }
+
+    pub fn is_empty(self: &Self) -> bool
+    {
+        self.aliases.is_empty()
+    }
+
+    /// The path with all aliases replaced by their targets.
+    pub fn resolve(self: &Self, path: &Path) -> Path
+    {
+        let mut path = path.clone();
+        for _ in 0..MAX_DEPTH
+        {
+            match self.aliases.iter().find_map(|(alias, target)| path.replace_prefix(alias, target))
+            {
+                Some(resolved) => path = resolved,
+                None => break,
+            }
+        }
+        path
+    }
+
+    /// The resolved path followed by all other names of it.
+    pub fn names(self: &Self, resolved: &Path) -> Vec<Path>
+    {
+        let mut names = vec![resolved.clone()];
+        let mut i = 0;
+        while i < names.len() && names.len() < MAX_DEPTH
+        {
+            for (alias, target) in self.aliases.iter()
+            {
+                if let Some(name) = names[i].replace_prefix(target, alias)
+                {
+                    if !names.contains(&name)
+                    {
+                        names.push(name);
+                    }
+                }
+            }
+            i += 1;
+        }
+        names
+    }
+}
+
+#[test]
+fn test_aliases()
+{
+    let path = |s: &str| s.parse::<Path>().unwrap();
+    let mut aliases = Aliases::new();
+    aliases.add(&path("/house/kg/workshop/light"), &path("/bus/ug/rx/5")).unwrap();
+    aliases.add(&path("/house/kg"), &path("/bus/kg")).unwrap_err();
+    aliases.add(&path("/house/eg"), &path("/bus/eg/rx")).unwrap();
+    // chained
+    aliases.add(&path("/rooms/kitchen"), &path("/house/eg/3")).unwrap();
+
+    assert_eq!(aliases.resolve(&path("/house/kg/workshop/light")), path("/bus/ug/rx/5"));
+    assert_eq!(aliases.resolve(&path("/house/eg/3/on")), path("/bus/eg/rx/3/on"));
+    assert_eq!(aliases.resolve(&path("/rooms/kitchen/on")), path("/bus/eg/rx/3/on"));
+    assert_eq!(aliases.resolve(&path("/house/og")), path("/house/og"));
+
+    assert_eq!(aliases.names(&path("/bus/eg/rx/3/on")), vec![path("/bus/eg/rx/3/on"), path("/house/eg/3/on"), path("/rooms/kitchen/on")]);
+    assert_eq!(aliases.names(&path("/bus/ug/rx/5")), vec![path("/bus/ug/rx/5"), path("/house/kg/workshop/light")]);
+
+    assert!(aliases.add(&path("/bus/eg/rx/3/x"), &path("/rooms/kitchen/x/y")).is_err());
+    assert!(aliases.add(&path("/a/*"), &path("/b")).is_err());
+    assert!(aliases.add(&path("/"), &path("/b")).is_err());
+
+    assert!(aliases.remove(&path("/house/eg")));
+    assert!(!aliases.remove(&path("/house/eg")));
+    assert_eq!(aliases.resolve(&path("/rooms/kitchen/on")), path("/house/eg/3/on"));
+}
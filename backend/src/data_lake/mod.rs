@@ -4,20 +4,154 @@ use std::collections::HashMap;
 use tokio::sync::RwLock;
 use std::sync::Arc;
 
+pub mod aliases;
+    use aliases::Aliases;
 pub mod path_tree;
     use path_tree::*;
+pub mod permissions;
+    use permissions::Permissions;
+pub mod request;
+    pub use request::Request;
+pub mod value;
+    pub use value::Value;
 
+/// Handle of the lake, cheap to clone. Handles returned by `scoped` are views of a subtree,
+/// handles returned by `with_permissions` are limited to what a client may access.
 #[derive(Clone)]
 pub struct TDataLake
 {
-    lake : Arc<RwLock<DataLake>>
+    lake : Arc<RwLock<DataLake>>,
+    // NOTE: shared with the lake, so aliases can be changed without awaiting the lake
+    aliases: Arc<std::sync::RwLock<Aliases>>,
+    scope: Option<Scope>,
+    access: Vec<Access>,
 }
 
 impl TDataLake
 {
     pub fn new() -> Self
     {
-        TDataLake{lake: Arc::new(RwLock::new(DataLake::new()))}
+        let lake = DataLake::new();
+        let aliases = lake.aliases.clone();
+        TDataLake{lake: Arc::new(RwLock::new(lake)), aliases, scope: None, access: Vec::new()}
+    }
+
+    /// View of the subtree below prefix: paths passed to and returned by the view are relative
+    /// to prefix, e.g. /rx/5 of a view of /bus/ug is /bus/ug/rx/5. A restricted view cannot reach
+    /// outside the subtree through aliases either: such publishes are dropped, such values are
+    /// neither received nor returned. A view of a restricted view is restricted.
+    pub fn scoped(self: &Self, prefix: &Path, restricted: bool) -> Result<TDataLake, String>
+    {
+        if prefix.has_wildcards()
+        {
+            return Err(format!("scope '{}': wildcards are not allowed", prefix));
+        }
+        let scope = match &self.scope
+        {
+            Some(scope) => Scope{prefix: scope.prefix.join(prefix), restricted: scope.restricted || restricted},
+            None => Scope{prefix: prefix.clone(), restricted},
+        };
+        Ok(TDataLake{scope: Some(scope), ..self.clone()})
+    }
+
+    /// The lake as seen by a client: publishing to paths the permissions do not allow is dropped,
+    /// values of paths they do not allow to receive are neither received nor returned.
+    /// Denials are logged with the name of the client. Views of the returned handle keep the
+    /// permissions, further permissions narrow them.
+    pub fn with_permissions(self: &Self, client: &str, permissions: Arc<Permissions>) -> TDataLake
+    {
+        let mut view = self.clone();
+        view.access.push(Access{client: client.to_string(), permissions});
+        view
+    }
+
+    /// False, and logged, if publishing to the path would be denied.
+    pub fn may_publish(self: &Self, path: &Path) -> bool
+    {
+        self.allows_publishing(&self.absolute(path))
+    }
+
+    /// False, and logged, if no value matching the pattern could be received.
+    pub fn may_subscribe(self: &Self, pattern: &Path) -> bool
+    {
+        self.allows_subscribing(&self.absolute(pattern))
+    }
+
+    fn absolute(self: &Self, path: &Path) -> Path
+    {
+        match &self.scope
+        {
+            Some(scope) => scope.prefix.join(path),
+            None => path.clone(),
+        }
+    }
+
+    fn relative(self: &Self, path: Path) -> Path
+    {
+        match &self.scope
+        {
+            Some(scope) => scope.relative(path),
+            None => path,
+        }
+    }
+
+    /// The subtree a restricted view is limited to, after resolving aliases.
+    fn restricted_to(self: &Self) -> Option<Path>
+    {
+        match &self.scope
+        {
+            Some(scope) if scope.restricted => Some(self.aliases.read().unwrap().resolve(&scope.prefix)),
+            _ => None,
+        }
+    }
+
+    /// False if a restricted view must not reach the (absolute) path.
+    fn reaches(self: &Self, path: &Path) -> bool
+    {
+        match self.restricted_to()
+        {
+            Some(subtree) => self.aliases.read().unwrap().resolve(path).starts_with(&subtree),
+            None => true,
+        }
+    }
+
+    /// True if the view may receive the value of the (absolute) path.
+    fn receives(self: &Self, path: &Path) -> bool
+    {
+        self.reaches(path) && self.access.iter().all(|access| access.permissions.may_receive(path))
+    }
+
+    /// Views with permissions or a restricted scope only publish to exact paths, a wildcard
+    /// path reaches subscribers of every path it matches.
+    fn allows_publishing(self: &Self, path: &Path) -> bool
+    {
+        let limited = !self.access.is_empty() || self.restricted_to().is_some();
+        if !(limited && path.has_wildcards()) && self.reaches(path) && self.access.iter().all(|access| access.permissions.may_publish(path))
+        {
+            return true;
+        }
+        self.log_denied("publishing to", path);
+        false
+    }
+
+    fn allows_subscribing(self: &Self, pattern: &Path) -> bool
+    {
+        if self.access.iter().all(|access| access.permissions.may_receive(pattern))
+        {
+            return true;
+        }
+        self.log_denied("subscribing to", pattern);
+        false
+    }
+
+    fn log_denied(self: &Self, action: &str, path: &Path)
+    {
+        let mut view: Vec<String> = self.access.iter().map(|access| format!("client '{}' of role '{}'", access.client, access.permissions.role())).collect();
+        if let Some(scope) = &self.scope
+        {
+            view.push(format!("view {}", scope.prefix));
+        }
+        println!("Lake: {} denied {} {}", view.join(", "), action, path);
     }
 
     pub async fn publish
@@ -26,27 +160,210 @@ impl TDataLake
     >
     (self: & Self, path: &path_tree::Path, object: T)
     {
+        let path = self.absolute(path);
+        if !self.allows_publishing(&path)
+        {
+            return;
+        }
         let lake = self.lake.read().await;
-        lake.publish(path, object).await
+        lake.publish(&path, object).await
+    }
+
+    /// Like `publish`, but the lake additionally keeps the object as current value of the path,
+    /// see `get()`. Nothing is kept if path contains wildcards.
+    pub async fn publish_retained
+    <
+    T : 'static + Clone + std::fmt::Debug + Send + Sync
+    >
+    (self: & Self, path: &path_tree::Path, object: T)
+    {
+        let path = self.absolute(path);
+        if !self.allows_publishing(&path)
+        {
+            return;
+        }
+        let lake = self.lake.read().await;
+        lake.retain(&path, object.clone());
+        lake.publish(&path, object).await
+    }
+
+    /// Forgets the value of type T kept at path by `publish_retained`, e.g. of a device that
+    /// moved. Subscribers are not notified.
+    pub async fn clear_retained<T: 'static>(self: &Self, path: &Path)
+    {
+        let path = self.absolute(path);
+        if !self.allows_publishing(&path)
+        {
+            return;
+        }
+        let lake = self.lake.read().await;
+        lake.clear_retained::<T>(&path);
+    }
+
+    /// Makes alias another name of target and everything below it, e.g. /house/kg/light for
+    /// /bus/ug/rx/5. Publishing to and getting an alias path is publishing to and getting the
+    /// target path, subscribers of either (or of wildcards matching either) receive what is
+    /// published to both, each with the path it subscribed to.
+    pub fn add_alias(self: &Self, alias: &Path, target: &Path) -> Result<(), String>
+    {
+        self.aliases.write().unwrap().add(&self.absolute(alias), &self.absolute(target))
+    }
+
+    /// Returns false if there was no such alias.
+    pub fn remove_alias(self: &Self, alias: &Path) -> bool
+    {
+        self.aliases.write().unwrap().remove(&self.absolute(alias))
+    }
+
+    /// Current value of type T at exactly this path, as published by `publish_retained`.
+    pub async fn get<T: 'static + Clone>(self: &Self, path: &Path) -> Option<T>
+    {
+        self.get_matching::<T>(path).await.into_iter().find(|(retained_path, _)| retained_path == path).map(|(_, object)| object)
+    }
+
+    /// Current values of type T of all paths matching the given (wildcard) path.
+    pub async fn get_matching<T: 'static + Clone>(self: &Self, path: &Path) -> Vec<(Path, T)>
+    {
+        let lake = self.lake.read().await;
+        let matching = lake.get_matching(&self.absolute(path));
+        if self.scope.is_none() && self.access.is_empty()
+        {
+            return matching;
+        }
+        matching.into_iter()
+            .filter(|(path, _)| self.receives(path))
+            .map(|(path, object)| (self.relative(path), object))
+            .collect()
+    }
+
+    /// Direct children of path which have a current value of any type at or below them,
+    /// sorted, each with a flag whether the child has children itself.
+    pub async fn browse(self: &Self, path: &Path) -> Vec<(Path, bool)>
+    {
+        let lake = self.lake.read().await;
+        let below: Path = "/**".parse().unwrap();
+        lake.browse(&self.absolute(path), self.restricted_to().as_ref()).into_iter()
+            .filter(|(child, _)| self.access.iter().all(|access| access.permissions.may_receive(&child.join(&below))))
+            .map(|(child, has_children)| (self.relative(child), has_children))
+            .collect()
+    }
+
+    /// Debug representation of all current values of all types, sorted by path.
+    /// Views return the values below their prefix only.
+    pub async fn snapshot(self: &Self) -> Vec<(Path, String)>
+    {
+        let lake = self.lake.read().await;
+        let mut snapshot = lake.snapshot();
+        snapshot.retain(|(path, _)| self.access.iter().all(|access| access.permissions.may_receive(path)));
+        match &self.scope
+        {
+            Some(scope) =>
+            {
+                let prefix = self.aliases.read().unwrap().resolve(&scope.prefix);
+                snapshot.into_iter().filter_map(|(path, value)| Some((path.strip_prefix(&prefix)?, value))).collect()
+            }
+            None => snapshot,
+        }
     }
 
     pub async fn subscribe<T: 'static + Send + Sync>(self: &mut Self, path: &Path) -> Fisher<T>
     {
+        let path = self.absolute(path);
+        // a denied subscription is kept, it never receives anything
+        self.allows_subscribing(&path);
         let mut lake = self.lake.write().await;
-        lake.subscribe(path)
+        lake.subscribe_scoped(&path, self.scope.clone(), self.access.clone())
+    }
+
+    /// Like `subscribe`, but every received object comes with the concrete path it was published to.
+    /// Useful for wildcard subscriptions.
+    pub async fn subscribe_with_path<T: 'static + Send + Sync>(self: &mut Self, path: &Path) -> Fisher<(Path, T)>
+    {
+        let path = self.absolute(path);
+        // a denied subscription is kept, it never receives anything
+        self.allows_subscribing(&path);
+        let mut lake = self.lake.write().await;
+        lake.subscribe_with_path_scoped(&path, self.scope.clone(), self.access.clone())
+    }
+
+    /// Publishes a `Request` carrying `query` and waits for the first subscriber to reply.
+    /// Returns None if nobody replied (e.g. no subscriber of type `Request<Q, R>` at path).
+    pub async fn request
+    <
+    Q : 'static + Clone + Send + Sync + std::fmt::Debug,
+    R : 'static + Send
+    >
+    (self: & Self, path: &path_tree::Path, query: Q) -> Option<R>
+    {
+        let (request, reply_receiver) = Request::new(query);
+        self.publish(path, request).await;
+        reply_receiver.await.ok()
     }
 
 }
 
+/// Subtree of a view, see `TDataLake::scoped`.
+#[derive(Clone, Debug)]
+struct Scope
+{
+    prefix: Path,
+    restricted: bool,
+}
+
+/// Permissions of a client, see `TDataLake::with_permissions`.
+#[derive(Clone, Debug)]
+struct Access
+{
+    client: String,
+    permissions: Arc<Permissions>,
+}
+
+impl Scope
+{
+    fn relative(self: &Self, path: Path) -> Path
+    {
+        path.strip_prefix(&self.prefix).unwrap_or(path)
+    }
+}
+
 struct DataLake 
 {
     subscriptions: HashMap<TypeId, path_tree::PathTree<Subscriber>>,
+    // NOTE: own lock, so retaining only needs read access to the lake, same as publishing
+    retained: std::sync::Mutex<HashMap<TypeId, path_tree::PathTree<Retained>>>,
+    // NOTE: values are retained and subscribers are reached under the resolved paths only
+    aliases: Arc<std::sync::RwLock<Aliases>>,
+}
+
+trait RetainedValue: Any + Send + Sync + std::fmt::Debug
+{
+    fn as_any(self: &Self) -> &dyn Any;
+}
+
+impl<T: Any + Send + Sync + std::fmt::Debug> RetainedValue for T
+{
+    fn as_any(self: &Self) -> &dyn Any
+    {
+        self
+    }
+}
+
+struct Retained
+{
+    path: Path,
+    value: Box<dyn RetainedValue>,
 }
 
 #[derive(Debug)]
 struct Subscriber
 {
     transmitter: Box<dyn Any + Send + Sync>,
+    // transmitter sends (Path, T) instead of T
+    with_path: bool,
+    // subscribed through a view, paths are sent relative to it
+    scope: Option<Scope>,
+    // subscribed with permissions, only permitted values are sent
+    access: Vec<Access>,
 }
 
 pub struct Fisher<T>
@@ -67,7 +384,95 @@ impl DataLake
 
     fn new() -> Self
     {
-        DataLake{subscriptions: HashMap::new()}
+        DataLake{subscriptions: HashMap::new(), retained: std::sync::Mutex::new(HashMap::new()), aliases: Arc::new(std::sync::RwLock::new(Aliases::new()))}
+    }
+
+    fn retain<T: 'static + Send + Sync + std::fmt::Debug>(self: &Self, path: &Path, object: T)
+    {
+        if path.has_wildcards()
+        {
+            return;
+        }
+        let path = self.aliases.read().unwrap().resolve(path);
+        self.retained.lock().unwrap()
+            .entry(TypeId::of::<T>())
+            .or_insert(path_tree::PathTree::<Retained>::new())
+            .replace_payloads(&path, Retained{path: path.clone(), value: Box::new(object)});
+    }
+
+    fn clear_retained<T: 'static>(self: &Self, path: &Path)
+    {
+        let path = self.aliases.read().unwrap().resolve(path);
+        if let Some(tree) = self.retained.lock().unwrap().get_mut(&TypeId::of::<T>())
+        {
+            tree.retain_payloads(&mut |entry| entry.path != path);
+        }
+    }
+
+    fn get_matching<T: 'static + Clone>(self: &Self, path: &Path) -> Vec<(Path, T)>
+    {
+        let retained = self.retained.lock().unwrap();
+        let tree = match retained.get(&TypeId::of::<T>())
+        {
+            Some(tree) => tree,
+            None => return Vec::new()
+        };
+        let aliases = self.aliases.read().unwrap();
+        if aliases.is_empty()
+        {
+            return tree.get_payloads(path).into_iter()
+                .filter_map(|entry| entry.value.as_ref().as_any().downcast_ref::<T>().map(|object| (entry.path.clone(), object.clone())))
+                .collect();
+        }
+        // every name of every value may match
+        let mut pattern = PathTree::new();
+        pattern.add_payload(path, ());
+        tree.all_payloads().into_iter()
+            .filter_map(|entry| entry.value.as_ref().as_any().downcast_ref::<T>().map(|object| (&entry.path, object)))
+            .flat_map(|(retained_path, object)| aliases.names(retained_path).into_iter()
+                .filter(|name| !pattern.get_payloads(name).is_empty())
+                .map(|name| (name, object.clone()))
+                .collect::<Vec<_>>())
+            .collect()
+    }
+
+    /// Values outside of `within` (if given) are skipped, also if they have a name within path.
+    fn browse(self: &Self, path: &Path, within: Option<&Path>) -> Vec<(Path, bool)>
+    {
+        let retained = self.retained.lock().unwrap();
+        let aliases = self.aliases.read().unwrap();
+        let depth = path.elements().len();
+        let mut children: Vec<(Path, bool)> = Vec::new();
+        for name in retained.values().flat_map(|tree| tree.all_payloads())
+            .filter(|entry| within.is_none_or(|within| entry.path.starts_with(within)))
+            .flat_map(|entry| aliases.names(&entry.path))
+        {
+            let elements = name.elements();
+            if elements.len() <= depth || !elements.starts_with(path.elements())
+            {
+                continue;
+            }
+            let child = Path::from(&elements[..=depth]);
+            let has_children = elements.len() > depth + 1;
+            match children.iter_mut().find(|(known, _)| *known == child)
+            {
+                Some((_, known_has_children)) => *known_has_children |= has_children,
+                None => children.push((child, has_children)),
+            }
+        }
+        children.sort_by_key(|(child, _)| child.to_string());
+        children
+    }
+
+    fn snapshot(self: &Self) -> Vec<(Path, String)>
+    {
+        let retained = self.retained.lock().unwrap();
+        let mut result: Vec<(Path, String)> = retained.values()
+            .flat_map(|tree| tree.all_payloads())
+            .map(|entry| (entry.path.clone(), format!("{:?}", entry.value)))
+            .collect();
+        result.sort_by_key(|(path, _)| path.to_string());
+        result
     }
 
     async fn publish
@@ -84,15 +489,60 @@ impl DataLake
         {
             Some(possible_subscribers) =>
             {
-                for subscriber in
-                possible_subscribers.get_payloads(path)
+                // subscribers matching several names of the path receive the object once, with the first name matched
+                let reached: Vec<(&Subscriber, Path)> = {
+                    let aliases = self.aliases.read().unwrap();
+                    let resolved = aliases.resolve(path);
+                    let mut reached: Vec<(&Subscriber, Path)> = Vec::new();
+                    for name in aliases.names(&resolved)
+                    {
+                        for subscriber in possible_subscribers.get_payloads(&name)
+                        {
+                            if reached.iter().any(|(known, _)| std::ptr::eq(*known, subscriber))
+                            {
+                                continue;
+                            }
+                            // restricted views only receive what is published within their subtree
+                            if subscriber.scope.as_ref().is_some_and(|scope| scope.restricted && !resolved.starts_with(&aliases.resolve(&scope.prefix)))
+                            {
+                                continue;
+                            }
+                            // permissions refer to the name subscribed to
+                            if !subscriber.access.iter().all(|access| access.permissions.may_receive(&name))
+                            {
+                                continue;
+                            }
+                            let name = match &subscriber.scope
+                            {
+                                Some(scope) => scope.relative(name.clone()),
+                                None => name.clone(),
+                            };
+                            reached.push((subscriber, name));
+                        }
+                    }
+                    reached
+                };
+                for (subscriber, path) in reached
                 {
-                    let sender = match subscriber.transmitter.downcast_ref::<tokio::sync::mpsc::Sender<T>>()
+                    // NOTE: send only fails if the Fisher was dropped, i.e. nobody is interested anymore
+                    if subscriber.with_path
                     {
-                        Some(boxed_sender) => boxed_sender,
-                        None => panic!("Publish and subscribe types do not match! This should not happen and is a programming error in the pubsub lib." )
-                    };
-                    sender.send((*boxed_object).clone()).await.unwrap(); // FIXME: handle error here (receiver dropped)
+                        let sender = match subscriber.transmitter.downcast_ref::<tokio::sync::mpsc::Sender<(Path, T)>>()
+                        {
+                            Some(boxed_sender) => boxed_sender,
+                            None => panic!("Publish and subscribe types do not match! This should not happen and is a programming error in the pubsub lib." )
+                        };
+                        let _ = sender.send((path, (*boxed_object).clone())).await;
+                    }
+                    else
+                    {
+                        let sender = match subscriber.transmitter.downcast_ref::<tokio::sync::mpsc::Sender<T>>()
+                        {
+                            Some(boxed_sender) => boxed_sender,
+                            None => panic!("Publish and subscribe types do not match! This should not happen and is a programming error in the pubsub lib." )
+                        };
+                        let _ = sender.send((*boxed_object).clone()).await;
+                    }
                 }
             }
             None => return
@@ -105,8 +555,34 @@ impl DataLake
         self.subscribe(&path.as_ref().parse().unwrap())
     }
 
+    // Subscribers are not removed when their fisher is dropped, clean up whenever a new one of the same type comes in
+    fn prune_closed_subscribers<T: 'static + Send>(self: &mut Self)
+    {
+        if let Some(tree) = self.subscriptions.get_mut(&TypeId::of::<T>())
+        {
+            tree.retain_payloads(&mut |subscriber| match subscriber.with_path
+            {
+                true => subscriber.transmitter.downcast_ref::<tokio::sync::mpsc::Sender<(Path, T)>>().is_none_or(|tx| !tx.is_closed()),
+                false => subscriber.transmitter.downcast_ref::<tokio::sync::mpsc::Sender<T>>().is_none_or(|tx| !tx.is_closed()),
+            });
+        }
+    }
+
     fn subscribe<T: 'static + Send>(self: &mut Self, path: &Path) -> Fisher<T>
     {
+        self.subscribe_scoped(path, None, Vec::new())
+    }
+
+    #[allow(dead_code)] // only used by tests so far
+    fn subscribe_with_path<T: 'static + Send>(self: &mut Self, path: &Path) -> Fisher<(Path, T)>
+    {
+        self.subscribe_with_path_scoped(path, None, Vec::new())
+    }
+
+    /// Subscription of a view, see `TDataLake::scoped` and `TDataLake::with_permissions`.
+    fn subscribe_scoped<T: 'static + Send>(self: &mut Self, path: &Path, scope: Option<Scope>, access: Vec<Access>) -> Fisher<T>
+    {
+        self.prune_closed_subscribers::<T>();
         let type_id = TypeId::of::<T>();
 
         let (tx, rx) = tokio::sync::mpsc::channel::<T>(10); // Buffer of hard coded size for now, if more elements queued, backpressure active i.e. send() will block
@@ -115,7 +591,25 @@ impl DataLake
             .or_insert(path_tree::PathTree::<Subscriber>::new())
             .add_payload(
                 path, 
-                Subscriber{transmitter : Box::new(tx)}
+                Subscriber{transmitter : Box::new(tx), with_path: false, scope, access}
+             );
+
+        Fisher{receiver: rx}
+    }
+
+    fn subscribe_with_path_scoped<T: 'static + Send>(self: &mut Self, path: &Path, scope: Option<Scope>, access: Vec<Access>) -> Fisher<(Path, T)>
+    {
+        self.prune_closed_subscribers::<T>();
+        // registered under the type id of T, so publishers of T reach this subscriber:
+        let type_id = TypeId::of::<T>();
+
+        let (tx, rx) = tokio::sync::mpsc::channel::<(Path, T)>(10);
+        self.subscriptions
+            .entry(type_id)
+            .or_insert(path_tree::PathTree::<Subscriber>::new())
+            .add_payload(
+                path,
+                Subscriber{transmitter : Box::new(tx), with_path: true, scope, access}
              );
 
         Fisher{receiver: rx}
@@ -165,6 +659,7 @@ async fn multi_task_publish_subscribe()
     assert!(false);
 
 }
+
 #[tokio::test]
 async fn single_publish_multi_subscribe()
 {
@@ -191,3 +686,202 @@ async fn single_publish_multi_subscribe()
         Err(e) => panic!("rx failed: {}", e)
     }
 }
+
+#[tokio::test]
+async fn subscribe_with_path()
+{
+    let mut datalake = DataLake::new();
+
+    let mut plain_fisher = datalake.subscribe::<u32>(&"/devices/*/temperature".parse().unwrap());
+    let mut path_fisher = datalake.subscribe_with_path::<u32>(&"/devices/*/temperature".parse().unwrap());
+
+    datalake.publish(&"/devices/5/temperature".parse().unwrap(), 21_u32).await;
+
+    assert_eq!(plain_fisher.receiver.try_recv().unwrap(), 21);
+    let (path, value) = path_fisher.receiver.try_recv().unwrap();
+    assert_eq!(path.to_string(), "/devices/5/temperature");
+    assert_eq!(value, 21);
+}
+
+#[tokio::test]
+async fn publish_to_dropped_fisher()
+{
+    let mut datalake = DataLake::new();
+
+    let fisher = datalake.subscribe::<u32>(&"/test".parse().unwrap());
+    drop(fisher);
+    datalake.publish(&"/test".parse().unwrap(), 1_u32).await;
+}
+
+#[tokio::test]
+async fn dropped_fishers_are_pruned()
+{
+    let mut datalake = DataLake::new();
+    let path: Path = "/test".parse().unwrap();
+    for _ in 0..3
+    {
+        let _dropped = datalake.subscribe::<u32>(&path);
+        let _dropped_with_path = datalake.subscribe_with_path::<u32>(&path);
+    }
+    let mut fisher = datalake.subscribe::<u32>(&path);
+    assert_eq!(datalake.subscriptions[&TypeId::of::<u32>()].all_payloads().len(), 1);
+
+    datalake.publish(&path, 5_u32).await;
+    assert_eq!(fisher.receive().await, Some(5));
+}
+
+#[tokio::test]
+async fn retained_values()
+{
+    let datalake = TDataLake::new();
+    datalake.publish_retained(&"/house/eg/temperature".parse().unwrap(), 21.5_f64).await;
+    datalake.publish_retained(&"/house/og/temperature".parse().unwrap(), 19.0_f64).await;
+    datalake.publish_retained(&"/house/og/temperature".parse().unwrap(), 19.5_f64).await;
+    datalake.publish_retained(&"/house/og/name".parse().unwrap(), "upstairs".to_string()).await;
+    datalake.publish(&"/house/kg/temperature".parse().unwrap(), 12.0_f64).await;
+
+    assert_eq!(datalake.get::<f64>(&"/house/og/temperature".parse().unwrap()).await, Some(19.5));
+    assert_eq!(datalake.get::<f64>(&"/house/kg/temperature".parse().unwrap()).await, None);
+    assert_eq!(datalake.get::<String>(&"/house/og/temperature".parse().unwrap()).await, None);
+    assert_eq!(datalake.get::<f64>(&"/house/*/temperature".parse().unwrap()).await, None);
+
+    let mut matching = datalake.get_matching::<f64>(&"/house/*/temperature".parse().unwrap()).await;
+    matching.sort_by_key(|(path, _)| path.to_string());
+    assert_eq!(matching, vec![("/house/eg/temperature".parse().unwrap(), 21.5), ("/house/og/temperature".parse().unwrap(), 19.5)]);
+
+    let browsed = datalake.browse(&"/house".parse().unwrap()).await;
+    assert_eq!(browsed, vec![("/house/eg".parse().unwrap(), true), ("/house/og".parse().unwrap(), true)]);
+    let browsed = datalake.browse(&"/house/og".parse().unwrap()).await;
+    assert_eq!(browsed, vec![("/house/og/name".parse().unwrap(), false), ("/house/og/temperature".parse().unwrap(), false)]);
+    assert_eq!(datalake.browse(&"/".parse().unwrap()).await, vec![("/house".parse().unwrap(), true)]);
+
+    let snapshot = datalake.snapshot().await;
+    assert_eq!(snapshot.len(), 3);
+    assert_eq!(snapshot[1], ("/house/og/name".parse().unwrap(), "\"upstairs\"".to_string()));
+
+    datalake.clear_retained::<f64>(&"/house/og/temperature".parse().unwrap()).await;
+    assert_eq!(datalake.get::<f64>(&"/house/og/temperature".parse().unwrap()).await, None);
+    assert_eq!(datalake.get::<String>(&"/house/og/name".parse().unwrap()).await, Some("upstairs".to_string()));
+    assert_eq!(datalake.snapshot().await.len(), 2);
+}
+
+#[tokio::test]
+async fn aliases()
+{
+    let mut datalake = TDataLake::new();
+    datalake.add_alias(&"/house/kg/light".parse().unwrap(), &"/bus/ug/rx/5".parse().unwrap()).unwrap();
+    datalake.add_alias(&"/house/eg".parse().unwrap(), &"/bus/eg/rx".parse().unwrap()).unwrap();
+    assert!(datalake.add_alias(&"/bus/ug/rx/5/x".parse().unwrap(), &"/house/kg/light/x".parse().unwrap()).is_err());
+
+    let mut target = datalake.subscribe::<i32>(&"/bus/ug/rx/5".parse().unwrap()).await;
+    let mut house = datalake.subscribe_with_path::<i32>(&"/house/**".parse().unwrap()).await;
+    let mut everything = datalake.subscribe_with_path::<i32>(&"/**".parse().unwrap()).await;
+
+    // via the alias
+    datalake.publish_retained(&"/house/kg/light".parse().unwrap(), 1).await;
+    assert_eq!(target.receive().await, Some(1));
+    assert_eq!(house.receive().await, Some(("/house/kg/light".parse().unwrap(), 1)));
+    // once, with the resolved path
+    assert_eq!(everything.receive().await, Some(("/bus/ug/rx/5".parse().unwrap(), 1)));
+
+    // to the target
+    datalake.publish(&"/bus/eg/rx/3".parse().unwrap(), 2).await;
+    assert_eq!(house.receive().await, Some(("/house/eg/3".parse().unwrap(), 2)));
+    assert_eq!(everything.receive().await, Some(("/bus/eg/rx/3".parse().unwrap(), 2)));
+    assert!(everything.receiver.try_recv().is_err());
+
+    assert_eq!(datalake.get::<i32>(&"/house/kg/light".parse().unwrap()).await, Some(1));
+    assert_eq!(datalake.get::<i32>(&"/bus/ug/rx/5".parse().unwrap()).await, Some(1));
+    assert_eq!(datalake.get_matching::<i32>(&"/house/**".parse().unwrap()).await, vec![("/house/kg/light".parse().unwrap(), 1)]);
+    assert_eq!(datalake.browse(&"/house".parse().unwrap()).await, vec![("/house/kg".parse().unwrap(), true)]);
+    assert_eq!(datalake.snapshot().await.len(), 1);
+
+    assert!(datalake.remove_alias(&"/house/kg/light".parse().unwrap()));
+    assert_eq!(datalake.get::<i32>(&"/house/kg/light".parse().unwrap()).await, None);
+}
+
+#[tokio::test]
+async fn scoped_views()
+{
+    let mut datalake = TDataLake::new();
+    let mut gateway = datalake.scoped(&"/bus/ug".parse().unwrap(), true).unwrap();
+    assert!(datalake.scoped(&"/bus/*".parse().unwrap(), false).is_err());
+    let mut tx = gateway.subscribe_with_path::<i32>(&"/tx".parse().unwrap()).await;
+    let mut all = datalake.subscribe_with_path::<i32>(&"/bus/**".parse().unwrap()).await;
+
+    datalake.publish(&"/bus/ug/tx".parse().unwrap(), 1).await;
+    assert_eq!(tx.receive().await, Some(("/tx".parse().unwrap(), 1)));
+    assert_eq!(all.receive().await, Some(("/bus/ug/tx".parse().unwrap(), 1)));
+    gateway.publish_retained(&"/rx/5".parse().unwrap(), 2).await;
+    assert_eq!(all.receive().await, Some(("/bus/ug/rx/5".parse().unwrap(), 2)));
+    assert_eq!(gateway.get::<i32>(&"/rx/5".parse().unwrap()).await, Some(2));
+    assert_eq!(datalake.get::<i32>(&"/bus/ug/rx/5".parse().unwrap()).await, Some(2));
+    assert_eq!(gateway.browse(&"/".parse().unwrap()).await, vec![("/rx".parse().unwrap(), true)]);
+    assert_eq!(gateway.snapshot().await, vec![("/rx/5".parse().unwrap(), "2".to_string())]);
+
+    // views of views nest
+    let rx = gateway.scoped(&"/rx".parse().unwrap(), false).unwrap();
+    assert_eq!(rx.get_matching::<i32>(&"/*".parse().unwrap()).await, vec![("/5".parse().unwrap(), 2)]);
+
+    // restricted views cannot leave their subtree through aliases, unrestricted ones can
+    datalake.publish_retained(&"/house/door".parse().unwrap(), 3).await;
+    datalake.add_alias(&"/bus/ug/rx/door".parse().unwrap(), &"/house/door".parse().unwrap()).unwrap();
+    assert_eq!(gateway.get::<i32>(&"/rx/door".parse().unwrap()).await, None);
+    gateway.publish(&"/rx/door".parse().unwrap(), 4).await;
+    assert_eq!(datalake.get::<i32>(&"/house/door".parse().unwrap()).await, Some(3));
+    let unrestricted = datalake.scoped(&"/bus/ug".parse().unwrap(), false).unwrap();
+    assert_eq!(unrestricted.get::<i32>(&"/rx/door".parse().unwrap()).await, Some(3));
+    let mut door = gateway.subscribe::<i32>(&"/rx/*".parse().unwrap()).await;
+    datalake.publish(&"/house/door".parse().unwrap(), 5).await;
+    datalake.publish(&"/bus/ug/rx/6".parse().unwrap(), 6).await;
+    assert_eq!(door.receive().await, Some(6));
+
+    // restricted views only publish to exact paths
+    gateway.publish(&"/rx/*".parse().unwrap(), 7).await;
+    datalake.publish(&"/bus/ug/rx/6".parse().unwrap(), 8).await;
+    assert_eq!(door.receive().await, Some(8));
+}
+
+#[tokio::test]
+async fn permissions()
+{
+    let path = |s: &str| s.parse::<Path>().unwrap();
+    let datalake = TDataLake::new();
+    let guest = Arc::new(Permissions::new("guest", &[path("/house/*/light")], &[path("/house/**")]));
+    let mut tablet = datalake.with_permissions("tablet", guest);
+
+    datalake.publish_retained(&path("/house/eg/door/open"), false).await;
+    datalake.publish_retained(&path("/bus/ug/rx/5"), false).await;
+    assert!(tablet.may_publish(&path("/house/eg/light")));
+    assert!(!tablet.may_publish(&path("/house/eg/door/open")));
+    tablet.publish_retained(&path("/house/eg/door/open"), true).await;
+    assert_eq!(datalake.get::<bool>(&path("/house/eg/door/open")).await, Some(false));
+    tablet.publish_retained(&path("/house/eg/light"), true).await;
+    assert_eq!(datalake.get::<bool>(&path("/house/eg/light")).await, Some(true));
+    // wildcards would reach paths the role may not publish to
+    assert!(!tablet.may_publish(&path("/house/*/*")));
+    let mut door = datalake.clone().subscribe::<bool>(&path("/house/eg/door/open")).await;
+    tablet.publish(&path("/house/**"), true).await;
+    datalake.publish(&path("/house/eg/door/open"), false).await;
+    assert_eq!(door.receive().await, Some(false));
+
+    assert_eq!(tablet.get::<bool>(&path("/bus/ug/rx/5")).await, None);
+    let mut values = tablet.get_matching::<bool>(&path("/**")).await;
+    values.sort_by_key(|(path, _)| path.to_string());
+    assert_eq!(values, vec![(path("/house/eg/door/open"), false), (path("/house/eg/light"), true)]);
+    assert_eq!(tablet.browse(&path("/")).await, vec![(path("/house"), true)]);
+    assert_eq!(tablet.snapshot().await.len(), 2);
+
+    assert!(!tablet.may_subscribe(&path("/bus/**")));
+    let mut everything = tablet.subscribe_with_path::<bool>(&path("/**")).await;
+    datalake.publish(&path("/bus/ug/rx/5"), true).await;
+    datalake.publish(&path("/house/og/light"), true).await;
+    assert_eq!(everything.receive().await, Some((path("/house/og/light"), true)));
+
+    // scoped views keep the permissions
+    let eg = tablet.scoped(&path("/house/eg"), false).unwrap();
+    eg.publish_retained(&path("/door/open"), true).await;
+    assert_eq!(datalake.get::<bool>(&path("/house/eg/door/open")).await, Some(false));
+    let outside = tablet.scoped(&path("/bus"), false).unwrap();
+    assert_eq!(outside.get::<bool>(&path("/ug/rx/5")).await, None);
+}
@@ -38,6 +38,7 @@ impl fmt::Display for PathElement
 
 /// A Path represents a selector on data to pubish and subscribe.
 /// It can be constructed from a &str.
+#[derive(PartialEq, Clone, Debug)]
 pub struct Path 
 {
     elements: Vec<PathElement>
@@ -54,6 +55,70 @@ impl fmt::Display for Path
     }
 }
 
+impl Path
+{
+    /// Elements of this path, absolute paths start with `Root`.
+    pub fn elements(self: &Self) -> &[PathElement]
+    {
+        &self.elements
+    }
+
+    pub fn has_wildcards(self: &Self) -> bool
+    {
+        self.elements.iter().any(|element| matches!(element, Wildcard(_)))
+    }
+
+    /// True if the elements of `prefix` are the first elements of this path, wildcards are
+    /// compared as elements, not expanded.
+    pub fn starts_with(self: &Self, prefix: &Path) -> bool
+    {
+        self.elements.starts_with(&prefix.elements)
+    }
+
+    /// This path with `prefix` replaced by `replacement`, None if it does not start with `prefix`.
+    pub fn replace_prefix(self: &Self, prefix: &Path, replacement: &Path) -> Option<Path>
+    {
+        let rest = self.elements.strip_prefix(prefix.elements.as_slice())?;
+        let mut elements = replacement.elements.clone();
+        elements.extend_from_slice(rest);
+        Some(Path{elements})
+    }
+
+    /// The rest of this path after `prefix` as absolute path, e.g. /rx/5 for /bus/ug/rx/5 and /bus/ug.
+    pub fn strip_prefix(self: &Self, prefix: &Path) -> Option<Path>
+    {
+        self.replace_prefix(prefix, &Path{elements: vec![Root]})
+    }
+
+    /// The (absolute) path `relative` appended to this one, the reverse of `strip_prefix`.
+    pub fn join(self: &Self, relative: &Path) -> Path
+    {
+        let mut elements = self.elements.clone();
+        elements.extend(relative.elements.iter().skip_while(|element| matches!(element, Root)).cloned());
+        Path{elements}
+    }
+
+    /// This path extended by `name`, which has to be a single `PathElement::Name`,
+    /// e.g. the name of an automation, below which its state is published.
+    pub fn child(self: &Self, name: &str) -> Result<Path, String>
+    {
+        if name.contains('/')
+        {
+            return Err(format!("'{}' must not contain '/'", name));
+        }
+        match name.parse::<PathElement>()?
+        {
+            Name(name) =>
+            {
+                let mut elements = self.elements.clone();
+                elements.push(Name(name));
+                Ok(Path{elements})
+            }
+            _ => Err(format!("'{}' must not be a wildcard", name)),
+        }
+    }
+}
+
 impl From<&[PathElement]> for Path {
     fn from(path_slice: &[PathElement]) -> Self {
         Path{elements: path_slice.into()}
@@ -257,17 +322,46 @@ impl<T> PathTree<T>
 
     pub fn add_payload(self: &mut Self, path: &Path, payload: T)
     {
-        self.add_payload_internal(& path.elements, payload)
+        self.node_mut(& path.elements).payloads.push(payload)
+    }
+
+    /// Replaces all payloads at exactly this path by `payload`.
+    /// NOTE: wildcards in path are not expanded, they address the wildcard node itself.
+    pub fn replace_payloads(self: &mut Self, path: &Path, payload: T)
+    {
+        let node = self.node_mut(& path.elements);
+        node.payloads.clear();
+        node.payloads.push(payload);
     }
 
-    fn add_payload_internal(self: &mut Self, path: &[PathElement], payload: T)
+    /// Removes all payloads for which `keep` returns false, regardless of their path.
+    pub fn retain_payloads<F: FnMut(&T) -> bool>(self: &mut Self, keep: &mut F)
+    {
+        self.payloads.retain(|payload| keep(payload));
+        for child in self.childs.iter_mut()
+        {
+            child.retain_payloads(keep);
+        }
+    }
+
+    /// All payloads of the tree, regardless of their path.
+    pub fn all_payloads(self: &Self) -> Vec<&T>
+    {
+        let mut result: Vec<&T> = self.payloads.iter().collect();
+        for child in self.childs.iter()
+        {
+            result.append(&mut child.all_payloads());
+        }
+        result
+    }
+
+    // returns the node at path, missing nodes are created
+    fn node_mut(self: &mut Self, path: &[PathElement]) -> &mut PathTree<T>
     {
         use PathElement::*;
         if path.is_empty()
         {
-            // add payload at current level
-            self.payloads.push(payload);
-            return;
+            return self;
         }
 
         if path[0] == Root
@@ -277,16 +371,13 @@ impl<T> PathTree<T>
                 panic!("Trying to add root element to tree. NOTE: Only Absolute paths may have a root element. And this is only allowed as first element.");
             }
             // we can only append childs, 
-            return self.add_payload_internal(&path[1..], payload);
+            return self.node_mut(&path[1..]);
         }
 
-        let existing_child =
-            self.childs.iter_mut().find(|x|
-                {x.element == path[0]}
-            );
-        let child: &mut PathTree<T>  = match existing_child
+        let existing_child = self.childs.iter().position(|x| x.element == path[0]);
+        let child_index = match existing_child
         {
-            Some( child) => child,
+            Some(index) => index,
             None => 
             {
                 self.childs.push(
@@ -296,17 +387,11 @@ impl<T> PathTree<T>
                         childs: Vec::new()
                     }
                     );
-                self.childs.last_mut().unwrap()
+                self.childs.len() - 1
             }
         };
 
-        if path.len() == 1
-        {
-            child.payloads.push(payload);
-            return;
-        }
-
-        return child.add_payload_internal(&path[1..], payload)
+        return self.childs[child_index].node_mut(&path[1..])
     }
 
     pub fn get_payloads<'tree, 'path>(
@@ -540,13 +625,7 @@ impl<T> PathTree<T>
             Some(wc_override) => wc_override,
             None => tree_wildcard.clone()
         };
-        if job.path.len() == 1 
-        {
-            // all matched and no more things to do for this path
-            // collect the reward:
-            results.append(&job.tree.payloads);
-            return;
-        }
+        // NOTE: check this before collecting, a fully consumed wildcard cannot match the remaining path
         if tree_wildcard.0 == 0 && tree_wildcard.1 == 0
         {
             // invalid wildcard
@@ -564,6 +643,16 @@ impl<T> PathTree<T>
             }
             return;
         }
+        if job.path.len() == 1 
+        {
+            if tree_wildcard.0 <= 1
+            {
+                // all matched and no more things to do for this path
+                // collect the reward:
+                results.append(&job.tree.payloads);
+            }
+            return;
+        }
 
         // no minumums required, so we might also skip wildcard here:
         if tree_wildcard.0 == 0
@@ -1065,6 +1154,76 @@ fn test_wildcard_in_tree()
     assert!(results.contains(&&"severything"));
 }
 
+#[test]
+fn test_single_wildcard_in_middle_of_tree()
+{
+    let mut tree = PathTree::<&str>::new();
+    tree.add_payload(&"/devices/*/temperature".parse().unwrap(), "any_temperature");
+    tree.add_payload(&"/devices/*2,0".parse().unwrap(), "two_elements");
+
+    let results = tree.get_payloads(&"/devices/5/temperature".parse().unwrap());
+    assert!(results.len() == 2);
+    assert!(results.contains(&&"any_temperature"));
+    assert!(results.contains(&&"two_elements"));
+
+    assert!(tree.get_payloads(&"/devices/5".parse().unwrap()).is_empty());
+    assert!(tree.get_payloads(&"/devices/5/humidity/x".parse().unwrap()).is_empty());
+}
+
+#[test]
+fn test_retain_payloads()
+{
+    let mut tree = PathTree::<u32>::new();
+    tree.add_payload(&"/a".parse().unwrap(), 1);
+    tree.add_payload(&"/a/b".parse().unwrap(), 2);
+    tree.add_payload(&"/a/*".parse().unwrap(), 3);
+    tree.retain_payloads(&mut |payload| *payload != 2);
+    assert!(tree.get_payloads(&"/a/b".parse().unwrap()) == vec![&3]);
+    assert!(tree.all_payloads().len() == 2);
+}
+
+#[test]
+fn test_replace_prefix()
+{
+    let path: Path = "/bus/ug/rx/5".parse().unwrap();
+    assert!(path.starts_with(&"/bus/ug".parse().unwrap()));
+    assert!(!path.starts_with(&"/bus/u".parse().unwrap()));
+    assert!(!path.starts_with(&"/bus/*".parse().unwrap()));
+    assert_eq!(path.replace_prefix(&"/bus/ug/rx".parse().unwrap(), &"/house".parse().unwrap()), Some("/house/5".parse().unwrap()));
+    assert_eq!(path.replace_prefix(&path, &"/house/light".parse().unwrap()), Some("/house/light".parse().unwrap()));
+    assert_eq!(path.replace_prefix(&"/house".parse().unwrap(), &"/bus".parse().unwrap()), None);
+
+    let prefix: Path = "/bus/ug".parse().unwrap();
+    assert_eq!(path.strip_prefix(&prefix), Some("/rx/5".parse().unwrap()));
+    assert_eq!(prefix.strip_prefix(&prefix), Some("/".parse().unwrap()));
+    assert_eq!(prefix.join(&"/rx/5".parse().unwrap()), path);
+    assert_eq!(prefix.join(&"/".parse().unwrap()), prefix);
+
+    assert_eq!(prefix.child("rx"), Ok("/bus/ug/rx".parse().unwrap()));
+    assert_eq!(prefix.child("kitchen*"), Ok("/bus/ug/kitchen*".parse().unwrap()));
+    for name in ["", "rx/5", "*", "**", "*x", "*1,0"]
+    {
+        assert!(prefix.child(name).is_err(), "{}", name);
+    }
+}
+
+#[test]
+fn test_replace_payloads()
+{
+    let mut tree = PathTree::<&str>::new();
+    tree.add_payload(&"/a/b".parse().unwrap(), "data1");
+    tree.add_payload(&"/a/b".parse().unwrap(), "data2");
+    tree.add_payload(&"/a".parse().unwrap(), "data_a");
+    tree.replace_payloads(&"/a/b".parse().unwrap(), "data3");
+    tree.replace_payloads(&"/c".parse().unwrap(), "data_c");
+
+    assert!(tree.get_payloads(&"/a/b".parse().unwrap()) == vec![&"data3"]);
+    assert!(tree.get_payloads(&"/c".parse().unwrap()) == vec![&"data_c"]);
+    let all = tree.all_payloads();
+    assert!(all.len() == 3);
+    assert!(all.contains(&&"data_a"));
+}
+
 #[test]
 fn test_wildcard_in_tree_and_path()
 {
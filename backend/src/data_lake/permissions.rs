@@ -0,0 +1,68 @@
+use std::fmt;
+
+use super::path_tree::{Path, PathTree};
+
+/// Path patterns a role may publish to and receive values of, see `TDataLake::with_permissions`.
+pub struct Permissions
+{
+    role: String,
+    publish: PathTree<()>,
+    subscribe: PathTree<()>,
+}
+
+impl fmt::Debug for Permissions
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Permissions of role '{}'", self.role)
+    }
+}
+
+impl Permissions
+{
+    pub fn new(role: &str, publish: &[Path], subscribe: &[Path]) -> Self
+    {
+        let mut permissions = Permissions{role: role.to_string(), publish: PathTree::new(), subscribe: PathTree::new()};
+        for pattern in publish
+        {
+            permissions.publish.add_payload(pattern, ());
+        }
+        for pattern in subscribe
+        {
+            permissions.subscribe.add_payload(pattern, ());
+        }
+        permissions
+    }
+
+    pub fn role(self: &Self) -> &str
+    {
+        &self.role
+    }
+
+    /// For patterns: true if the role may publish to some of the paths matching it, views of
+    /// the lake therefore do not publish to patterns at all.
+    pub fn may_publish(self: &Self, path: &Path) -> bool
+    {
+        !self.publish.get_payloads(path).is_empty()
+    }
+
+    /// For patterns: true if the role may receive some of the values matching it.
+    pub fn may_receive(self: &Self, path: &Path) -> bool
+    {
+        !self.subscribe.get_payloads(path).is_empty()
+    }
+}
+
+#[test]
+fn test_permissions()
+{
+    let path = |s: &str| s.parse::<Path>().unwrap();
+    let guest = Permissions::new("guest", &[path("/house/*/light")], &[path("/house/**")]);
+    assert!(guest.may_publish(&path("/house/eg/light")));
+    assert!(!guest.may_publish(&path("/house/eg/door/open")));
+    assert!(guest.may_receive(&path("/house/eg/door/open")));
+    assert!(!guest.may_receive(&path("/bus/ug/rx/5")));
+    // patterns overlapping the permitted ones
+    assert!(guest.may_receive(&path("/**")));
+    assert!(guest.may_receive(&path("/*/eg")));
+    assert!(!guest.may_receive(&path("/bus/**")));
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// A query published into the lake which expects a reply.
+/// Subscribe to `Request<Q, R>` at some path to serve requests there,
+/// use `TDataLake::request()` to issue them.
+/// As published objects are cloned for every subscriber, all clones share the
+/// reply channel: only the first reply is delivered, later ones are discarded.
+pub struct Request<Q, R>
+{
+    pub query: Q,
+    reply_sender: Arc<Mutex<Option<tokio::sync::oneshot::Sender<R>>>>,
+}
+
+impl<Q, R> Request<Q, R>
+{
+    pub fn new(query: Q) -> (Self, tokio::sync::oneshot::Receiver<R>)
+    {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        (Request{query, reply_sender: Arc::new(Mutex::new(Some(tx)))}, rx)
+    }
+
+    /// Sends the reply to the requester.
+    /// Returns false if the request was already answered or the requester is gone.
+    pub fn reply(self: &Self, reply: R) -> bool
+    {
+        match self.reply_sender.lock().unwrap().take()
+        {
+            Some(sender) => sender.send(reply).is_ok(),
+            None => false
+        }
+    }
+}
+
+impl<Q: Clone, R> Clone for Request<Q, R>
+{
+    fn clone(&self) -> Self
+    {
+        Request{query: self.query.clone(), reply_sender: self.reply_sender.clone()}
+    }
+}
+
+impl<Q: fmt::Debug, R> fmt::Debug for Request<Q, R>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.debug_struct("Request").field("query", &self.query).finish()
+    }
+}
+
+#[tokio::test]
+async fn request_reply()
+{
+    let mut datalake = super::TDataLake::new();
+    let mut fisher = datalake.subscribe::<Request<u32, u32>>(&"/double".parse().unwrap()).await;
+    tokio::task::spawn(async move {
+        let request = fisher.receive().await.unwrap();
+        assert!(request.reply(request.query * 2));
+        assert!(!request.reply(0));
+    });
+
+    let reply = datalake.request::<u32, u32>(&"/double".parse().unwrap(), 21).await;
+    assert_eq!(reply, Some(42));
+}
+
+#[tokio::test]
+async fn request_without_server()
+{
+    let datalake = super::TDataLake::new();
+    let reply = datalake.request::<u32, u32>(&"/nobody".parse().unwrap(), 21).await;
+    assert_eq!(reply, None);
+}
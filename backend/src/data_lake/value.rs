@@ -0,0 +1,217 @@
+use std::fmt;
+
+/// Structured payload as exchanged with devices, independent of its encoding on the bus.
+/// Covers the MessagePack data model, JSON maps onto a subset of it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value
+{
+    Nil,
+    Bool(bool),
+    /// NOTE: unsigned integers above `i64::MAX` are represented as `Float`
+    Integer(i64),
+    Float(f64),
+    String(String),
+    Binary(Vec<u8>),
+    Array(Vec<Value>),
+    /// Keys are kept in order and may be of any type, as in MessagePack
+    Map(Vec<(Value, Value)>),
+}
+
+impl Value
+{
+    /// Decodes a single MessagePack encoded value.
+    /// Extension types are not interpreted, their data is kept as `Binary`.
+    pub fn from_message_pack(data: &[u8]) -> Result<Self, String>
+    {
+        let mut reader = data;
+        let value = rmpv::decode::read_value(&mut reader)
+            .map_err(|e| format!("Invalid MessagePack payload: {}", e))?;
+        if !reader.is_empty()
+        {
+            return Err(format!("Invalid MessagePack payload: {} trailing bytes", reader.len()));
+        }
+        Ok(Self::from_rmpv(value))
+    }
+
+    pub fn to_message_pack(self: &Self) -> Vec<u8>
+    {
+        let mut data = Vec::new();
+        // writing to a Vec does not fail
+        rmpv::encode::write_value(&mut data, &self.to_rmpv()).unwrap();
+        data
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, String>
+    {
+        let value: serde_json::Value = serde_json::from_str(text)
+            .map_err(|e| format!("Invalid JSON payload: {}", e))?;
+        Ok(Self::from_serde_json(value))
+    }
+
+    /// JSON text of the value. Binary data becomes an array of numbers,
+    /// map keys which are not strings are replaced by their JSON text.
+    pub fn to_json(self: &Self) -> String
+    {
+        self.to_serde_json().to_string()
+    }
+
+    /// Converts a value written in the configuration file. Datetimes become strings.
+    pub fn from_toml(value: &toml::Value) -> Self
+    {
+        match value
+        {
+            toml::Value::String(s) => Value::String(s.clone()),
+            toml::Value::Integer(i) => Value::Integer(*i),
+            toml::Value::Float(f) => Value::Float(*f),
+            toml::Value::Boolean(b) => Value::Bool(*b),
+            toml::Value::Datetime(d) => Value::String(d.to_string()),
+            toml::Value::Array(a) => Value::Array(a.iter().map(Self::from_toml).collect()),
+            toml::Value::Table(t) => Value::Map(t.iter().map(|(k, v)| (Value::String(k.clone()), Self::from_toml(v))).collect()),
+        }
+    }
+
+    /// Value of the entry with the given string key, if this is a map.
+    pub fn get(self: &Self, key: &str) -> Option<&Value>
+    {
+        match self
+        {
+            Value::Map(entries) => entries.iter()
+                .find(|(entry_key, _)| matches!(entry_key, Value::String(k) if k == key))
+                .map(|(_, value)| value),
+            _ => None
+        }
+    }
+
+    fn from_rmpv(value: rmpv::Value) -> Self
+    {
+        match value
+        {
+            rmpv::Value::Nil => Value::Nil,
+            rmpv::Value::Boolean(b) => Value::Bool(b),
+            rmpv::Value::Integer(i) => match i.as_i64()
+            {
+                Some(i) => Value::Integer(i),
+                None => Value::Float(i.as_f64().unwrap_or(f64::NAN)),
+            },
+            rmpv::Value::F32(f) => Value::Float(f as f64),
+            rmpv::Value::F64(f) => Value::Float(f),
+            // invalid UTF-8 is kept as it is
+            rmpv::Value::String(s) if s.is_str() => Value::String(s.into_str().unwrap()),
+            rmpv::Value::String(s) => Value::Binary(s.into_bytes()),
+            rmpv::Value::Binary(b) => Value::Binary(b),
+            rmpv::Value::Array(a) => Value::Array(a.into_iter().map(Self::from_rmpv).collect()),
+            rmpv::Value::Map(m) => Value::Map(m.into_iter().map(|(k, v)| (Self::from_rmpv(k), Self::from_rmpv(v))).collect()),
+            rmpv::Value::Ext(_, data) => Value::Binary(data),
+        }
+    }
+
+    fn to_rmpv(self: &Self) -> rmpv::Value
+    {
+        match self
+        {
+            Value::Nil => rmpv::Value::Nil,
+            Value::Bool(b) => rmpv::Value::Boolean(*b),
+            Value::Integer(i) => rmpv::Value::from(*i),
+            Value::Float(f) => rmpv::Value::F64(*f),
+            Value::String(s) => rmpv::Value::from(s.as_str()),
+            Value::Binary(b) => rmpv::Value::Binary(b.clone()),
+            Value::Array(a) => rmpv::Value::Array(a.iter().map(Self::to_rmpv).collect()),
+            Value::Map(m) => rmpv::Value::Map(m.iter().map(|(k, v)| (k.to_rmpv(), v.to_rmpv())).collect()),
+        }
+    }
+
+    pub fn from_serde_json(value: serde_json::Value) -> Self
+    {
+        match value
+        {
+            serde_json::Value::Null => Value::Nil,
+            serde_json::Value::Bool(b) => Value::Bool(b),
+            serde_json::Value::Number(n) => match n.as_i64()
+            {
+                Some(i) => Value::Integer(i),
+                None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(s) => Value::String(s),
+            serde_json::Value::Array(a) => Value::Array(a.into_iter().map(Self::from_serde_json).collect()),
+            serde_json::Value::Object(o) => Value::Map(o.into_iter().map(|(k, v)| (Value::String(k), Self::from_serde_json(v))).collect()),
+        }
+    }
+
+    /// See `to_json`
+    pub fn to_serde_json(self: &Self) -> serde_json::Value
+    {
+        match self
+        {
+            Value::Nil => serde_json::Value::Null,
+            Value::Bool(b) => serde_json::Value::Bool(*b),
+            Value::Integer(i) => serde_json::Value::from(*i),
+            // NaN and infinity have no JSON representation and become null
+            Value::Float(f) => serde_json::Value::from(*f),
+            Value::String(s) => serde_json::Value::String(s.clone()),
+            Value::Binary(b) => serde_json::Value::from(b.clone()),
+            Value::Array(a) => serde_json::Value::Array(a.iter().map(Self::to_serde_json).collect()),
+            Value::Map(m) => serde_json::Value::Object(m.iter().map(|(k, v)|
+                {
+                    let key = match k
+                    {
+                        Value::String(s) => s.clone(),
+                        other => other.to_json(),
+                    };
+                    (key, v.to_serde_json())
+                }).collect()),
+        }
+    }
+}
+
+impl fmt::Display for Value
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}", self.to_json())
+    }
+}
+
+#[test]
+fn test_message_pack_round_trip()
+{
+    let value = Value::Map(vec![
+        (Value::String("on".into()), Value::Bool(true)),
+        (Value::String("level".into()), Value::Integer(-42)),
+        (Value::String("temperature".into()), Value::Float(21.5)),
+        (Value::Integer(1), Value::Array(vec![Value::Nil, Value::Binary(vec![0xde, 0xad])])),
+    ]);
+    let data = value.to_message_pack();
+    assert_eq!(Value::from_message_pack(&data), Ok(value.clone()));
+
+    // fixmap with one entry: {"a": 1}
+    assert_eq!(Value::from_message_pack(&[0x81, 0xa1, b'a', 0x01]), Ok(Value::Map(vec![(Value::String("a".into()), Value::Integer(1))])));
+    // uint64 max does not fit into i64
+    assert_eq!(Value::from_message_pack(&[0xcf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]), Ok(Value::Float(u64::MAX as f64)));
+    assert!(Value::from_message_pack(&[0x81, 0xa1]).is_err());
+    assert!(Value::from_message_pack(&[0x01, 0x02]).is_err());
+}
+
+#[test]
+fn test_json()
+{
+    let value = Value::from_json(r#"{"on": true, "level": 3, "name": "lamp", "rgb": [1.5, null]}"#).unwrap();
+    assert_eq!(value.get("on"), Some(&Value::Bool(true)));
+    assert_eq!(value.get("level"), Some(&Value::Integer(3)));
+    assert_eq!(value.get("rgb"), Some(&Value::Array(vec![Value::Float(1.5), Value::Nil])));
+    assert_eq!(value.get("missing"), None);
+    assert_eq!(Value::from_json(&value.to_json()), Ok(value));
+
+    assert_eq!(Value::Map(vec![(Value::Integer(1), Value::Binary(vec![1, 2]))]).to_json(), r#"{"1":[1,2]}"#);
+    assert!(Value::from_json("{").is_err());
+}
+
+#[test]
+fn test_toml()
+{
+    let table: toml::Value = toml::from_str("on = true\nlevel = 3\nrgb = [0.5, 1.0]\nname = \"lamp\"").unwrap();
+    let value = Value::from_toml(&table);
+    assert_eq!(value.get("on"), Some(&Value::Bool(true)));
+    assert_eq!(value.get("level"), Some(&Value::Integer(3)));
+    assert_eq!(value.get("rgb"), Some(&Value::Array(vec![Value::Float(0.5), Value::Float(1.0)])));
+    assert_eq!(value.get("name"), Some(&Value::String("lamp".into())));
+}
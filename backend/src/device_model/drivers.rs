@@ -0,0 +1,220 @@
+//! Drivers of the device kinds. Nodes exchange maps keyed by property name with the backend,
+//! e.g. a dimmer reports `{"on": true, "level": 40}` and is sent `{"level": 60}`.
+
+use crate::config::DeviceKind;
+use crate::data_lake::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access
+{
+    /// reported by the device only
+    Read,
+    /// commands only, e.g. "stop" of a shutter
+    Write,
+    ReadWrite,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropertyType
+{
+    Bool,
+    /// Integer 0-100
+    Percent,
+    Float,
+    /// Array of three integers 0-255
+    Rgb,
+    /// One of the strings
+    Choice(&'static [&'static str]),
+}
+
+impl PropertyType
+{
+    /// The value in its canonical form, e.g. `Integer` for a `Float` percentage.
+    fn check(self: &Self, value: &Value) -> Result<Value, String>
+    {
+        let checked = match (self, value)
+        {
+            (PropertyType::Bool, Value::Bool(_)) => Some(value.clone()),
+            (PropertyType::Percent, Value::Integer(i)) if (0..=100).contains(i) => Some(value.clone()),
+            (PropertyType::Percent, Value::Float(f)) if (0.0..=100.0).contains(f) => Some(Value::Integer(f.round() as i64)),
+            (PropertyType::Float, Value::Float(_)) => Some(value.clone()),
+            (PropertyType::Float, Value::Integer(i)) => Some(Value::Float(*i as f64)),
+            (PropertyType::Rgb, Value::Array(components)) if components.len() == 3
+                && components.iter().all(|component| matches!(component, Value::Integer(0..=255))) => Some(value.clone()),
+            (PropertyType::Choice(choices), Value::String(s)) if choices.contains(&s.as_str()) => Some(value.clone()),
+            _ => None,
+        };
+        checked.ok_or(match self
+        {
+            PropertyType::Bool => format!("expected true or false, got {}", value),
+            PropertyType::Percent => format!("expected a percentage 0-100, got {}", value),
+            PropertyType::Float => format!("expected a number, got {}", value),
+            PropertyType::Rgb => format!("expected [red, green, blue] with 0-255 each, got {}", value),
+            PropertyType::Choice(choices) => format!("expected one of {}, got {}", choices.join(", "), value),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Property
+{
+    pub name: &'static str,
+    pub property_type: PropertyType,
+    pub access: Access,
+}
+
+const fn property(name: &'static str, property_type: PropertyType, access: Access) -> Property
+{
+    Property{name, property_type, access}
+}
+
+/// Translates between the properties of a device kind, published below the device's path,
+/// and the messages of its node.
+pub trait DeviceDriver: Send + Sync
+{
+    fn properties(self: &Self) -> &'static [Property];
+
+    fn property(self: &Self, name: &str) -> Option<&'static Property>
+    {
+        self.properties().iter().find(|property| property.name == name)
+    }
+
+    /// Readable properties contained in a message received from the device,
+    /// entries which are unknown or invalid are skipped.
+    fn decode(self: &Self, message: &Value) -> Vec<(&'static str, Value)>
+    {
+        self.properties().iter()
+            .filter(|property| property.access != Access::Write)
+            .filter_map(|property| {
+                let value = property.property_type.check(message.get(property.name)?).ok()?;
+                Some((property.name, value))
+            })
+            .collect()
+    }
+
+    /// Message to the device setting a writable property.
+    fn encode(self: &Self, name: &str, value: &Value) -> Result<Value, String>
+    {
+        let property = self.property(name).ok_or(format!("no property '{}'", name))?;
+        if property.access == Access::Read
+        {
+            return Err(format!("property '{}' is read-only", name));
+        }
+        let value = property.property_type.check(value).map_err(|e| format!("property '{}': {}", name, e))?;
+        Ok(Value::Map(vec![(Value::String(name.to_string()), value)]))
+    }
+}
+
+pub struct Switch;
+
+impl DeviceDriver for Switch
+{
+    fn properties(self: &Self) -> &'static [Property]
+    {
+        const PROPERTIES: &[Property] = &[property("on", PropertyType::Bool, Access::ReadWrite)];
+        PROPERTIES
+    }
+}
+
+pub struct Dimmer;
+
+impl DeviceDriver for Dimmer
+{
+    fn properties(self: &Self) -> &'static [Property]
+    {
+        const PROPERTIES: &[Property] = &[
+            property("on", PropertyType::Bool, Access::ReadWrite),
+            property("level", PropertyType::Percent, Access::ReadWrite),
+        ];
+        PROPERTIES
+    }
+}
+
+pub struct RgbLight;
+
+impl DeviceDriver for RgbLight
+{
+    fn properties(self: &Self) -> &'static [Property]
+    {
+        const PROPERTIES: &[Property] = &[
+            property("on", PropertyType::Bool, Access::ReadWrite),
+            property("level", PropertyType::Percent, Access::ReadWrite),
+            property("color", PropertyType::Rgb, Access::ReadWrite),
+        ];
+        PROPERTIES
+    }
+}
+
+/// Publishing true to `open` releases the door, the node locks it again by itself.
+pub struct DoorOpener;
+
+impl DeviceDriver for DoorOpener
+{
+    fn properties(self: &Self) -> &'static [Property]
+    {
+        const PROPERTIES: &[Property] = &[property("open", PropertyType::Bool, Access::ReadWrite)];
+        PROPERTIES
+    }
+}
+
+pub struct TemperatureSensor;
+
+impl DeviceDriver for TemperatureSensor
+{
+    fn properties(self: &Self) -> &'static [Property]
+    {
+        const PROPERTIES: &[Property] = &[property("temperature", PropertyType::Float, Access::Read)];
+        PROPERTIES
+    }
+}
+
+/// `position` 0 is open, 100 closed.
+pub struct Shutter;
+
+impl DeviceDriver for Shutter
+{
+    fn properties(self: &Self) -> &'static [Property]
+    {
+        const PROPERTIES: &[Property] = &[
+            property("position", PropertyType::Percent, Access::ReadWrite),
+            property("moving", PropertyType::Choice(&["up", "down", "stopped"]), Access::Read),
+            property("command", PropertyType::Choice(&["up", "down", "stop"]), Access::Write),
+        ];
+        PROPERTIES
+    }
+}
+
+pub fn driver_of(kind: DeviceKind) -> Box<dyn DeviceDriver>
+{
+    match kind
+    {
+        DeviceKind::Switch => Box::new(Switch),
+        DeviceKind::Dimmer => Box::new(Dimmer),
+        DeviceKind::RgbLight => Box::new(RgbLight),
+        DeviceKind::DoorOpener => Box::new(DoorOpener),
+        DeviceKind::TemperatureSensor => Box::new(TemperatureSensor),
+        DeviceKind::Shutter => Box::new(Shutter),
+    }
+}
+
+#[test]
+fn test_drivers()
+{
+    let message = Value::from_json(r#"{"on": true, "level": 40.4, "color": [255, 0, 300], "unknown": 1}"#).unwrap();
+    assert_eq!(RgbLight.decode(&message), vec![("on", Value::Bool(true)), ("level", Value::Integer(40))]);
+    assert_eq!(Switch.decode(&message), vec![("on", Value::Bool(true))]);
+    assert_eq!(Dimmer.encode("level", &Value::Integer(60)), Ok(Value::from_json(r#"{"level": 60}"#).unwrap()));
+    assert!(Dimmer.encode("level", &Value::Integer(101)).is_err());
+    assert!(Dimmer.encode("color", &Value::Integer(1)).is_err());
+    assert_eq!(RgbLight.encode("color", &Value::from_json("[1, 2, 3]").unwrap()), Ok(Value::from_json(r#"{"color": [1, 2, 3]}"#).unwrap()));
+
+    assert_eq!(TemperatureSensor.decode(&Value::from_json(r#"{"temperature": 21}"#).unwrap()), vec![("temperature", Value::Float(21.0))]);
+    assert_eq!(TemperatureSensor.encode("temperature", &Value::Float(1.0)), Err("property 'temperature' is read-only".into()));
+
+    // commands are never reported
+    let message = Value::from_json(r#"{"position": 30, "moving": "down", "command": "stop"}"#).unwrap();
+    assert_eq!(Shutter.decode(&message), vec![("position", Value::Integer(30)), ("moving", Value::String("down".into()))]);
+    assert!(Shutter.encode("command", &Value::String("stop".into())).is_ok());
+    assert!(Shutter.encode("command", &Value::String("sideways".into())).is_err());
+    assert!(DoorOpener.encode("open", &Value::Bool(true)).is_ok());
+}
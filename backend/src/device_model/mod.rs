@@ -0,0 +1,188 @@
+//! Publishes the state of the `[[devices]]` with a `kind` as properties below their `path`,
+//! e.g. /house/eg/kitchen/lamp/on, and turns values published to writable properties into
+//! `TransmitRequest`s to the device, so automations need not know the messages of the nodes.
+//! Messages received from the device (<base_path>/rx/<device_id>) update the properties.
+
+use std::collections::HashMap;
+
+use crate::bus_access::{TransmitRequest, ROUTED_TX_PATH};
+use crate::config::{BusConfig, DeviceConfig};
+use crate::data_lake::*;
+use crate::data_lake::path_tree::Path;
+use crate::echoes::Echoes;
+use crate::shutdown::TaskHandle;
+
+pub mod drivers;
+use drivers::DeviceDriver;
+
+struct TypedDevice
+{
+    id: String,
+    /// where the device's messages are published
+    rx_path: Path,
+    /// base of the property paths
+    path: String,
+    driver: Box<dyn DeviceDriver>,
+}
+
+enum Event
+{
+    Received(usize, Value),
+    Written(usize, Path, Value),
+}
+
+/// Devices with kind and path, the configuration was validated.
+fn typed_devices(devices: &[DeviceConfig], gateways: &[BusConfig]) -> Vec<TypedDevice>
+{
+    devices.iter().filter_map(|device| {
+        let gateway = crate::bus_access::gateway_of(gateways, &device.id)?;
+        Some(TypedDevice{
+            id: device.id.clone(),
+            rx_path: format!("{}/rx/{}", gateway.base_path, device.id).parse().unwrap(),
+            path: device.path.clone()?,
+            driver: drivers::driver_of(device.kind?),
+        })
+    }).collect()
+}
+
+pub fn create(datalake: TDataLake, devices: &[DeviceConfig], gateways: &[BusConfig]) -> TaskHandle
+{
+    let devices = typed_devices(devices, gateways);
+    TaskHandle::spawn(|rx| run_drivers(datalake, devices, rx))
+}
+
+async fn run_drivers(mut datalake: TDataLake, devices: Vec<TypedDevice>, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut forwarders = Vec::new();
+    for (i, device) in devices.iter().enumerate()
+    {
+        let mut messages = datalake.subscribe::<Value>(&device.rx_path).await;
+        let messages_sender = sender.clone();
+        forwarders.push(tokio::task::spawn(async move {
+            while let Some(message) = messages.receive().await
+            {
+                if messages_sender.send(Event::Received(i, message)).is_err()
+                {
+                    break;
+                }
+            }
+        }));
+        let mut properties = datalake.subscribe_with_path::<Value>(&format!("{}/*", device.path).parse().unwrap()).await;
+        let properties_sender = sender.clone();
+        forwarders.push(tokio::task::spawn(async move {
+            while let Some((path, value)) = properties.receive().await
+            {
+                if properties_sender.send(Event::Written(i, path, value)).is_err()
+                {
+                    break;
+                }
+            }
+        }));
+    }
+    drop(sender);
+
+    // property values published by the drivers come back through the subscriptions
+    let mut echoes = Echoes::new();
+    let property_paths: HashMap<String, Path> = devices.iter()
+        .flat_map(|device| device.driver.properties().iter().map(move |property| format!("{}/{}", device.path, property.name)))
+        .map(|path| (path.clone(), path.parse().unwrap()))
+        .collect();
+    loop
+    {
+        tokio::select!
+        {
+            Some(event) = receiver.recv() =>
+            {
+                match event
+                {
+                    Event::Received(i, message) =>
+                    {
+                        let device = &devices[i];
+                        for (name, value) in device.driver.decode(&message)
+                        {
+                            let path = format!("{}/{}", device.path, name);
+                            echoes.expect(path.clone(), value.clone());
+                            datalake.publish_retained(&property_paths[&path], value).await;
+                        }
+                    }
+                    Event::Written(i, path, value) =>
+                    {
+                        if echoes.is_echo(&path.to_string(), &value)
+                        {
+                            continue;
+                        }
+                        let device = &devices[i];
+                        let name = path.to_string().rsplit('/').next().unwrap_or_default().to_string();
+                        match device.driver.encode(&name, &value)
+                        {
+                            Ok(payload) =>
+                            {
+                                let request = TransmitRequest{device_id: device.id.clone(), payload};
+                                datalake.publish(&ROUTED_TX_PATH.parse().unwrap(), request).await;
+                            }
+                            Err(e) => println!("Device '{}': ignoring {} published to {}: {}", device.id, value, path, e),
+                        }
+                    }
+                }
+            }
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped)
+                break;
+            }
+        }
+    }
+    for forwarder in forwarders.iter()
+    {
+        forwarder.abort();
+    }
+}
+
+#[tokio::test]
+async fn drivers_translate_between_properties_and_messages()
+{
+    let config: crate::config::Config = r#"
+        [[gateways]]
+        base_path = "/bus/ug"
+        [[devices]]
+        id = "5"
+        kind = "dimmer"
+        path = "/house/eg/kitchen/lamp"
+        [[devices]]
+        id = "6"
+        kind = "temperature_sensor"
+        path = "/house/eg/kitchen/temperature"
+    "#.parse().unwrap();
+    let mut datalake = TDataLake::new();
+    let mut transmitted = datalake.subscribe::<TransmitRequest>(&ROUTED_TX_PATH.parse().unwrap()).await;
+    let handle = create(datalake.clone(), &config.devices, &config.gateways);
+    // give the drivers a chance to subscribe
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    datalake.publish_retained(&"/bus/ug/rx/5".parse().unwrap(), Value::from_json(r#"{"on": true, "level": 40}"#).unwrap()).await;
+    datalake.publish_retained(&"/bus/ug/rx/6".parse().unwrap(), Value::from_json(r#"{"temperature": 21.5}"#).unwrap()).await;
+    let level: Path = "/house/eg/kitchen/lamp/level".parse().unwrap();
+    for _ in 0..100
+    {
+        if datalake.get::<Value>(&level).await.is_some()
+        {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(datalake.get::<Value>(&level).await, Some(Value::Integer(40)));
+    assert_eq!(datalake.get::<Value>(&"/house/eg/kitchen/lamp/on".parse().unwrap()).await, Some(Value::Bool(true)));
+
+    // the reported state is not sent back, commands are
+    datalake.publish_retained(&"/house/eg/kitchen/temperature/temperature".parse().unwrap(), Value::Float(30.0)).await;
+    datalake.publish_retained(&level, Value::Integer(500)).await;
+    datalake.publish_retained(&level, Value::Integer(60)).await;
+    let request = transmitted.receive().await.unwrap();
+    assert_eq!(request.device_id, "5");
+    assert_eq!(request.payload, Value::from_json(r#"{"level": 60}"#).unwrap());
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    assert!(transmitted.receiver.try_recv().is_err());
+
+    handle.stop().await;
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use tokio_stream::StreamExt;
+use tonic::transport::Channel;
+
+use crate::bus_access::bus;
+use crate::bus_access::bus::home_control_client::HomeControlClient;
+use crate::config::{BusConfig, DeviceRegistryConfig};
+use crate::data_lake::*;
+use crate::shutdown::TaskHandle;
+use crate::data_lake::path_tree::{Path, PathElement};
+
+/// Lake path below which the registry publishes a `DeviceInfo` per device: /devices/<address>
+pub const DEVICES_PATH: &str = "/devices";
+
+/// Everything the registry knows about a device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceInfo
+{
+    pub address: String,
+    pub name: String,
+    /// Name of the gateway the device was found on
+    pub gateway: String,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+}
+
+/// Served at /devices/<address>/identify, replies with the refreshed `DeviceInfo`.
+pub type IdentifyRequest = Request<(), Result<DeviceInfo, String>>;
+/// Served at /devices/<address>/rename, query is the new name.
+pub type RenameRequest = Request<String, Result<DeviceInfo, String>>;
+/// Served at /devices/<address>/readdress, query is the new address.
+pub type ReaddressRequest = Request<String, Result<DeviceInfo, String>>;
+
+#[derive(Default)]
+struct Inventory
+{
+    devices: HashMap<String, DeviceInfo>,
+}
+
+impl Inventory
+{
+    /// Records that a device answered, returns its updated info.
+    fn seen(self: &mut Self, gateway: &str, address: &str, name: &str, now: SystemTime) -> DeviceInfo
+    {
+        let info = self.devices.entry(address.to_string()).or_insert_with(|| DeviceInfo{
+            address: address.to_string(),
+            name: name.to_string(),
+            gateway: gateway.to_string(),
+            first_seen: now,
+            last_seen: now,
+        });
+        info.name = name.to_string();
+        info.gateway = gateway.to_string();
+        info.last_seen = now;
+        info.clone()
+    }
+
+    fn renamed(self: &mut Self, address: &str, name: &str) -> Option<DeviceInfo>
+    {
+        let info = self.devices.get_mut(address)?;
+        info.name = name.to_string();
+        Some(info.clone())
+    }
+
+    /// None if there is no device at address or new_address is in use by another device.
+    fn readdressed(self: &mut Self, address: &str, new_address: &str) -> Option<DeviceInfo>
+    {
+        if self.devices.contains_key(new_address)
+        {
+            return None;
+        }
+        let mut info = self.devices.remove(address)?;
+        info.address = new_address.to_string();
+        self.devices.insert(new_address.to_string(), info.clone());
+        Some(info)
+    }
+}
+
+pub fn create(datalake: TDataLake, config: DeviceRegistryConfig, gateways: &[BusConfig]) -> TaskHandle
+{
+    let clients = crate::bus_access::home_control_clients(gateways);
+
+    TaskHandle::spawn(|rx| run_registry(datalake, config, clients, rx))
+}
+
+/// Extracts <address> from /devices/<address>/<operation>
+fn address_from_path(path: &Path) -> Option<String>
+{
+    match path.elements()
+    {
+        [PathElement::Root, PathElement::Name(_), PathElement::Name(address), PathElement::Name(_)] => Some(address.clone()),
+        _ => None
+    }
+}
+
+fn device_path(address: &str) -> Option<Path>
+{
+    format!("{}/{}", DEVICES_PATH, address).parse().ok()
+}
+
+async fn publish_device(datalake: &TDataLake, info: &DeviceInfo)
+{
+    match device_path(&info.address)
+    {
+        Some(path) => datalake.publish_retained(&path, info.clone()).await,
+        None => println!("Device registry: address '{}' cannot be used as lake path", info.address)
+    }
+}
+
+/// will scan all gateways every `scan_interval_seconds` and publish a `DeviceInfo` to /devices/<address>
+/// for every device found.
+/// will serve `IdentifyRequest`s, `RenameRequest`s and `ReaddressRequest`s at /devices/<address>/{identify,rename,readdress}
+async fn run_registry(mut datalake: TDataLake, config: DeviceRegistryConfig, mut clients: HashMap<String, HomeControlClient<Channel>>, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    let mut inventory = Inventory::default();
+
+    let mut identify_fisher = datalake.subscribe_with_path::<IdentifyRequest>(&(DEVICES_PATH.to_string() + "/*/identify").parse().unwrap()).await;
+    let mut rename_fisher = datalake.subscribe_with_path::<RenameRequest>(&(DEVICES_PATH.to_string() + "/*/rename").parse().unwrap()).await;
+    let mut readdress_fisher = datalake.subscribe_with_path::<ReaddressRequest>(&(DEVICES_PATH.to_string() + "/*/readdress").parse().unwrap()).await;
+
+    let mut scan_interval = tokio::time::interval(Duration::from_secs(config.scan_interval_seconds));
+    loop
+    {
+        tokio::select!
+        {
+            _ = scan_interval.tick(), if config.enabled =>
+            {
+                for (gateway, client) in clients.iter_mut()
+                {
+                    // NOTE: requests are not served while scanning
+                    scan(&datalake, &mut inventory, gateway, client, config.scan_timeout_milliseconds).await;
+                }
+            }
+            Some((path, request)) = identify_fisher.receive() =>
+            {
+                let result = identify(&mut inventory, &mut clients, &path).await;
+                if let Ok(info) = &result
+                {
+                    publish_device(&datalake, info).await;
+                }
+                request.reply(result);
+            }
+            Some((path, request)) = rename_fisher.receive() =>
+            {
+                let result = rename(&mut inventory, &mut clients, &path, &request.query).await;
+                if let Ok(info) = &result
+                {
+                    publish_device(&datalake, info).await;
+                }
+                request.reply(result);
+            }
+            Some((path, request)) = readdress_fisher.receive() =>
+            {
+                let result = readdress(&mut inventory, &mut clients, &path, &request.query).await;
+                if let Ok(info) = &result
+                {
+                    // the device is no longer at its old address
+                    if let Some(old_path) = address_from_path(&path).and_then(|address| device_path(&address))
+                    {
+                        datalake.clear_retained::<DeviceInfo>(&old_path).await;
+                    }
+                    publish_device(&datalake, info).await;
+                }
+                request.reply(result);
+            }
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped)
+                break;
+            }
+        }
+    }
+}
+
+async fn scan(datalake: &TDataLake, inventory: &mut Inventory, gateway: &str, client: &mut HomeControlClient<Channel>, timeout_milliseconds: u32)
+{
+    let scan = async {
+        let mut replies = client.scan_devices(bus::ScanDevicesRequest{timeout_milliseconds}).await?.into_inner();
+        while let Some(reply) = replies.next().await
+        {
+            let reply = reply?;
+            let info = inventory.seen(gateway, &reply.device_address, &reply.device_name, SystemTime::now());
+            publish_device(datalake, &info).await;
+        }
+        Ok::<(), tonic::Status>(())
+    };
+    // the gateway ends the stream after timeout_milliseconds, do not rely on it:
+    let deadline = Duration::from_millis(timeout_milliseconds as u64) + Duration::from_secs(5);
+    match tokio::time::timeout(deadline, scan).await
+    {
+        Ok(Ok(())) => (),
+        Ok(Err(status)) => println!("Device registry: scanning gateway '{}' failed: {}", gateway, status),
+        Err(_) => println!("Device registry: scanning gateway '{}' timed out", gateway),
+    }
+}
+
+/// Looks up the device addressed by `path` and the client of its gateway.
+fn lookup<'a>(inventory: &Inventory, clients: &'a mut HashMap<String, HomeControlClient<Channel>>, path: &Path) -> Result<(String, &'a mut HomeControlClient<Channel>), String>
+{
+    let address = address_from_path(path).ok_or(format!("Invalid device path '{}'", path))?;
+    let info = inventory.devices.get(&address).ok_or(format!("Unknown device '{}', it was not found by any scan yet", address))?;
+    let client = clients.get_mut(&info.gateway).ok_or(format!("Gateway '{}' of device '{}' is not available", info.gateway, address))?;
+    Ok((address, client))
+}
+
+async fn identify(inventory: &mut Inventory, clients: &mut HashMap<String, HomeControlClient<Channel>>, path: &Path) -> Result<DeviceInfo, String>
+{
+    let (address, client) = lookup(inventory, clients, path)?;
+    let reply = client.identify_device(bus::IdentifyDeviceRequest{device_address: address.clone()}).await
+        .map_err(|status| format!("Identifying device '{}' failed: {}", address, status))?
+        .into_inner();
+    let gateway = inventory.devices[&address].gateway.clone();
+    Ok(inventory.seen(&gateway, &reply.device_address, &reply.device_name, SystemTime::now()))
+}
+
+async fn rename(inventory: &mut Inventory, clients: &mut HashMap<String, HomeControlClient<Channel>>, path: &Path, new_name: &str) -> Result<DeviceInfo, String>
+{
+    let (address, client) = lookup(inventory, clients, path)?;
+    client.set_device_name(bus::SetDeviceNameRequest{device_address: address.clone(), new_device_name: new_name.to_string()}).await
+        .map_err(|status| format!("Renaming device '{}' failed: {}", address, status))?;
+    Ok(inventory.renamed(&address, new_name).unwrap())
+}
+
+async fn readdress(inventory: &mut Inventory, clients: &mut HashMap<String, HomeControlClient<Channel>>, path: &Path, new_address: &str) -> Result<DeviceInfo, String>
+{
+    if device_path(new_address).is_none()
+    {
+        return Err(format!("Address '{}' cannot be used as lake path", new_address));
+    }
+    let (address, client) = lookup(inventory, clients, path)?;
+    if inventory.devices.contains_key(new_address)
+    {
+        return Err(format!("Address '{}' is already used by device '{}'", new_address, inventory.devices[new_address].name));
+    }
+    client.set_device_address(bus::SetDeviceAddressRequest{device_address: address.clone(), new_device_address: new_address.to_string()}).await
+        .map_err(|status| format!("Changing address of device '{}' failed: {}", address, status))?;
+    Ok(inventory.readdressed(&address, new_address).unwrap())
+}
+
+#[test]
+fn test_inventory()
+{
+    let mut inventory = Inventory::default();
+    let t0 = SystemTime::UNIX_EPOCH;
+    let t1 = t0 + Duration::from_secs(10);
+
+    let info = inventory.seen("ug", "5:1", "lamp", t0);
+    assert_eq!(info.first_seen, t0);
+    assert_eq!(info.last_seen, t0);
+
+    let info = inventory.seen("ug", "5:1", "kitchen lamp", t1);
+    assert_eq!(info.name, "kitchen lamp");
+    assert_eq!(info.first_seen, t0);
+    assert_eq!(info.last_seen, t1);
+
+    assert_eq!(inventory.renamed("5:1", "ceiling").unwrap().name, "ceiling");
+    assert!(inventory.renamed("6:1", "ceiling").is_none());
+
+    inventory.seen("ug", "6:1", "switch", t0);
+    assert!(inventory.readdressed("5:1", "6:1").is_none());
+    assert_eq!(inventory.devices["6:1"].name, "switch");
+
+    let info = inventory.readdressed("5:1", "7:1").unwrap();
+    assert_eq!(info.address, "7:1");
+    assert_eq!(info.first_seen, t0);
+    assert!(!inventory.devices.contains_key("5:1"));
+    assert!(inventory.devices.contains_key("7:1"));
+}
+
+#[test]
+fn test_address_from_path()
+{
+    assert_eq!(address_from_path(&"/devices/5:1/rename".parse().unwrap()), Some("5:1".to_string()));
+    assert_eq!(address_from_path(&"/devices/5:1".parse().unwrap()), None);
+}
+
+#[tokio::test]
+async fn unknown_device_request()
+{
+    let datalake = TDataLake::new();
+    let _registry = create(datalake.clone(), DeviceRegistryConfig{enabled: false, ..Default::default()}, &[BusConfig::default()]);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let reply = datalake.request::<String, Result<DeviceInfo, String>>(&"/devices/5:1/rename".parse().unwrap(), "lamp".into()).await;
+    assert!(reply.unwrap().is_err());
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+/// Upper bound of remembered echoes, see `Echoes`
+const MAX_ECHOES: usize = 1000;
+
+/// Messages forwarded by a component which will come back to it, e.g. a value the MQTT bridge
+/// sent to MQTT is received again through the subscription of a bidirectional mapping.
+/// Those are dropped instead of forwarding them again and again.
+pub struct Echoes<T>
+{
+    expected: HashMap<String, Vec<T>>,
+    count: usize,
+}
+
+impl<T: PartialEq> Default for Echoes<T>
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+impl<T: PartialEq> Echoes<T>
+{
+    pub fn new() -> Self
+    {
+        Echoes{expected: HashMap::new(), count: 0}
+    }
+
+    pub fn expect(self: &mut Self, key: String, message: T)
+    {
+        // echoes may be lost, e.g. when the connection breaks
+        if self.count >= MAX_ECHOES
+        {
+            self.expected.clear();
+            self.count = 0;
+        }
+        self.expected.entry(key).or_default().push(message);
+        self.count += 1;
+    }
+
+    /// Returns true (once) for each message passed to `expect`
+    pub fn is_echo(self: &mut Self, key: &str, message: &T) -> bool
+    {
+        let messages = match self.expected.get_mut(key)
+        {
+            Some(messages) => messages,
+            None => return false,
+        };
+        match messages.iter().position(|expected| expected == message)
+        {
+            Some(i) =>
+            {
+                messages.remove(i);
+                if messages.is_empty()
+                {
+                    self.expected.remove(key);
+                }
+                self.count -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[test]
+fn test_echoes()
+{
+    let mut echoes = Echoes::new();
+    echoes.expect("a".into(), 1);
+    echoes.expect("a".into(), 1);
+    assert!(!echoes.is_echo("a", &2));
+    assert!(echoes.is_echo("a", &1));
+    assert!(echoes.is_echo("a", &1));
+    assert!(!echoes.is_echo("a", &1));
+    assert_eq!(echoes.count, 0);
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio_stream::StreamExt;
+use tonic::transport::Channel;
+
+use crate::bus_access::bus;
+use crate::bus_access::bus::home_control_client::HomeControlClient;
+use crate::config::BusConfig;
+use crate::data_lake::*;
+use crate::shutdown::TaskHandle;
+
+/// Lake path below which flashing is controlled and reported:
+/// /flash/campaign                serves `CampaignRequest`s
+/// /flash/<address>/progress      status messages (String) of the gateway while flashing
+/// /flash/<address>/result        `FlashResult` once flashing of the device finished
+pub const FLASH_PATH: &str = "/flash";
+
+/// A firmware image together with the signature of the device type it was built for.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Firmware
+{
+    pub device_signature: Vec<u8>,
+    pub application: Vec<u8>,
+}
+
+/// Remote node behind a PJON device which is flashed via I2C.
+#[derive(Clone, Debug, PartialEq)]
+pub struct I2cTarget
+{
+    pub bus_number: u32,
+    pub device_address: u32,
+}
+
+/// A device to flash.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlashTarget
+{
+    pub address: String,
+
+    /// Gateway the device is connected to, may be omitted if there is only one gateway
+    pub gateway: Option<String>,
+
+    /// Signature the device is known to have. If given, it has to match the firmware's signature.
+    pub expected_signature: Option<Vec<u8>>,
+
+    /// Flash a node attached to the device via I2C instead of the device itself
+    pub i2c: Option<I2cTarget>,
+}
+
+/// Flashes one firmware onto many devices, one after the other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlashCampaign
+{
+    pub firmware: Firmware,
+    pub targets: Vec<FlashTarget>,
+    pub skip_reboot: bool,
+
+    /// Abort the campaign after the first failed device, remaining devices are reported as skipped
+    pub stop_on_error: bool,
+
+    /// Flashing a device fails if the gateway reports no progress for this long
+    pub progress_timeout_milliseconds: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FlashResult
+{
+    pub address: String,
+    pub result: Result<(), String>,
+}
+
+/// Served at /flash/campaign, replies with one `FlashResult` per target in order of the targets.
+pub type CampaignRequest = Request<FlashCampaign, Vec<FlashResult>>;
+
+pub fn create(datalake: TDataLake, gateways: &[BusConfig]) -> TaskHandle
+{
+    let clients = crate::bus_access::home_control_clients(gateways);
+
+    TaskHandle::spawn(|rx| run_flasher(datalake, clients, rx))
+}
+
+/// will run campaigns requested at /flash/campaign, one at a time.
+async fn run_flasher(mut datalake: TDataLake, mut clients: HashMap<String, HomeControlClient<Channel>>, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    let mut campaign_fisher = datalake.subscribe::<CampaignRequest>(&(FLASH_PATH.to_string() + "/campaign").parse().unwrap()).await;
+    loop
+    {
+        tokio::select!
+        {
+            Some(request) = campaign_fisher.receive() =>
+            {
+                let results = run_campaign(&datalake, &mut clients, &request.query).await;
+                request.reply(results);
+            }
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped)
+                break;
+            }
+        }
+    }
+}
+
+/// Checks whether the firmware may be flashed onto the target at all.
+fn verify_signature(firmware: &Firmware, target: &FlashTarget) -> Result<(), String>
+{
+    if firmware.device_signature.is_empty()
+    {
+        return Err("Firmware has no device signature".into());
+    }
+    match &target.expected_signature
+    {
+        Some(expected) if *expected != firmware.device_signature =>
+            Err(format!("Device signature mismatch: device has {:02x?}, firmware is built for {:02x?}", expected, firmware.device_signature)),
+        _ => Ok(())
+    }
+}
+
+fn select_client<'a>(clients: &'a mut HashMap<String, HomeControlClient<Channel>>, target: &FlashTarget) -> Result<&'a mut HomeControlClient<Channel>, String>
+{
+    match &target.gateway
+    {
+        Some(gateway) => clients.get_mut(gateway).ok_or(format!("Unknown gateway '{}'", gateway)),
+        None if clients.len() == 1 => Ok(clients.values_mut().next().unwrap()),
+        None => Err("Gateway needs to be given if more than one gateway is configured".into()),
+    }
+}
+
+async fn run_campaign(datalake: &TDataLake, clients: &mut HashMap<String, HomeControlClient<Channel>>, campaign: &FlashCampaign) -> Vec<FlashResult>
+{
+    let mut results = Vec::new();
+    let mut failed = false;
+    for target in campaign.targets.iter()
+    {
+        let result = if failed && campaign.stop_on_error
+        {
+            Err("Skipped, campaign stopped after previous error".to_string())
+        }
+        else
+        {
+            flash(datalake, clients, campaign, target).await
+        };
+        failed |= result.is_err();
+
+        let result = FlashResult{address: target.address.clone(), result};
+        if let Ok(path) = format!("{}/{}/result", FLASH_PATH, target.address).parse()
+        {
+            datalake.publish_retained(&path, result.clone()).await;
+        }
+        results.push(result);
+    }
+    results
+}
+
+async fn flash(datalake: &TDataLake, clients: &mut HashMap<String, HomeControlClient<Channel>>, campaign: &FlashCampaign, target: &FlashTarget) -> Result<(), String>
+{
+    let firmware = &campaign.firmware;
+    let skip_reboot = campaign.skip_reboot;
+    verify_signature(firmware, target)?;
+    let progress_path: path_tree::Path = format!("{}/{}/progress", FLASH_PATH, target.address).parse()
+        .map_err(|e| format!("Address '{}' cannot be used as lake path: {}", target.address, e))?;
+    let client = select_client(clients, target)?;
+
+    let response = match &target.i2c
+    {
+        None => client.flash(bus::FlashRequest{
+            remote_address: target.address.clone(),
+            device_signature: firmware.device_signature.clone(),
+            application: firmware.application.clone(),
+            skip_reboot,
+        }).await,
+        Some(i2c) => client.flash_via_i2c(bus::FlashViaI2cRequest{
+            remote_address: target.address.clone(),
+            i2c_bus_number: i2c.bus_number,
+            i2c_7bit_device_address: i2c.device_address,
+            device_signature: firmware.device_signature.clone(),
+            application: firmware.application.clone(),
+            skip_reboot,
+        }).await,
+    };
+    let mut states = response.map_err(|status| format!("Flashing failed: {}", status))?.into_inner();
+    let progress_timeout = Duration::from_millis(campaign.progress_timeout_milliseconds as u64);
+    loop
+    {
+        let state = match tokio::time::timeout(progress_timeout, states.next()).await
+        {
+            Ok(Some(state)) => state.map_err(|status| format!("Flashing failed: {}", status))?,
+            Ok(None) => return Ok(()),
+            Err(_) => return Err(format!("Flashing failed: no progress for {} ms", campaign.progress_timeout_milliseconds)),
+        };
+        datalake.publish(&progress_path, state.status_message).await;
+    }
+}
+
+#[test]
+fn test_verify_signature()
+{
+    let firmware = Firmware{device_signature: vec![0x1e, 0x95, 0x0f], application: vec![0; 16]};
+    let mut target = FlashTarget{address: "5:1".into(), gateway: None, expected_signature: None, i2c: None};
+    assert!(verify_signature(&firmware, &target).is_ok());
+
+    target.expected_signature = Some(vec![0x1e, 0x95, 0x0f]);
+    assert!(verify_signature(&firmware, &target).is_ok());
+
+    target.expected_signature = Some(vec![0x1e, 0x95, 0x16]);
+    assert!(verify_signature(&firmware, &target).is_err());
+
+    let unsigned = Firmware{device_signature: Vec::new(), application: vec![0; 16]};
+    assert!(verify_signature(&unsigned, &FlashTarget{expected_signature: None, ..target}).is_err());
+}
+
+#[tokio::test]
+async fn campaign_reports_every_target()
+{
+    let mut datalake = TDataLake::new();
+    let _flasher = create(datalake.clone(), &[BusConfig::default()]);
+    let mut results_fisher = datalake.subscribe::<FlashResult>(&"/flash/*/result".parse().unwrap()).await;
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    let target = FlashTarget{address: "5:1".into(), gateway: None, expected_signature: Some(vec![1]), i2c: None};
+    let campaign = FlashCampaign{
+        firmware: Firmware{device_signature: vec![2], application: vec![0; 16]},
+        targets: vec![target.clone(), FlashTarget{address: "6:1".into(), ..target}],
+        skip_reboot: false,
+        stop_on_error: true,
+        progress_timeout_milliseconds: 1000,
+    };
+    let results = datalake.request::<FlashCampaign, Vec<FlashResult>>(&"/flash/campaign".parse().unwrap(), campaign).await.unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(results[0].result.as_ref().unwrap_err().contains("mismatch"));
+    assert!(results[1].result.as_ref().unwrap_err().contains("Skipped"));
+
+    assert_eq!(results_fisher.receive().await.unwrap().address, "5:1");
+    assert_eq!(results_fisher.receive().await.unwrap().address, "6:1");
+}
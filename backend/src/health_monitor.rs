@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+use tonic::transport::Channel;
+
+use crate::bus_access::bus;
+use crate::bus_access::bus::debug_client::DebugClient;
+use crate::config::{BusConfig, HealthMonitorConfig};
+use crate::data_lake::*;
+use crate::shutdown::TaskHandle;
+
+/// Lake path below which a `GatewayHealth` is published per gateway: /system/gateways/<name>
+pub const GATEWAYS_STATUS_PATH: &str = "/system/gateways";
+
+/// Lake path below which a `GatewayAlert` is published whenever a gateway becomes
+/// unreachable or reachable again: /system/alerts/gateways/<name>
+pub const GATEWAYS_ALERT_PATH: &str = "/system/alerts/gateways";
+
+/// Result of probing a gateway.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GatewayHealth
+{
+    pub gateway: String,
+    pub reachable: bool,
+    /// Round trip time of the probe, None if unreachable
+    pub latency: Option<Duration>,
+    /// Version of the gateway software, None if unreachable
+    pub version: Option<bus::VersionInformation>,
+    pub error: Option<String>,
+    pub checked_at: SystemTime,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GatewayAlert
+{
+    pub gateway: String,
+    pub reachable: bool,
+    pub message: String,
+}
+
+/// Returns an alert if reachability changed in a way worth raising one.
+/// A gateway unreachable from the start raises an alert, one reachable from the start does not.
+fn alert_for_transition(gateway: &str, previously_reachable: Option<bool>, health: &GatewayHealth) -> Option<GatewayAlert>
+{
+    match (previously_reachable, health.reachable)
+    {
+        (None | Some(true), false) => Some(GatewayAlert{
+            gateway: gateway.to_string(),
+            reachable: false,
+            message: format!("Gateway '{}' is unreachable: {}", gateway, health.error.clone().unwrap_or_default()),
+        }),
+        (Some(false), true) => Some(GatewayAlert{
+            gateway: gateway.to_string(),
+            reachable: true,
+            message: format!("Gateway '{}' is reachable again", gateway),
+        }),
+        _ => None
+    }
+}
+
+pub fn create(datalake: TDataLake, config: HealthMonitorConfig, gateways: &[BusConfig]) -> TaskHandle
+{
+    let clients = crate::bus_access::gateway_channels(gateways).into_iter()
+        .map(|(name, channel)| (name, DebugClient::new(channel)))
+        .collect();
+
+    TaskHandle::spawn(|rx| run_monitor(datalake, config, clients, rx))
+}
+
+async fn probe(gateway: &str, client: &mut DebugClient<Channel>, timeout: Duration) -> GatewayHealth
+{
+    let start = Instant::now();
+    let result = tokio::time::timeout(timeout, client.get_rscom_version(bus::Empty{})).await;
+    let latency = start.elapsed();
+    let (reachable, latency, version, error) = match result
+    {
+        Ok(Ok(response)) => (true, Some(latency), Some(response.into_inner()), None),
+        Ok(Err(status)) => (false, None, None, Some(status.to_string())),
+        Err(_) => (false, None, None, Some(format!("no answer within {:?}", timeout))),
+    };
+    GatewayHealth{gateway: gateway.to_string(), reachable, latency, version, error, checked_at: SystemTime::now()}
+}
+
+/// will probe every gateway each `interval_seconds` and publish the result to /system/gateways/<name>
+/// will publish a `GatewayAlert` to /system/alerts/gateways/<name> on reachability changes
+async fn run_monitor(datalake: TDataLake, config: HealthMonitorConfig, mut clients: HashMap<String, DebugClient<Channel>>, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    let mut reachability: HashMap<String, bool> = HashMap::new();
+    let timeout = Duration::from_millis(config.timeout_milliseconds);
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_seconds));
+    loop
+    {
+        tokio::select!
+        {
+            _ = interval.tick(), if config.enabled =>
+            {
+                for (gateway, client) in clients.iter_mut()
+                {
+                    let health = probe(gateway, client, timeout).await;
+                    if let Some(alert) = alert_for_transition(gateway, reachability.get(gateway).copied(), &health)
+                    {
+                        println!("{}", alert.message);
+                        datalake.publish_retained(&format!("{}/{}", GATEWAYS_ALERT_PATH, gateway).parse().unwrap(), alert).await;
+                    }
+                    reachability.insert(gateway.clone(), health.reachable);
+                    datalake.publish_retained(&format!("{}/{}", GATEWAYS_STATUS_PATH, gateway).parse().unwrap(), health).await;
+                }
+            }
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped)
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_alert_for_transition()
+{
+    let reachable = GatewayHealth{gateway: "ug".into(), reachable: true, latency: Some(Duration::from_millis(3)), version: None, error: None, checked_at: SystemTime::UNIX_EPOCH};
+    let unreachable = GatewayHealth{reachable: false, latency: None, error: Some("connection refused".into()), ..reachable.clone()};
+
+    assert!(alert_for_transition("ug", None, &reachable).is_none());
+    assert!(alert_for_transition("ug", Some(true), &reachable).is_none());
+    assert!(alert_for_transition("ug", Some(false), &unreachable).is_none());
+
+    let alert = alert_for_transition("ug", None, &unreachable).unwrap();
+    assert!(!alert.reachable);
+    assert!(alert.message.contains("connection refused"));
+    assert!(!alert_for_transition("ug", Some(true), &unreachable).unwrap().reachable);
+    assert!(alert_for_transition("ug", Some(false), &reachable).unwrap().reachable);
+}
+
+#[tokio::test]
+async fn unreachable_gateway_raises_alert()
+{
+    let mut datalake = TDataLake::new();
+    let mut alerts = datalake.subscribe::<GatewayAlert>(&(GATEWAYS_ALERT_PATH.to_string() + "/*").parse().unwrap()).await;
+    let mut status = datalake.subscribe::<GatewayHealth>(&(GATEWAYS_STATUS_PATH.to_string() + "/test").parse().unwrap()).await;
+
+    // nothing listens on port 9 (discard) of localhost
+    let gateway = BusConfig{name: "test".into(), gateway_url: "http://127.0.0.1:9".into(), ..Default::default()};
+    let _monitor = create(datalake.clone(), HealthMonitorConfig{timeout_milliseconds: 500, ..Default::default()}, &[gateway]);
+
+    let alert = alerts.receive().await.unwrap();
+    assert_eq!(alert.gateway, "test");
+    assert!(!alert.reachable);
+    let health = status.receive().await.unwrap();
+    assert!(!health.reachable);
+    assert!(health.latency.is_none());
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tonic::transport::Channel;
+
+use crate::bus_access::bus;
+use crate::bus_access::bus::home_control_client::HomeControlClient;
+use crate::config::{BusConfig, I2cDecoding, I2cPollConfig};
+use crate::data_lake::*;
+use crate::shutdown::TaskHandle;
+use crate::data_lake::path_tree::{Path, PathElement};
+
+/// Lake path below which I2C transactions are served: /i2c/<device>/<bus>/<addr>
+pub const I2C_PATH: &str = "/i2c";
+
+/// Highest 7 bit I2C address.
+pub const MAX_ADDRESS: u32 = 0x7f;
+
+/// Bytes to write to a peripheral, followed by reading `read` bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct I2cTransaction
+{
+    pub write: Vec<u8>,
+    pub read: u32,
+}
+
+/// Served at /i2c/<device>/<bus>/<addr>, replies with the bytes read.
+/// <addr> is the 7 bit address of the peripheral, decimal or hex with "0x" prefix.
+pub type I2cRequest = Request<I2cTransaction, Result<Vec<u8>, String>>;
+
+/// Number of bytes needed to decode a value.
+pub fn decoded_size(decoding: I2cDecoding) -> usize
+{
+    use I2cDecoding::*;
+    match decoding
+    {
+        Raw => 0,
+        U8 | I8 => 1,
+        U16Be | U16Le | I16Be | I16Le => 2,
+        U32Be | U32Le | I32Be | I32Le => 4,
+    }
+}
+
+/// Decodes the first bytes of `data` as number. Not applicable to `I2cDecoding::Raw`.
+fn decode(decoding: I2cDecoding, data: &[u8]) -> Option<f64>
+{
+    use I2cDecoding::*;
+    let size = decoded_size(decoding);
+    if size == 0 || data.len() < size
+    {
+        return None;
+    }
+    let value = match decoding
+    {
+        Raw => return None,
+        U8 => data[0] as f64,
+        I8 => data[0] as i8 as f64,
+        U16Be => u16::from_be_bytes([data[0], data[1]]) as f64,
+        U16Le => u16::from_le_bytes([data[0], data[1]]) as f64,
+        I16Be => i16::from_be_bytes([data[0], data[1]]) as f64,
+        I16Le => i16::from_le_bytes([data[0], data[1]]) as f64,
+        U32Be => u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as f64,
+        U32Le => u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as f64,
+        I32Be => i32::from_be_bytes([data[0], data[1], data[2], data[3]]) as f64,
+        I32Le => i32::from_le_bytes([data[0], data[1], data[2], data[3]]) as f64,
+    };
+    Some(value)
+}
+
+fn parse_number(s: &str) -> Option<u32>
+{
+    match s.strip_prefix("0x")
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok()
+    }
+}
+
+/// Extracts (<device>, <bus>, <addr>) from /i2c/<device>/<bus>/<addr>
+fn target_from_path(path: &Path) -> Option<(String, u32, u32)>
+{
+    match path.elements()
+    {
+        [PathElement::Root, PathElement::Name(_), PathElement::Name(device), PathElement::Name(bus), PathElement::Name(address)] =>
+            Some((device.clone(), parse_number(bus)?, parse_number(address).filter(|address| *address <= MAX_ADDRESS)?)),
+        _ => None
+    }
+}
+
+pub fn create(datalake: TDataLake, polls: Vec<I2cPollConfig>, gateways: &[BusConfig]) -> TaskHandle
+{
+    let bridge = I2cBridge{clients: crate::bus_access::home_control_clients(gateways), gateways: gateways.to_vec()};
+
+    TaskHandle::spawn(|rx| run_bridge(datalake, bridge, polls, rx))
+}
+
+struct I2cBridge
+{
+    clients: HashMap<String, HomeControlClient<Channel>>,
+    gateways: Vec<BusConfig>,
+}
+
+impl I2cBridge
+{
+    async fn transfer(self: &mut Self, device: &str, bus_number: u32, address: u32, transaction: &I2cTransaction) -> Result<Vec<u8>, String>
+    {
+        let gateway = crate::bus_access::gateway_of(&self.gateways, device).ok_or(format!("No gateway owns device '{}'", device))?;
+        let client = self.clients.get_mut(&gateway.name).ok_or(format!("Gateway '{}' is not available", gateway.name))?;
+        let reply = client.i2c_via_pjon(bus::I2cViaPjonRequest{
+            remote_address: crate::bus_access::remote_address_of(device),
+            i2c_bus_number: bus_number,
+            i2c_7bit_device_address: address,
+            data_write: transaction.write.clone(),
+            num_bytes_read: transaction.read,
+        }).await.map_err(|status| format!("I2C transaction with device '{}' failed: {}", device, status))?;
+        Ok(reply.into_inner().data_read)
+    }
+
+    async fn poll(self: &mut Self, datalake: &TDataLake, poll: &I2cPollConfig, path: &Path)
+    {
+        let transaction = I2cTransaction{write: poll.write.clone(), read: poll.read};
+        match self.transfer(&poll.device, poll.bus, poll.address, &transaction).await
+        {
+            Ok(data) if poll.decode == I2cDecoding::Raw => datalake.publish_retained(path, Value::Binary(data)).await,
+            Ok(data) => match decode(poll.decode, &data)
+            {
+                Some(value) => datalake.publish_retained(path, Value::Float(value * poll.scale)).await,
+                None => println!("I2C poll for {}: received {} bytes, too few for {:?}", poll.path, data.len(), poll.decode)
+            },
+            Err(e) => println!("I2C poll for {}: {}", poll.path, e)
+        }
+    }
+}
+
+/// will serve `I2cRequest`s at /i2c/<device>/<bus>/<addr>
+/// will poll the configured registers and publish the decoded values
+async fn run_bridge(mut datalake: TDataLake, mut bridge: I2cBridge, polls: Vec<I2cPollConfig>, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    let mut request_fisher = datalake.subscribe_with_path::<I2cRequest>(&(I2C_PATH.to_string() + "/*/*/*").parse().unwrap()).await;
+
+    // paths were validated with the configuration
+    let poll_paths: Vec<Path> = polls.iter().map(|poll| poll.path.parse().unwrap()).collect();
+    let now = tokio::time::Instant::now();
+    let mut next_polls: Vec<tokio::time::Instant> = polls.iter().map(|_| now).collect();
+    loop
+    {
+        let next_poll = next_polls.iter().enumerate().min_by_key(|(_, due)| **due).map(|(i, due)| (i, *due));
+        tokio::select!
+        {
+            _ = tokio::time::sleep_until(next_poll.map(|(_, due)| due).unwrap_or(now)), if next_poll.is_some() =>
+            {
+                let (i, due) = next_poll.unwrap();
+                bridge.poll(&datalake, &polls[i], &poll_paths[i]).await;
+                // do not catch up on missed polls
+                next_polls[i] = std::cmp::max(due + Duration::from_millis(polls[i].interval_milliseconds), tokio::time::Instant::now());
+            }
+            Some((path, request)) = request_fisher.receive() =>
+            {
+                let result = match target_from_path(&path)
+                {
+                    Some((device, bus_number, address)) => bridge.transfer(&device, bus_number, address, &request.query).await,
+                    None => Err(format!("Invalid I2C path '{}', expected {}/<device>/<bus>/<addr>", path, I2C_PATH))
+                };
+                request.reply(result);
+            }
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped)
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_decode()
+{
+    assert_eq!(decode(I2cDecoding::U8, &[0xff]), Some(255.0));
+    assert_eq!(decode(I2cDecoding::I8, &[0xff]), Some(-1.0));
+    assert_eq!(decode(I2cDecoding::U16Be, &[0x01, 0x02]), Some(258.0));
+    assert_eq!(decode(I2cDecoding::U16Le, &[0x01, 0x02]), Some(513.0));
+    assert_eq!(decode(I2cDecoding::I16Be, &[0xff, 0xfe]), Some(-2.0));
+    assert_eq!(decode(I2cDecoding::I32Le, &[0xfe, 0xff, 0xff, 0xff]), Some(-2.0));
+    assert_eq!(decode(I2cDecoding::U32Be, &[0, 0, 1, 0, 7]), Some(256.0));
+    assert_eq!(decode(I2cDecoding::U16Be, &[0x01]), None);
+    assert_eq!(decode(I2cDecoding::Raw, &[0x01]), None);
+}
+
+#[test]
+fn test_target_from_path()
+{
+    assert_eq!(target_from_path(&"/i2c/5/0/0x48".parse().unwrap()), Some(("5".to_string(), 0, 0x48)));
+    assert_eq!(target_from_path(&"/i2c/5/1/72".parse().unwrap()), Some(("5".to_string(), 1, 72)));
+    assert_eq!(target_from_path(&"/i2c/5/1/sensor".parse().unwrap()), None);
+    assert_eq!(target_from_path(&"/i2c/5/1/0x80".parse().unwrap()), None);
+    assert_eq!(target_from_path(&"/i2c/5/1".parse().unwrap()), None);
+}
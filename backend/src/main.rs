@@ -1,150 +1,88 @@
 pub mod data_lake;
+pub mod config;
+pub mod bus_access;
+pub mod device_registry;
+pub mod device_model;
+pub mod flashing;
+pub mod health_monitor;
+pub mod i2c_bridge;
+pub mod shutdown;
+pub mod persistence;
+pub mod components;
+pub mod api;
+pub mod mqtt_bridge;
+pub mod echoes;
+pub mod automations;
+pub mod scheduler;
+pub mod scenes;
+pub mod scripting;
+pub mod reload;
 use data_lake::*;
-//use tokio_stream::stream_ext::StreamExt;
-//use bus::MessagePackBusAccess;
-//use bus::ConnectionSetup;
 
 
-//use futures::stream::Stream;
-
-
-mod bus_access
+#[tokio::main]
+async fn main()
 {
-    use regex::Regex;
-    pub mod bus {
-        tonic::include_proto!("_");
-    }
-    use crate::data_lake::*;
-    use tokio_stream::StreamExt;
-
-    pub struct TransmitRequest
-    {
-        device_id : String,
-        json_payload : String
-    }
-
-    pub struct BusAccessHandle
+    // usage: homecentral_backend [--check-config] [<configuration file>]
+    let mut check_only = false;
+    let mut config_path = "homecentral.toml".to_string();
+    for argument in std::env::args().skip(1)
     {
-        stop_sender: Option<tokio::sync::oneshot::Sender<()>>,
-        join_handle: Option<tokio::task::JoinHandle::<()>>,
+        match argument.as_str()
+        {
+            "--check-config" => check_only = true,
+            _ => config_path = argument,
+        }
     }
-
-    // TODO: use `signal-hook` crate to catch signals and cleanly exit the main() function in order
-    // to utilize drop here...
-    impl Drop for BusAccessHandle
+    let config = match config::Config::load(&config_path)
     {
-        fn drop(self: &mut Self)
+        Ok(config) => config,
+        Err(e) =>
         {
-            println!("DROP!!");
-            self.stop_sender.take().unwrap().send(()).unwrap();
-            tokio::runtime::Handle::try_current().unwrap().block_on(self.join_handle.take().unwrap()).unwrap();
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
-    }
-
-    pub fn create(datalake: TDataLake, server_url: String, datalake_base_path: String) -> BusAccessHandle
+    };
+    if check_only
     {
-        let (tx, rx) = tokio::sync::oneshot::channel();
-        let join_handle = tokio::task::spawn(
-            receive_from_bus_and_publish(datalake, server_url, datalake_base_path, rx)
-        );
-
-        BusAccessHandle{stop_sender: Some(tx), join_handle: Some(join_handle)}
+        println!("Configuration '{}' is valid: {} gateway(s), {} device(s), {} automation(s)",
+            config_path, config.gateways.len(), config.gateways.iter().map(|gateway| gateway.devices.len()).sum::<usize>(), config.automations.len());
+        return;
     }
 
-    /// will publish received bus messages to <datalake_base_path>/rx/<device_id>
-    /// will send all messages to the bus which are published to <datalake_base_path>/tx
-    async fn receive_from_bus_and_publish(mut datalake: TDataLake, server_url: String, datalake_base_path: String, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
-    {
-        let mut client = bus::message_pack_bus_access_client::MessagePackBusAccessClient::connect(server_url).await.unwrap();
-
-        let req = bus::ConnectionSetup {
-            rx_mask: "0:255".into(),
-            remote_address: "0:1".into(),
-        };
-        // receive from bus (data to send to data lake):
-        let response = client.receive(req).await.unwrap();
-        let mut resp_stream = response.into_inner();
+    let datalake = TDataLake::new();
 
-        // receive from lake (data to send to bus)
-        let mut fisher = datalake.subscribe::<TransmitRequest>(&((datalake_base_path.clone() + "tx").as_str().parse().unwrap())).await;
-        
-        loop
+    if let Some(persistence_path) = &config.lake.persistence_path
+    {
+        match persistence::restore(&datalake, persistence_path).await
         {
-            tokio::select!
-            {
-                grpc_event = resp_stream.next() =>
-                {
-                    if let Some(received) = grpc_event
-                    {
-                        let received = received.unwrap();
-                        println!("\treceived message: `{:?}`", received);
-
-                        let re = Regex::new(r".*([0-9]+):.*").unwrap();
-                        let captures = re.captures(received.remote_address.as_str());
-                        if let Some(captures) = captures 
-                        {
-                            if let Some(device_id) = captures.get(1)
-                            {
-                                datalake.publish(&(datalake_base_path.clone() + "rx/" + device_id.as_str()).as_str().parse().unwrap(), received.data).await;
-                            }
-                        }
-                    }
-                    else 
-                    {
-                        // TODO reconnect here
-                        break;
-                    }
-                }
-                Some(transmit_request) = fisher.receive() =>
-                {
-                    let req = bus::SendJsonMessageRequest {
-                        remote_address: transmit_request.device_id + ":1",
-                        data: transmit_request.json_payload,
-                        timeout_milliseconds: 5000
-                    };
-                    let result = client.send(req).await;
-                    // TODO: this will block the select?
-                    // handle result
-                }
-                _ = &mut stop_receiver =>
-                {
-                    // Quit
-                    break;
-                }
-            }
+            Ok(count) => println!("Restored {} values from '{}'", count, persistence_path),
+            Err(e) => println!("{}", e),
         }
     }
-}
 
-#[tokio::main]
-async fn main() -> ()
-{
-    let mut datalake = TDataLake::new();
-
-    let _bus_handle = bus_access::create(datalake.clone(), "http://192.168.0.200:50051".into(), "/bus/ug".into());
-
-
-    let mut sub = datalake.subscribe::<String>(&"/bus/rx/*".parse().unwrap()).await;
+    let mut components = components::Components::start(datalake.clone(), config);
+    let mut reload_trigger = reload::ReloadTrigger::new(&config_path);
+    let signal = shutdown::wait_for_signal();
+    tokio::pin!(signal);
     loop
     {
         tokio::select!
         {
-            data = sub.receiver.recv() =>
+            trigger = reload_trigger.wait() =>
             {
-                println!("rx: {}", data.unwrap());
+                reload::reload(&datalake, &config_path, &mut components, trigger).await;
             }
-            s = tokio::signal::ctrl_c() =>
+            signal = &mut signal =>
             {
-                s.unwrap();
-                println!("ctrl-c received!");
+                println!("{} received, shutting down", signal);
                 break;
             }
         }
     }
 
-    //bus_handle.stop();
-
-    //join1.await.unwrap();
+    let snapshot_path = components.config().shutdown.snapshot_path.clone();
+    components.into_coordinator().shutdown(&datalake, snapshot_path.as_deref()).await;
 }
 
 #[tokio::test]
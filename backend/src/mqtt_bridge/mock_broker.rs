@@ -0,0 +1,203 @@
+//! In-process MQTT 3.1.1 broker, for tests.
+//! Supports just what the bridge needs: connect, subscribe, publish with QoS 0/1, retained
+//! messages and pings. Tests inject messages "from other clients" and inspect what was published.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use rumqttc::mqttbytes::v4::*;
+use rumqttc::mqttbytes::{Error, QoS};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+
+type Subscriber = (Arc<Mutex<Vec<String>>>, tokio::sync::mpsc::UnboundedSender<Publish>);
+
+#[derive(Default)]
+struct BrokerState
+{
+    retained: BTreeMap<String, Publish>,
+    subscribers: Vec<Subscriber>,
+    /// Messages published by the clients
+    published: Vec<Publish>,
+}
+
+impl BrokerState
+{
+    fn route(self: &mut Self, publish: &Publish)
+    {
+        if publish.retain
+        {
+            match publish.payload.is_empty()
+            {
+                true => self.retained.remove(&publish.topic),
+                false => self.retained.insert(publish.topic.clone(), publish.clone()),
+            };
+        }
+        // live messages are delivered without retain flag
+        let mut message = Publish::from_bytes(publish.topic.clone(), QoS::AtMostOnce, publish.payload.clone());
+        message.retain = false;
+        self.subscribers.retain(|(filters, sender)|
+        {
+            if filters.lock().unwrap().iter().any(|filter| rumqttc::matches(&publish.topic, filter))
+            {
+                return sender.send(message.clone()).is_ok();
+            }
+            !sender.is_closed()
+        });
+    }
+}
+
+pub struct MockBroker
+{
+    /// "host:port" to connect to
+    pub address: String,
+    state: Arc<Mutex<BrokerState>>,
+    _shutdown: tokio::sync::oneshot::Sender<()>,
+}
+
+impl MockBroker
+{
+    pub async fn start() -> Self
+    {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let state = Arc::new(Mutex::new(BrokerState::default()));
+        let (shutdown, mut shutdown_receiver) = tokio::sync::oneshot::channel();
+        let accept_state = state.clone();
+        tokio::task::spawn(async move {
+            loop
+            {
+                tokio::select!
+                {
+                    Ok((stream, _)) = listener.accept() =>
+                    {
+                        tokio::task::spawn(serve_client(stream, accept_state.clone()));
+                    }
+                    _ = &mut shutdown_receiver => break,
+                }
+            }
+        });
+        MockBroker{address, state, _shutdown: shutdown}
+    }
+
+    /// Publishes as some other client would
+    pub fn publish(self: &Self, topic: &str, payload: &[u8], retain: bool)
+    {
+        let mut publish = Publish::new(topic, QoS::AtMostOnce, payload);
+        publish.retain = retain;
+        self.state.lock().unwrap().route(&publish);
+    }
+
+    /// Waits until the clients subscribed at least `count` filters in total.
+    pub async fn wait_for_subscriptions(self: &Self, count: usize)
+    {
+        self.wait_for(|state| state.subscribers.iter().map(|(filters, _)| filters.lock().unwrap().len()).sum::<usize>() >= count).await;
+    }
+
+    /// Waits until the clients published at least `count` messages, returns all of them.
+    pub async fn wait_for_published(self: &Self, count: usize) -> Vec<Publish>
+    {
+        self.wait_for(|state| state.published.len() >= count).await;
+        self.state.lock().unwrap().published.clone()
+    }
+
+    /// Polls the state until `condition` holds, panics after 5s.
+    async fn wait_for(self: &Self, condition: impl Fn(&BrokerState) -> bool)
+    {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while !condition(&self.state.lock().unwrap())
+        {
+            if tokio::time::Instant::now() > deadline
+            {
+                panic!("Mock broker: timeout waiting for condition");
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+
+/// Answers one packet of a client, returns false if the connection is to be closed.
+fn handle_packet(packet: Packet, state: &Mutex<BrokerState>, filters: &Mutex<Vec<String>>, sender: &tokio::sync::mpsc::UnboundedSender<Publish>, output: &mut BytesMut) -> bool
+{
+    let result = match packet
+    {
+        Packet::Connect(_) => ConnAck::new(ConnectReturnCode::Success, false).write(output),
+        Packet::Subscribe(subscribe) =>
+        {
+            let codes = subscribe.filters.iter().map(|_| SubscribeReasonCode::Success(QoS::AtMostOnce)).collect();
+            let result = SubAck::new(subscribe.pkid, codes).write(output);
+            let state = state.lock().unwrap();
+            for filter in subscribe.filters
+            {
+                for retained in state.retained.values().filter(|retained| rumqttc::matches(&retained.topic, &filter.path))
+                {
+                    let _ = sender.send(retained.clone());
+                }
+                filters.lock().unwrap().push(filter.path);
+            }
+            result
+        }
+        Packet::Publish(publish) =>
+        {
+            let mut state = state.lock().unwrap();
+            state.published.push(publish.clone());
+            state.route(&publish);
+            match publish.qos
+            {
+                QoS::AtMostOnce => Ok(0),
+                _ => PubAck::new(publish.pkid).write(output),
+            }
+        }
+        Packet::PingReq => PingResp.write(output),
+        Packet::Disconnect => return false,
+        _ => Ok(0),
+    };
+    result.is_ok()
+}
+
+async fn serve_client(mut stream: tokio::net::TcpStream, state: Arc<Mutex<BrokerState>>)
+{
+    let filters = Arc::new(Mutex::new(Vec::new()));
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    state.lock().unwrap().subscribers.push((filters.clone(), sender.clone()));
+
+    let mut input = BytesMut::new();
+    let mut output = BytesMut::new();
+    loop
+    {
+        loop
+        {
+            match read(&mut input, MAX_PACKET_SIZE)
+            {
+                Ok(packet) => if !handle_packet(packet, &state, &filters, &sender, &mut output)
+                {
+                    return;
+                },
+                Err(Error::InsufficientBytes(_)) => break,
+                Err(_) => return,
+            }
+        }
+        if !output.is_empty() && stream.write_all(&output.split()).await.is_err()
+        {
+            return;
+        }
+        tokio::select!
+        {
+            read = stream.read_buf(&mut input) => match read
+            {
+                Ok(0) | Err(_) => return,
+                Ok(_) => (),
+            },
+            Some(publish) = receiver.recv() =>
+            {
+                if publish.write(&mut output).is_err()
+                {
+                    return;
+                }
+            }
+        }
+    }
+}
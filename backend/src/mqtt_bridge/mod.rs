@@ -0,0 +1,399 @@
+//! Mirrors lake subtrees to and from topics of an external MQTT broker.
+//! Values are sent as JSON, received payloads which are no JSON become strings (or binary).
+
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+
+use crate::config::{MqttBridgeConfig, MqttDirection, MqttMappingConfig};
+use crate::data_lake::*;
+use crate::data_lake::path_tree::{Path, PathTree};
+use crate::echoes::Echoes;
+use crate::shutdown::TaskHandle;
+
+#[cfg(test)]
+pub mod mock_broker;
+
+/// Requests queued for the MQTT event loop
+const REQUEST_CAPACITY: usize = 256;
+/// Wait time before connecting again after the connection failed
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Splits "host:port".
+pub fn parse_broker(broker: &str) -> Result<(String, u16), String>
+{
+    let (host, port) = broker.rsplit_once(':')
+        .ok_or(format!("'{}' is not of the form host:port", broker))?;
+    let port = port.parse::<u16>().map_err(|e| format!("invalid port '{}': {}", port, e))?;
+    if host.is_empty()
+    {
+        return Err(format!("'{}' has no host", broker));
+    }
+    Ok((host.to_string(), port))
+}
+
+fn join_topic(prefix: &str, rest: &str) -> String
+{
+    match (prefix.is_empty(), rest.is_empty())
+    {
+        (true, _) => rest.to_string(),
+        (_, true) => prefix.to_string(),
+        _ => format!("{}/{}", prefix, rest),
+    }
+}
+
+/// Translation between lake paths and MQTT topics of one `MqttMappingConfig`.
+pub struct TopicMapping
+{
+    pattern: Path,
+    /// lake path before the first wildcard, "" for the root
+    lake_prefix: String,
+    topic_prefix: String,
+    /// MQTT subscription corresponding to `pattern`
+    filter: String,
+    direction: MqttDirection,
+    retain: bool,
+}
+
+impl TopicMapping
+{
+    pub fn new(config: &MqttMappingConfig) -> Result<Self, String>
+    {
+        let pattern: Path = config.lake.parse().map_err(|e| format!("lake: {}", e))?;
+        let elements: Vec<&str> = config.lake.split('/').filter(|element| !element.is_empty()).collect();
+        let fixed = elements.iter().take_while(|element| !element.starts_with('*')).count();
+        let mut filter_tail = Vec::new();
+        for (i, element) in elements.iter().enumerate()
+        {
+            if element.contains(['+', '#'])
+            {
+                return Err(format!("lake: '{}' contains characters reserved by MQTT", element));
+            }
+            filter_tail.push(match *element
+            {
+                _ if i < fixed => element,
+                "*" => "+",
+                "**" if i == elements.len() - 1 => "#",
+                _ if element.starts_with('*') => return Err(format!("lake: wildcard '{}' cannot be expressed in MQTT, only '*' and a trailing '**' can", element)),
+                _ => element,
+            });
+        }
+        let topic_prefix = config.topic.clone().unwrap_or(elements[..fixed].join("/"));
+        if topic_prefix.contains(['+', '#'])
+        {
+            return Err(format!("topic: '{}' must not contain wildcards", topic_prefix));
+        }
+        let filter = join_topic(&topic_prefix, &filter_tail[fixed..].join("/"));
+        if !rumqttc::valid_filter(&filter)
+        {
+            return Err(format!("'{}' is no valid MQTT topic filter", filter));
+        }
+        Ok(TopicMapping{
+            pattern,
+            lake_prefix: elements[..fixed].iter().map(|element| format!("/{}", element)).collect(),
+            topic_prefix,
+            filter,
+            direction: config.direction,
+            retain: config.retain,
+        })
+    }
+
+    /// Topic of a lake path matching `pattern`
+    fn topic_of(self: &Self, path: &Path) -> String
+    {
+        let path = path.to_string();
+        let rest = path.strip_prefix(&self.lake_prefix).unwrap_or(&path);
+        join_topic(&self.topic_prefix, rest.trim_start_matches('/'))
+    }
+
+    /// Lake path of a topic matching `filter`, None if the topic is no valid lake path
+    fn path_of(self: &Self, topic: &str) -> Option<Path>
+    {
+        let rest = match topic.strip_prefix(&self.topic_prefix)
+        {
+            Some(rest) if self.topic_prefix.is_empty() => rest,
+            Some("") => "",
+            Some(rest) => rest.strip_prefix('/')?,
+            None => return None,
+        };
+        let path = match rest.is_empty()
+        {
+            true if self.lake_prefix.is_empty() => "/".to_string(),
+            true => self.lake_prefix.clone(),
+            false => format!("{}/{}", self.lake_prefix, rest),
+        };
+        path.parse().ok()
+    }
+
+    fn to_mqtt(self: &Self) -> bool
+    {
+        self.direction != MqttDirection::FromMqtt
+    }
+
+    fn from_mqtt(self: &Self) -> bool
+    {
+        self.direction != MqttDirection::ToMqtt
+    }
+}
+
+/// Turns a received MQTT payload into a value, payloads which are no JSON are taken as text
+pub fn decode_payload(payload: &[u8]) -> Value
+{
+    match std::str::from_utf8(payload)
+    {
+        Ok(text) => Value::from_json(text).unwrap_or(Value::String(text.to_string())),
+        Err(_) => Value::Binary(payload.to_vec()),
+    }
+}
+
+/// Connects to the configured broker and forwards values according to the mappings until stopped.
+pub fn create(datalake: TDataLake, config: MqttBridgeConfig) -> TaskHandle
+{
+    TaskHandle::spawn(|rx| run_bridge(datalake, config, rx))
+}
+
+async fn run_bridge(mut datalake: TDataLake, config: MqttBridgeConfig, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    // validated with the configuration
+    let mappings: Vec<TopicMapping> = config.mappings.iter().map(|mapping| TopicMapping::new(mapping).unwrap()).collect();
+    let (host, port) = parse_broker(&config.broker).unwrap();
+
+    let mut options = MqttOptions::new(&config.client_id, host, port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = &config.username
+    {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+
+    // lake paths forwarded to MQTT, to tell which values published to the lake will come back
+    let mut to_mqtt_patterns = PathTree::new();
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut forwarders = Vec::new();
+    for (i, mapping) in mappings.iter().enumerate().filter(|(_, mapping)| mapping.to_mqtt())
+    {
+        to_mqtt_patterns.add_payload(&mapping.pattern, i);
+        let mut fisher = datalake.subscribe_with_path::<Value>(&mapping.pattern).await;
+        let sender = sender.clone();
+        forwarders.push(tokio::task::spawn(async move {
+            while let Some((path, value)) = fisher.receive().await
+            {
+                if sender.send((i, path, value)).is_err()
+                {
+                    break;
+                }
+            }
+        }));
+    }
+
+    let mut mqtt_echoes = Echoes::new();
+    let mut lake_echoes = Echoes::new();
+    loop
+    {
+        tokio::select!
+        {
+            event = eventloop.poll() =>
+            {
+                match event
+                {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) =>
+                    {
+                        println!("MQTT bridge: connected to {}", config.broker);
+                        // subscriptions do not survive a reconnect (clean session)
+                        for mapping in mappings.iter().filter(|mapping| mapping.from_mqtt())
+                        {
+                            if let Err(e) = client.try_subscribe(&mapping.filter, QoS::AtLeastOnce)
+                            {
+                                println!("MQTT bridge: cannot subscribe to '{}': {}", mapping.filter, e);
+                            }
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) =>
+                    {
+                        if mqtt_echoes.is_echo(&publish.topic, &publish.payload.to_vec())
+                        {
+                            continue;
+                        }
+                        let mapping = match mappings.iter().find(|mapping| mapping.from_mqtt() && rumqttc::matches(&publish.topic, &mapping.filter))
+                        {
+                            Some(mapping) => mapping,
+                            None => continue,
+                        };
+                        let path = match mapping.path_of(&publish.topic)
+                        {
+                            Some(path) => path,
+                            None =>
+                            {
+                                println!("MQTT bridge: topic '{}' is no valid lake path", publish.topic);
+                                continue;
+                            }
+                        };
+                        let value = decode_payload(&publish.payload);
+                        if !to_mqtt_patterns.get_payloads(&path).is_empty()
+                        {
+                            lake_echoes.expect(path.to_string(), value.clone());
+                        }
+                        match mapping.retain || publish.retain
+                        {
+                            true => datalake.publish_retained(&path, value).await,
+                            false => datalake.publish(&path, value).await,
+                        }
+                    }
+                    Ok(_) => (),
+                    Err(e) =>
+                    {
+                        println!("MQTT bridge: connection to {} failed: {}", config.broker, e);
+                        // the next poll connects again
+                        tokio::select!
+                        {
+                            _ = tokio::time::sleep(RECONNECT_DELAY) => (),
+                            _ = &mut stop_receiver => break,
+                        }
+                    }
+                }
+            }
+            Some((i, path, value)) = receiver.recv() =>
+            {
+                if lake_echoes.is_echo(&path.to_string(), &value)
+                {
+                    continue;
+                }
+                let mapping: &TopicMapping = &mappings[i];
+                let topic = mapping.topic_of(&path);
+                let payload = value.to_json().into_bytes();
+                if mappings.iter().any(|mapping| mapping.from_mqtt() && rumqttc::matches(&topic, &mapping.filter))
+                {
+                    mqtt_echoes.expect(topic.clone(), payload.clone());
+                }
+                if let Err(e) = client.try_publish(&topic, QoS::AtLeastOnce, mapping.retain, payload)
+                {
+                    println!("MQTT bridge: cannot publish to '{}': {}", topic, e);
+                }
+            }
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped)
+                break;
+            }
+        }
+    }
+    for forwarder in forwarders
+    {
+        forwarder.abort();
+    }
+    // give the event loop a moment to say goodbye, the broker notices the closed connection anyway
+    if client.try_disconnect().is_ok()
+    {
+        let _ = tokio::time::timeout(Duration::from_millis(500), async {
+            while let Ok(event) = eventloop.poll().await
+            {
+                if let Event::Outgoing(rumqttc::Outgoing::Disconnect) = event
+                {
+                    break;
+                }
+            }
+        }).await;
+    }
+}
+
+#[test]
+fn test_topic_mapping()
+{
+    let mapping = |lake: &str, topic: Option<&str>| TopicMapping::new(&MqttMappingConfig{
+        lake: lake.into(),
+        topic: topic.map(String::from),
+        direction: MqttDirection::Both,
+        retain: true,
+    });
+
+    let house = mapping("/house/**", Some("home")).unwrap();
+    assert_eq!(house.filter, "home/#");
+    assert_eq!(house.topic_of(&"/house/eg/light".parse().unwrap()), "home/eg/light");
+    assert_eq!(house.topic_of(&"/house".parse().unwrap()), "home");
+    assert_eq!(house.path_of("home/eg/light").map(|path| path.to_string()), Some("/house/eg/light".into()));
+    assert_eq!(house.path_of("home").map(|path| path.to_string()), Some("/house".into()));
+    assert!(house.path_of("homes/eg").is_none());
+    assert!(house.path_of("home//light").is_none());
+
+    let lights = mapping("/house/*/light", None).unwrap();
+    assert_eq!(lights.filter, "house/+/light");
+    assert_eq!(lights.topic_of(&"/house/eg/light".parse().unwrap()), "house/eg/light");
+    assert_eq!(lights.path_of("house/eg/light").map(|path| path.to_string()), Some("/house/eg/light".into()));
+
+    let everything = mapping("/**", Some("homecentral")).unwrap();
+    assert_eq!(everything.filter, "homecentral/#");
+    assert_eq!(everything.path_of("homecentral/a/b").map(|path| path.to_string()), Some("/a/b".into()));
+    assert_eq!(mapping("/**", None).unwrap().filter, "#");
+
+    assert!(mapping("/house/**/light", None).is_err());
+    assert!(mapping("/house/*2,0", None).is_err());
+    assert!(mapping("/house/a+b", None).is_err());
+    assert!(mapping("/house", Some("home/#")).is_err());
+    assert!(mapping("house", None).is_err());
+}
+
+#[cfg(test)]
+async fn wait_for_value(datalake: &TDataLake, path: &str, expected: Value)
+{
+    let path: Path = path.parse().unwrap();
+    for _ in 0..500
+    {
+        if datalake.get::<Value>(&path).await.as_ref() == Some(&expected)
+        {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("no value {:?} at '{}'", expected, path);
+}
+
+#[tokio::test]
+async fn bridge_forwards_both_directions()
+{
+    let broker = mock_broker::MockBroker::start().await;
+    let datalake = TDataLake::new();
+    datalake.publish_retained(&"/house/eg/light".parse().unwrap(), Value::Bool(true)).await;
+    broker.publish("sensors/garden/temperature", b"12.5", true);
+
+    let config: crate::config::Config = format!(r#"
+        [mqtt_bridge]
+        broker = "{}"
+        [[mqtt_bridge.mappings]]
+        lake = "/house/**"
+        topic = "home"
+        [[mqtt_bridge.mappings]]
+        lake = "/sensors/**"
+        direction = "from_mqtt"
+        retain = false
+    "#, broker.address).parse().unwrap();
+    let bridge = create(datalake.clone(), config.mqtt_bridge.unwrap());
+    broker.wait_for_subscriptions(2).await;
+
+    // retained MQTT messages are retained in the lake
+    wait_for_value(&datalake, "/sensors/garden/temperature", Value::Float(12.5)).await;
+    let mut fisher = datalake.clone().subscribe::<Value>(&"/sensors/garden/temperature".parse().unwrap()).await;
+    broker.publish("sensors/garden/humidity", b"high", false);
+    broker.publish("sensors/garden/temperature", b"13", false);
+    assert_eq!(fisher.receive().await, Some(Value::Integer(13)));
+    assert_eq!(datalake.get::<Value>(&"/sensors/garden/humidity".parse().unwrap()).await, None);
+
+    // lake to MQTT with retain flag
+    datalake.publish(&"/house/og/light".parse().unwrap(), Value::Bool(false)).await;
+    let published = broker.wait_for_published(1).await;
+    assert_eq!(published[0].topic, "home/og/light");
+    assert_eq!(&published[0].payload[..], b"false");
+    assert!(published[0].retain);
+
+    // MQTT to lake for the bidirectional mapping, the value is not sent back to MQTT
+    broker.publish("home/eg/light", br#"{"level": 3}"#, false);
+    wait_for_value(&datalake, "/house/eg/light", Value::Map(vec![(Value::String("level".into()), Value::Integer(3))])).await;
+    // values of a mapping reach MQTT in order, so had the value been sent back it would come before this one
+    datalake.publish(&"/house/og/door".parse().unwrap(), Value::Bool(true)).await;
+    let published = broker.wait_for_published(2).await;
+    assert_eq!(published.len(), 2);
+    assert_eq!(published[1].topic, "home/og/door");
+    // the broker sent the published value back as well, it was not published again
+    assert_eq!(datalake.get::<Value>(&"/house/og/light".parse().unwrap()).await, None);
+
+    bridge.stop().await;
+}
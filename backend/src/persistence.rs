@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::config::LakeConfig;
+use crate::data_lake::*;
+use crate::data_lake::path_tree::Path;
+use crate::shutdown::TaskHandle;
+
+// File format: one retained value per line, "<path>\t<value as JSON>"
+// NOTE: JSON has no binary type, binary values are restored as arrays of numbers
+
+/// Publishes all values saved in the file as retained values.
+/// Returns the number of values restored, a missing file restores nothing.
+pub async fn restore(datalake: &TDataLake, file: &str) -> Result<usize, String>
+{
+    let content = match std::fs::read_to_string(file)
+    {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(format!("Failed to read lake persistence file '{}': {}", file, e)),
+    };
+    let mut values = Vec::new();
+    for (i, line) in content.lines().enumerate().filter(|(_, line)| !line.is_empty())
+    {
+        let (path, value) = line.split_once('\t')
+            .ok_or(format!("{}:{}: expected <path><TAB><value>", file, i + 1))?;
+        let path: Path = path.parse().map_err(|e| format!("{}:{}: {}", file, i + 1, e))?;
+        let value = Value::from_json(value).map_err(|e| format!("{}:{}: {}", file, i + 1, e))?;
+        values.push((path, value));
+    }
+    let count = values.len();
+    for (path, value) in values
+    {
+        datalake.publish_retained(&path, value).await;
+    }
+    Ok(count)
+}
+
+/// Writes all retained values at paths matching one of `patterns` to the file.
+/// Returns the number of values saved.
+pub async fn save(datalake: &TDataLake, file: &str, patterns: &[Path]) -> Result<usize, String>
+{
+    let mut values = BTreeMap::new();
+    for pattern in patterns.iter()
+    {
+        for (path, value) in datalake.get_matching::<Value>(pattern).await
+        {
+            values.insert(path.to_string(), value);
+        }
+    }
+    let content: String = values.iter().map(|(path, value)| format!("{}\t{}\n", path, value.to_json())).collect();
+
+    // write a new file and replace the old one, so a crash while writing does not lose the old state
+    let temporary = format!("{}.tmp", file);
+    std::fs::write(&temporary, content)
+        .and_then(|_| std::fs::rename(&temporary, file))
+        .map_err(|e| format!("Failed to write lake persistence file '{}': {}", file, e))?;
+    Ok(values.len())
+}
+
+/// Periodically saves the lake as configured, and once more when stopped.
+/// Does nothing if no `persistence_path` is configured.
+pub fn create(datalake: TDataLake, config: LakeConfig) -> TaskHandle
+{
+    TaskHandle::spawn(|rx| run_persistence(datalake, config, rx))
+}
+
+async fn run_persistence(datalake: TDataLake, config: LakeConfig, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    let file = match config.persistence_path
+    {
+        Some(file) => file,
+        None => return,
+    };
+    // paths were validated with the configuration
+    let patterns: Vec<Path> = config.persisted_paths.iter().map(|path| path.parse().unwrap()).collect();
+
+    let mut interval = tokio::time::interval(Duration::from_secs(config.save_interval_seconds));
+    // the first tick completes immediately, nothing new to save yet
+    interval.tick().await;
+    loop
+    {
+        tokio::select!
+        {
+            _ = interval.tick() =>
+            {
+                if let Err(e) = save(&datalake, &file, &patterns).await
+                {
+                    println!("{}", e);
+                }
+            }
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped), keep the latest state
+                if let Err(e) = save(&datalake, &file, &patterns).await
+                {
+                    println!("{}", e);
+                }
+                break;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn save_and_restore()
+{
+    let file = std::env::temp_dir().join(format!("homecentral_lake_{}", std::process::id()));
+    let file = file.to_str().unwrap();
+
+    let datalake = TDataLake::new();
+    datalake.publish_retained(&"/house/eg/light".parse().unwrap(), Value::Bool(true)).await;
+    datalake.publish_retained(&"/house/eg/name".parse().unwrap(), Value::String("tab\there".into())).await;
+    datalake.publish_retained(&"/bus/ug/rx/5".parse().unwrap(), Value::Integer(3)).await;
+    // only values are persisted
+    datalake.publish_retained(&"/house/eg/temperature".parse().unwrap(), 21.5_f64).await;
+
+    assert_eq!(save(&datalake, file, &["/house/**".parse().unwrap()]).await, Ok(2));
+
+    let restored = TDataLake::new();
+    assert_eq!(restore(&restored, file).await, Ok(2));
+    std::fs::remove_file(file).unwrap();
+    assert_eq!(restored.get::<Value>(&"/house/eg/light".parse().unwrap()).await, Some(Value::Bool(true)));
+    assert_eq!(restored.get::<Value>(&"/house/eg/name".parse().unwrap()).await, Some(Value::String("tab\there".into())));
+    assert_eq!(restored.get::<Value>(&"/bus/ug/rx/5".parse().unwrap()).await, None);
+
+    assert_eq!(restore(&restored, file).await, Ok(0));
+}
+
+#[tokio::test]
+async fn saves_when_stopped()
+{
+    let file = std::env::temp_dir().join(format!("homecentral_lake_stop_{}", std::process::id()));
+    let file = file.to_str().unwrap().to_string();
+
+    let datalake = TDataLake::new();
+    let config = LakeConfig{persistence_path: Some(file.clone()), save_interval_seconds: 3600, ..Default::default()};
+    let handle = create(datalake.clone(), config);
+    datalake.publish_retained(&"/scene".parse().unwrap(), Value::String("movie".into())).await;
+    handle.stop().await;
+
+    let content = std::fs::read_to_string(&file).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(content, "/scene\t\"movie\"\n");
+}
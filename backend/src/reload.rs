@@ -0,0 +1,141 @@
+use std::time::{Duration, SystemTime};
+
+use crate::components::Components;
+use crate::config::Config;
+use crate::data_lake::*;
+
+/// Lake path the `ReloadResult` of the latest configuration reload is published to.
+pub const RELOAD_STATUS_PATH: &str = "/system/config/reload";
+
+/// Interval in which the configuration file is checked for modifications
+const FILE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReloadResult
+{
+    /// What caused the reload, e.g. "SIGHUP"
+    pub trigger: String,
+    pub at: SystemTime,
+    /// Changes applied, or why the new configuration was rejected.
+    /// A rejected configuration leaves the running one untouched.
+    pub result: Result<Vec<String>, String>,
+}
+
+/// Fires on SIGHUP and whenever the modification time of the configuration file changes.
+pub struct ReloadTrigger
+{
+    config_path: String,
+    modified: Option<SystemTime>,
+    #[cfg(unix)]
+    hangup: tokio::signal::unix::Signal,
+}
+
+fn modified(path: &str) -> Option<SystemTime>
+{
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+impl ReloadTrigger
+{
+    pub fn new(config_path: &str) -> Self
+    {
+        ReloadTrigger{
+            config_path: config_path.to_string(),
+            modified: modified(config_path),
+            #[cfg(unix)]
+            hangup: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()).expect("Failed to install SIGHUP handler"),
+        }
+    }
+
+    /// Waits for the next reason to reload, returns its description.
+    pub async fn wait(self: &mut Self) -> &'static str
+    {
+        let mut file_check = tokio::time::interval(FILE_CHECK_INTERVAL);
+        loop
+        {
+            #[cfg(unix)]
+            let hangup = self.hangup.recv();
+            #[cfg(not(unix))]
+            let hangup = std::future::pending::<Option<()>>();
+            tokio::select!
+            {
+                _ = hangup =>
+                {
+                    self.modified = modified(&self.config_path);
+                    return "SIGHUP";
+                }
+                _ = file_check.tick() =>
+                {
+                    let modified = modified(&self.config_path);
+                    // a file being replaced may be missing for a moment, wait until it is back
+                    if modified.is_some() && modified != self.modified
+                    {
+                        self.modified = modified;
+                        return "file change";
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Loads the configuration file again and applies it to the components.
+/// The result is printed and published to `RELOAD_STATUS_PATH`.
+pub async fn reload(datalake: &TDataLake, config_path: &str, components: &mut Components, trigger: &str) -> ReloadResult
+{
+    let result = match Config::load(config_path)
+    {
+        Ok(config) => Ok(components.reconfigure(config).await),
+        Err(e) => Err(e),
+    };
+    match &result
+    {
+        Ok(changes) if changes.is_empty() => println!("Configuration reloaded ({}), nothing changed", trigger),
+        Ok(changes) => println!("Configuration reloaded ({}): {}", trigger, changes.join(", ")),
+        Err(e) => println!("Configuration not reloaded ({}): {}", trigger, e),
+    }
+    let result = ReloadResult{trigger: trigger.to_string(), at: SystemTime::now(), result};
+    datalake.publish_retained(&RELOAD_STATUS_PATH.parse().unwrap(), result.clone()).await;
+    result
+}
+
+#[tokio::test]
+async fn reload_applies_valid_and_rejects_invalid_configuration()
+{
+    let file = std::env::temp_dir().join(format!("homecentral_reload_{}.toml", std::process::id()));
+    let file = file.to_str().unwrap().to_string();
+    let base = "[[gateways]]\ngateway_url = \"http://127.0.0.1:9\"\n[device_registry]\nenabled = false\n[health_monitor]\nenabled = false\n";
+    std::fs::write(&file, base).unwrap();
+
+    let datalake = TDataLake::new();
+    let mut components = Components::start(datalake.clone(), Config::load(&file).unwrap());
+
+    std::fs::write(&file, base.to_string() + "[[i2c_polls]]\ndevice = \"5\"\nbus = 0\naddress = 0x48\nread = 1\ndecode = \"u8\"\ninterval_milliseconds = 100000\npath = \"/t\"\n").unwrap();
+    let result = reload(&datalake, &file, &mut components, "test").await;
+    assert_eq!(result.result, Ok(vec!["i2c bridge restarted".to_string()]));
+
+    std::fs::write(&file, base.to_string() + "[lake]\nsave_interval = 5\n").unwrap();
+    let result = reload(&datalake, &file, &mut components, "test").await;
+    assert!(result.result.unwrap_err().contains("save_interval"));
+    assert_eq!(components.config().i2c_polls.len(), 1);
+    let published = datalake.get::<ReloadResult>(&RELOAD_STATUS_PATH.parse().unwrap()).await.unwrap();
+    assert!(published.result.is_err());
+
+    std::fs::remove_file(&file).unwrap();
+    components.into_coordinator().shutdown(&datalake, Some("/dev/null")).await;
+}
+
+#[tokio::test]
+async fn file_change_triggers_reload()
+{
+    let file = std::env::temp_dir().join(format!("homecentral_trigger_{}.toml", std::process::id()));
+    let file = file.to_str().unwrap().to_string();
+    std::fs::write(&file, "").unwrap();
+    let mut trigger = ReloadTrigger::new(&file);
+    // pretend the file was seen long ago
+    trigger.modified = Some(SystemTime::UNIX_EPOCH);
+
+    let reason = tokio::time::timeout(Duration::from_secs(5), trigger.wait()).await.unwrap();
+    assert_eq!(reason, "file change");
+    std::fs::remove_file(&file).unwrap();
+}
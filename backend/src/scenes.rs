@@ -0,0 +1,228 @@
+//! Scenes are target values of several paths which are applied together, e.g. "all off".
+//! Every scene lives below `SCENES_PATH/<name>`:
+//!   targets:  `Value::Map` of path -> value, retained. Published from the `[[scenes]]` of the
+//!             configuration at start, anyone may publish further scenes or change them.
+//!   activate: publish any value to publish all targets retained, concurrently
+//!   result:   `Value::Map` with `at` (unix seconds) and `targets`: path -> "ok" or the error,
+//!             of the latest activation
+//!   capture:  publish a path pattern, e.g. "/house/eg/**", to make the current (retained) values
+//!             matching it the targets of the scene
+
+use std::time::SystemTime;
+
+use crate::config::SceneConfig;
+use crate::data_lake::*;
+use crate::data_lake::path_tree::Path;
+use crate::shutdown::TaskHandle;
+
+pub const SCENES_PATH: &str = "/scenes";
+
+/// Path of the given part of a scene, fails if the name cannot be used in a path.
+pub fn scene_path(name: &str, part: &str) -> Result<Path, String>
+{
+    if name.contains('/')
+    {
+        return Err(format!("scene name '{}' must not contain '/'", name));
+    }
+    let path: Path = format!("{}/{}/{}", SCENES_PATH, name, part).parse()?;
+    match path.has_wildcards()
+    {
+        true => Err(format!("scene name '{}' must not contain wildcards", name)),
+        false => Ok(path),
+    }
+}
+
+/// Name of the scene a path below `SCENES_PATH` belongs to.
+fn scene_name(path: &Path) -> String
+{
+    path.to_string().split('/').nth(2).unwrap_or_default().to_string()
+}
+
+fn is_scene_path(path: &Path) -> bool
+{
+    path.to_string().starts_with(&format!("{}/", SCENES_PATH))
+}
+
+fn now() -> Value
+{
+    Value::Integer(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|at| at.as_secs() as i64).unwrap_or(0))
+}
+
+async fn apply_target(datalake: TDataLake, path: String, value: Value) -> Result<(), String>
+{
+    let path: Path = path.parse()?;
+    if path.has_wildcards()
+    {
+        return Err("wildcards are not allowed".into());
+    }
+    // scenes activating scenes could loop forever
+    if is_scene_path(&path)
+    {
+        return Err(format!("paths below {} are not allowed", SCENES_PATH));
+    }
+    datalake.publish_retained(&path, value).await;
+    Ok(())
+}
+
+/// Publishes all targets of the scene, each in a task of its own, and publishes the result.
+async fn activate(datalake: TDataLake, name: String)
+{
+    let targets = match datalake.get::<Value>(&scene_path(&name, "targets").unwrap()).await
+    {
+        Some(Value::Map(targets)) => targets,
+        Some(other) => return println!("Scene '{}': cannot activate, targets {} are no map of path to value", name, other),
+        None => return println!("Scene '{}': cannot activate, no such scene", name),
+    };
+    let tasks: Vec<(String, tokio::task::JoinHandle<Result<(), String>>)> = targets.into_iter().map(|(path, value)| {
+        let path = match path
+        {
+            Value::String(path) => path,
+            other => other.to_json(),
+        };
+        (path.clone(), tokio::task::spawn(apply_target(datalake.clone(), path, value)))
+    }).collect();
+    let mut results = Vec::new();
+    for (path, task) in tasks
+    {
+        let result = match task.await
+        {
+            Ok(Ok(())) => "ok".to_string(),
+            Ok(Err(e)) => e,
+            Err(e) => e.to_string(),
+        };
+        if result != "ok"
+        {
+            println!("Scene '{}': target {}: {}", name, path, result);
+        }
+        results.push((Value::String(path), Value::String(result)));
+    }
+    let result = Value::Map(vec![
+        (Value::String("at".into()), now()),
+        (Value::String("targets".into()), Value::Map(results)),
+    ]);
+    datalake.publish_retained(&scene_path(&name, "result").unwrap(), result).await;
+}
+
+/// Makes the current values matching the pattern the targets of the scene.
+async fn capture(datalake: &TDataLake, name: &str, pattern: &Value)
+{
+    let pattern: Path = match pattern
+    {
+        Value::String(pattern) => match pattern.parse()
+        {
+            Ok(pattern) => pattern,
+            Err(e) => return println!("Scene '{}': cannot capture '{}': {}", name, pattern, e),
+        },
+        other => return println!("Scene '{}': cannot capture {}, expected a path pattern", name, other),
+    };
+    let targets = datalake.get_matching::<Value>(&pattern).await.into_iter()
+        .filter(|(path, _)| !is_scene_path(path))
+        .map(|(path, value)| (Value::String(path.to_string()), value))
+        .collect();
+    datalake.publish_retained(&scene_path(name, "targets").unwrap(), Value::Map(targets)).await;
+}
+
+/// Publishes the configured scenes and serves activation and capture of all scenes until stopped.
+pub fn create(datalake: TDataLake, scenes: Vec<SceneConfig>) -> TaskHandle
+{
+    TaskHandle::spawn(|rx| run_scenes(datalake, scenes, rx))
+}
+
+async fn run_scenes(mut datalake: TDataLake, scenes: Vec<SceneConfig>, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    let mut activations = datalake.subscribe_with_path::<Value>(&format!("{}/*/activate", SCENES_PATH).parse().unwrap()).await;
+    let mut captures = datalake.subscribe_with_path::<Value>(&format!("{}/*/capture", SCENES_PATH).parse().unwrap()).await;
+    // names and paths were validated with the configuration
+    for scene in scenes.iter()
+    {
+        let targets = scene.targets.iter().map(|target| (Value::String(target.path.clone()), Value::from_toml(&target.value))).collect();
+        datalake.publish_retained(&scene_path(&scene.name, "targets").unwrap(), Value::Map(targets)).await;
+    }
+
+    let mut running: Vec<tokio::task::JoinHandle<()>> = Vec::new();
+    loop
+    {
+        tokio::select!
+        {
+            Some((path, _)) = activations.receive() =>
+            {
+                running.retain(|activation| !activation.is_finished());
+                running.push(tokio::task::spawn(activate(datalake.clone(), scene_name(&path))));
+            }
+            Some((path, pattern)) = captures.receive() =>
+            {
+                capture(&datalake, &scene_name(&path), &pattern).await;
+            }
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped)
+                break;
+            }
+        }
+    }
+    for activation in running.iter()
+    {
+        activation.abort();
+    }
+}
+
+#[tokio::test]
+async fn scenes_activate_and_capture()
+{
+    async fn wait_for_result(datalake: &TDataLake, name: &str) -> Value
+    {
+        let path = scene_path(name, "result").unwrap();
+        for _ in 0..100
+        {
+            if let Some(result) = datalake.get::<Value>(&path).await
+            {
+                return result;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("scene '{}' was not activated", name);
+    }
+
+    let config: crate::config::Config = r#"
+        [[scenes]]
+        name = "all off"
+        targets = [{path = "/house/eg/light", value = false}, {path = "/house/og/light", value = false}]
+    "#.parse().unwrap();
+    let datalake = TDataLake::new();
+    let handle = create(datalake.clone(), config.scenes);
+    while datalake.get::<Value>(&scene_path("all off", "targets").unwrap()).await.is_none()
+    {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    datalake.publish(&scene_path("all off", "activate").unwrap(), Value::Nil).await;
+    let result = wait_for_result(&datalake, "all off").await;
+    assert_eq!(result.get("targets").unwrap().to_json(), r#"{"/house/eg/light":"ok","/house/og/light":"ok"}"#);
+    assert_eq!(datalake.get::<Value>(&"/house/og/light".parse().unwrap()).await, Some(Value::Bool(false)));
+
+    // defined at runtime, with invalid targets
+    let targets = Value::from_json(r#"{"/house/eg/light": true, "/house/*": 1, "/scenes/all off/activate": true}"#).unwrap();
+    datalake.publish_retained(&scene_path("party", "targets").unwrap(), targets).await;
+    datalake.publish(&scene_path("party", "activate").unwrap(), Value::Nil).await;
+    let result = wait_for_result(&datalake, "party").await;
+    let results = result.get("targets").unwrap();
+    assert_eq!(results.get("/house/eg/light"), Some(&Value::String("ok".into())));
+    assert_eq!(results.get("/house/*"), Some(&Value::String("wildcards are not allowed".into())));
+    assert_eq!(results.get("/scenes/all off/activate"), Some(&Value::String("paths below /scenes are not allowed".into())));
+    assert_eq!(datalake.get::<Value>(&"/house/eg/light".parse().unwrap()).await, Some(Value::Bool(true)));
+
+    // captured from the current state
+    datalake.publish(&scene_path("evening", "capture").unwrap(), Value::String("/house/**".into())).await;
+    let targets = scene_path("evening", "targets").unwrap();
+    for _ in 0..100
+    {
+        if datalake.get::<Value>(&targets).await.is_some()
+        {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(datalake.get::<Value>(&targets).await.map(|targets| targets.to_json()), Some(r#"{"/house/eg/light":true,"/house/og/light":false}"#.into()));
+
+    handle.stop().await;
+}
@@ -0,0 +1,289 @@
+//! Publishes the values of the `[[scheduler.timers]]` when they are due, in local time.
+//!
+//! The state of every timer is kept below `SCHEDULER_PATH/<name>`:
+//!   next_run: RFC 3339 string, `Value::Nil` if the timer will not run again
+//!   last_run: RFC 3339 string, missing until the timer ran
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+
+use crate::config::{SchedulerConfig, SunEvent, TimerConfig};
+use crate::data_lake::*;
+use crate::data_lake::path_tree::Path;
+use crate::shutdown::TaskHandle;
+
+pub const SCHEDULER_PATH: &str = "/system/scheduler";
+
+/// Path of the given state of a timer, fails if the name cannot be used as a path element.
+pub fn state_path(name: &str, state: &str) -> Result<Path, String>
+{
+    SCHEDULER_PATH.parse::<Path>()?.child(name)?.child(state)
+}
+
+/// Changes of the clock (and daylight saving time) are noticed after this at the latest
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Parses a cron expression with the five fields "minute hour day-of-month month day-of-week".
+pub fn parse_cron(expression: &str) -> Result<cron::Schedule, String>
+{
+    let fields = expression.split_whitespace().count();
+    if fields != 5
+    {
+        return Err(format!("'{}' has {} fields, expected minute, hour, day of month, month and day of week", expression, fields));
+    }
+    // the cron crate expects seconds first
+    cron::Schedule::from_str(&format!("0 {}", expression)).map_err(|e| format!("'{}' is no valid cron expression: {}", expression, e))
+}
+
+/// Parses a local time like "2026-12-24T18:00:00".
+pub fn parse_at(at: &str) -> Result<NaiveDateTime, String>
+{
+    NaiveDateTime::parse_from_str(at, "%Y-%m-%dT%H:%M:%S").map_err(|e| format!("'{}' is no local time like 2026-12-24T18:00:00: {}", at, e))
+}
+
+/// Computes sunrise or sunset on `date` at the given location (degrees, north and east positive)
+/// using the sunrise equation, accurate to a minute or two. None during polar day or night.
+pub fn sun_event(date: NaiveDate, latitude: f64, longitude: f64, event: SunEvent) -> Option<DateTime<Utc>>
+{
+    // days since 2000-01-01 12:00 UTC
+    let days = (date - NaiveDate::from_ymd_opt(2000, 1, 1).unwrap()).num_days() as f64;
+    let mean_noon = days - longitude / 360.0;
+    let anomaly = (357.5291 + 0.98560028 * mean_noon).rem_euclid(360.0).to_radians();
+    let center = 1.9148 * anomaly.sin() + 0.02 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let ecliptic_longitude = (anomaly.to_degrees() + center + 180.0 + 102.9372).rem_euclid(360.0).to_radians();
+    let noon = mean_noon + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+    let declination = (ecliptic_longitude.sin() * 23.4397f64.to_radians().sin()).asin();
+
+    // -0.833° accounts for refraction and the size of the sun's disc
+    let latitude = latitude.to_radians();
+    let cos_hour_angle = ((-0.833f64).to_radians().sin() - latitude.sin() * declination.sin()) / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle)
+    {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees() / 360.0;
+    let time = match event
+    {
+        SunEvent::Sunrise => noon - hour_angle,
+        SunEvent::Sunset => noon + hour_angle,
+    };
+    const NOON_2000: f64 = 946_728_000.0;
+    DateTime::from_timestamp((NOON_2000 + time * 86400.0).round() as i64, 0)
+}
+
+enum When
+{
+    Cron(Box<cron::Schedule>),
+    At(NaiveDateTime),
+    Sun{event: SunEvent, offset: chrono::Duration, latitude: f64, longitude: f64},
+}
+
+impl When
+{
+    // the configuration was validated
+    fn new(config: &TimerConfig, scheduler: &SchedulerConfig) -> Self
+    {
+        if let Some(cron) = &config.cron
+        {
+            return When::Cron(Box::new(parse_cron(cron).unwrap()));
+        }
+        if let Some(at) = &config.at
+        {
+            return When::At(parse_at(at).unwrap());
+        }
+        When::Sun{
+            event: config.sun.unwrap(),
+            offset: chrono::Duration::minutes(config.offset_minutes),
+            latitude: scheduler.latitude.unwrap(),
+            longitude: scheduler.longitude.unwrap(),
+        }
+    }
+
+    /// First time due after `now`, None if there is none.
+    fn next_after<Tz: TimeZone>(self: &Self, now: &DateTime<Tz>) -> Option<DateTime<Tz>>
+    {
+        match self
+        {
+            When::Cron(schedule) => schedule.after(now).next(),
+            When::At(at) => now.timezone().from_local_datetime(at).earliest().filter(|at| at > now),
+            When::Sun{event, offset, latitude, longitude} =>
+            {
+                // starting the day before, the offset might move events across midnight;
+                // a year ahead covers polar night
+                let today = now.date_naive();
+                (-1..=366)
+                    .filter_map(|day| sun_event(today + chrono::Duration::days(day), *latitude, *longitude, *event))
+                    .map(|time| (time + *offset).with_timezone(&now.timezone()))
+                    .find(|time| time > now)
+            }
+        }
+    }
+}
+
+struct Timer
+{
+    name: String,
+    when: When,
+    publish: Vec<(Path, Value)>,
+    next: Option<DateTime<Local>>,
+}
+
+impl Timer
+{
+    fn state_path(self: &Self, state: &str) -> Path
+    {
+        // names were checked with `state_path` with the configuration
+        state_path(&self.name, state).unwrap()
+    }
+
+    async fn publish_next(self: &Self, datalake: &TDataLake)
+    {
+        let next = self.next.map(|next| Value::String(next.to_rfc3339_opts(SecondsFormat::Secs, false))).unwrap_or(Value::Nil);
+        datalake.publish_retained(&self.state_path("next_run"), next).await;
+    }
+}
+
+/// Runs the timers until stopped.
+pub fn create(datalake: TDataLake, config: SchedulerConfig) -> TaskHandle
+{
+    TaskHandle::spawn(|rx| run_scheduler(datalake, config, rx))
+}
+
+async fn run_scheduler(datalake: TDataLake, config: SchedulerConfig, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    let now = Local::now();
+    let mut timers: Vec<Timer> = config.timers.iter().map(|timer| {
+        let when = When::new(timer, &config);
+        Timer{
+            name: timer.name.clone(),
+            next: when.next_after(&now),
+            when,
+            // paths were validated with the configuration
+            publish: timer.publish.iter().map(|publish| (publish.path.parse().unwrap(), Value::from_toml(&publish.value))).collect(),
+        }
+    }).collect();
+    for timer in timers.iter()
+    {
+        timer.publish_next(&datalake).await;
+    }
+
+    loop
+    {
+        let now = Local::now();
+        let sleep = timers.iter().filter_map(|timer| timer.next)
+            .min()
+            .map(|next| (next - now).to_std().unwrap_or(Duration::ZERO).min(MAX_SLEEP))
+            .unwrap_or(MAX_SLEEP);
+        tokio::select!
+        {
+            _ = tokio::time::sleep(sleep) => (),
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped)
+                break;
+            }
+        }
+
+        let now = Local::now();
+        for timer in timers.iter_mut()
+        {
+            if timer.next.is_none_or(|next| next > now)
+            {
+                continue;
+            }
+            for (path, value) in timer.publish.iter()
+            {
+                datalake.publish_retained(path, value.clone()).await;
+            }
+            datalake.publish_retained(&timer.state_path("last_run"), Value::String(now.to_rfc3339_opts(SecondsFormat::Secs, false))).await;
+            timer.next = timer.when.next_after(&now);
+            timer.publish_next(&datalake).await;
+        }
+    }
+}
+
+#[test]
+fn test_next_after()
+{
+    let zone = chrono::FixedOffset::east_opt(2 * 3600).unwrap();
+    let now = zone.with_ymd_and_hms(2026, 10, 18, 22, 59, 30).unwrap();
+
+    let when = When::Cron(Box::new(parse_cron("0 23 * * *").unwrap()));
+    assert_eq!(when.next_after(&now), Some(zone.with_ymd_and_hms(2026, 10, 18, 23, 0, 0).unwrap()));
+    // 2026-10-18 is a sunday
+    let when = When::Cron(Box::new(parse_cron("30 6 * * mon-fri").unwrap()));
+    assert_eq!(when.next_after(&now), Some(zone.with_ymd_and_hms(2026, 10, 19, 6, 30, 0).unwrap()));
+    assert!(parse_cron("0 23 * *").is_err());
+    assert!(parse_cron("0 25 * * *").is_err());
+
+    let when = When::At(parse_at("2026-12-24T18:00:00").unwrap());
+    assert_eq!(when.next_after(&now), Some(zone.with_ymd_and_hms(2026, 12, 24, 18, 0, 0).unwrap()));
+    assert_eq!(when.next_after(&zone.with_ymd_and_hms(2026, 12, 24, 18, 0, 0).unwrap()), None);
+
+    // Munich, sunrise 2026-10-19 at about 07:37 CEST
+    let when = When::Sun{event: SunEvent::Sunrise, offset: chrono::Duration::minutes(30), latitude: 48.14, longitude: 11.58};
+    let next = when.next_after(&now).unwrap();
+    let expected = zone.with_ymd_and_hms(2026, 10, 19, 8, 7, 0).unwrap();
+    assert!((next - expected).num_minutes().abs() <= 3, "{}", next);
+}
+
+#[test]
+fn test_sun_event()
+{
+    let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+    // Munich: 03:12 and 19:17 UTC
+    let sunrise = sun_event(date, 48.14, 11.58, SunEvent::Sunrise).unwrap();
+    let sunset = sun_event(date, 48.14, 11.58, SunEvent::Sunset).unwrap();
+    assert!((sunrise - Utc.with_ymd_and_hms(2024, 6, 21, 3, 12, 0).unwrap()).num_minutes().abs() <= 3, "{}", sunrise);
+    assert!((sunset - Utc.with_ymd_and_hms(2024, 6, 21, 19, 17, 0).unwrap()).num_minutes().abs() <= 3, "{}", sunset);
+    // Sydney, southern winter: 20:59 UTC the day before and 06:53 UTC
+    let sunrise = sun_event(date, -33.87, 151.21, SunEvent::Sunrise).unwrap();
+    let sunset = sun_event(date, -33.87, 151.21, SunEvent::Sunset).unwrap();
+    assert!((sunrise - Utc.with_ymd_and_hms(2024, 6, 20, 20, 59, 0).unwrap()).num_minutes().abs() <= 3, "{}", sunrise);
+    assert!((sunset - Utc.with_ymd_and_hms(2024, 6, 21, 6, 53, 0).unwrap()).num_minutes().abs() <= 3, "{}", sunset);
+    // polar day at Tromsø
+    assert_eq!(sun_event(date, 69.65, 18.96, SunEvent::Sunset), None);
+}
+
+#[tokio::test]
+async fn timers_publish_when_due()
+{
+    let at = (Local::now() + chrono::Duration::seconds(2)).format("%Y-%m-%dT%H:%M:%S").to_string();
+    let config: crate::config::Config = format!(r#"
+        [[scheduler.timers]]
+        name = "soon"
+        at = "{}"
+        publish = [{{path = "/house/light", value = false}}]
+        [[scheduler.timers]]
+        name = "nightly"
+        cron = "0 23 * * *"
+        publish = []
+    "#, at).parse().unwrap();
+    let datalake = TDataLake::new();
+    let handle = create(datalake.clone(), config.scheduler);
+
+    let light: Path = "/house/light".parse().unwrap();
+    let mut published = None;
+    for _ in 0..400
+    {
+        published = datalake.get::<Value>(&light).await;
+        if published.is_some()
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(published, Some(Value::Bool(false)));
+    assert_eq!(datalake.get::<Value>(&"/system/scheduler/soon/next_run".parse().unwrap()).await, Some(Value::Nil));
+    assert!(datalake.get::<Value>(&"/system/scheduler/soon/last_run".parse().unwrap()).await.is_some());
+    let next = datalake.get::<Value>(&"/system/scheduler/nightly/next_run".parse().unwrap()).await;
+    match next
+    {
+        Some(Value::String(next)) => assert!(DateTime::parse_from_rfc3339(&next).is_ok_and(|next| next.format("%H:%M").to_string() == "23:00"), "{}", next),
+        other => panic!("unexpected next_run {:?}", other),
+    }
+
+    handle.stop().await;
+}
@@ -0,0 +1,525 @@
+//! Runs the Rhai scripts (`*.rhai`) of the configured directory, each on a thread of its own
+//! with a fresh engine. The script body runs once to set up, usually subscribing handlers:
+//!
+//!   subscribe("/house/*/switch", |path, value| {
+//!       if get("/house/mode") != "night" { publish("/house/eg/light", value); }
+//!   });
+//!
+//! Functions available to scripts:
+//!   subscribe(pattern, handler)    calls handler(path, value) for every value published
+//!   publish(path, value)           publishes a retained value, `publish(path, value, false)` does not retain
+//!   get(path)                      retained value, () if there is none
+//!   request(path, query)           reply to a `Request<Value, Value>`, () if nobody replied in time
+//!
+//! Scripts cannot access files or the network. Setup and every handler call are aborted after
+//! `max_operations` or `timeout_milliseconds`. A script whose file changes is restarted.
+//!
+//! The state of every script is kept below `SCRIPTS_PATH/<name>` (name of the file without `.rhai`):
+//!   status: "running", "failed" (setup did not complete) or "stopped" (file removed)
+//!   error:  `Value::Map` with `at` (unix seconds) and `message` of the latest error
+
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use rhai::{Dynamic, EvalAltResult, FnPtr};
+
+use crate::config::ScriptingConfig;
+use crate::data_lake::*;
+use crate::data_lake::path_tree::Path;
+use crate::shutdown::TaskHandle;
+
+pub const SCRIPTS_PATH: &str = "/system/scripts";
+
+/// Interval in which the script directory is checked for modifications
+const DIRECTORY_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The time limit is checked whenever this many operations were done
+const TIME_CHECK_OPERATIONS: u64 = 1000;
+
+pub fn to_dynamic(value: &Value) -> Dynamic
+{
+    match value
+    {
+        Value::Nil => Dynamic::UNIT,
+        Value::Bool(b) => Dynamic::from_bool(*b),
+        Value::Integer(i) => Dynamic::from_int(*i),
+        Value::Float(f) => Dynamic::from_float(*f),
+        Value::String(s) => s.clone().into(),
+        Value::Binary(b) => Dynamic::from_blob(b.clone()),
+        Value::Array(a) => Dynamic::from_array(a.iter().map(to_dynamic).collect()),
+        // object maps of scripts have string keys only
+        Value::Map(m) => Dynamic::from_map(m.iter().map(|(key, value)| {
+            let key = match key
+            {
+                Value::String(key) => key.clone(),
+                other => other.to_json(),
+            };
+            (key.into(), to_dynamic(value))
+        }).collect()),
+    }
+}
+
+pub fn from_dynamic(value: Dynamic) -> Result<Value, String>
+{
+    let type_name = value.type_name();
+    if value.is_unit()
+    {
+        return Ok(Value::Nil);
+    }
+    if let Ok(b) = value.as_bool()
+    {
+        return Ok(Value::Bool(b));
+    }
+    if let Ok(i) = value.as_int()
+    {
+        return Ok(Value::Integer(i));
+    }
+    if let Ok(f) = value.as_float()
+    {
+        return Ok(Value::Float(f));
+    }
+    if let Ok(c) = value.as_char()
+    {
+        return Ok(Value::String(c.to_string()));
+    }
+    if value.is_string()
+    {
+        return Ok(Value::String(value.into_string().unwrap()));
+    }
+    if value.is_blob()
+    {
+        return Ok(Value::Binary(value.into_blob().unwrap()));
+    }
+    if value.is_array()
+    {
+        return value.into_array().unwrap().into_iter().map(from_dynamic).collect::<Result<Vec<_>, _>>().map(Value::Array);
+    }
+    if let Some(map) = value.try_cast::<rhai::Map>()
+    {
+        return map.into_iter()
+            .map(|(key, value)| from_dynamic(value).map(|value| (Value::String(key.to_string()), value)))
+            .collect::<Result<Vec<_>, _>>()
+            .map(Value::Map);
+    }
+    Err(format!("a {} cannot be published", type_name))
+}
+
+enum Event
+{
+    Message(usize, Path, Value),
+    Stop,
+}
+
+/// A running script, stopped when dropped.
+struct Script
+{
+    modified: SystemTime,
+    stop: Arc<AtomicBool>,
+    events: std::sync::mpsc::Sender<Event>,
+}
+
+impl Drop for Script
+{
+    fn drop(&mut self)
+    {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.events.send(Event::Stop);
+    }
+}
+
+fn state_path(name: &str, state: &str) -> Result<Path, String>
+{
+    let path: Path = format!("{}/{}/{}", SCRIPTS_PATH, name, state).parse()?;
+    match path.has_wildcards()
+    {
+        true => Err(format!("'{}' contains wildcards", path)),
+        false => Ok(path),
+    }
+}
+
+/// Publishes the state of a script, names were checked with `state_path` before.
+async fn publish_state(datalake: &TDataLake, name: &str, state: &str, value: Value)
+{
+    datalake.publish_retained(&state_path(name, state).unwrap(), value).await;
+}
+
+async fn publish_error(datalake: &TDataLake, name: &str, message: String)
+{
+    println!("Script '{}': {}", name, message);
+    let at = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|at| at.as_secs() as i64).unwrap_or(0);
+    let error = Value::Map(vec![
+        (Value::String("at".into()), Value::Integer(at)),
+        (Value::String("message".into()), Value::String(message)),
+    ]);
+    publish_state(datalake, name, "error", error).await;
+}
+
+fn describe(error: &EvalAltResult) -> String
+{
+    match error
+    {
+        EvalAltResult::ErrorTerminated(reason, position) => format!("{} ({})", reason, position),
+        other => other.to_string(),
+    }
+}
+
+/// Everything the functions registered with the engine need
+struct Context
+{
+    runtime: tokio::runtime::Handle,
+    datalake: TDataLake,
+    request_timeout: Duration,
+    events: std::sync::mpsc::Sender<Event>,
+    handlers: RefCell<Vec<FnPtr>>,
+    forwarders: RefCell<Vec<tokio::task::JoinHandle<()>>>,
+}
+
+fn parse_path(function: &str, path: &str) -> Result<Path, Box<EvalAltResult>>
+{
+    path.parse().map_err(|e| format!("{}: {}", function, e).into())
+}
+
+fn register_functions(engine: &mut rhai::Engine, context: &Rc<Context>)
+{
+    let c = context.clone();
+    engine.register_fn("subscribe", move |pattern: &str, handler: FnPtr| -> Result<(), Box<EvalAltResult>>
+    {
+        let pattern = parse_path("subscribe", pattern)?;
+        let mut datalake = c.datalake.clone();
+        let mut fisher = c.runtime.block_on(datalake.subscribe_with_path::<Value>(&pattern));
+        let index = c.handlers.borrow().len();
+        c.handlers.borrow_mut().push(handler);
+        let events = c.events.clone();
+        c.forwarders.borrow_mut().push(c.runtime.spawn(async move {
+            while let Some((path, value)) = fisher.receive().await
+            {
+                if events.send(Event::Message(index, path, value)).is_err()
+                {
+                    break;
+                }
+            }
+        }));
+        Ok(())
+    });
+
+    let c = context.clone();
+    let publish = move |path: &str, value: Dynamic, retain: bool| -> Result<(), Box<EvalAltResult>>
+    {
+        let path = parse_path("publish", path)?;
+        if path.has_wildcards()
+        {
+            return Err(format!("publish: cannot publish to '{}', wildcards are not allowed", path).into());
+        }
+        let value = from_dynamic(value).map_err(|e| format!("publish: {}", e))?;
+        match retain
+        {
+            true => c.runtime.block_on(c.datalake.publish_retained(&path, value)),
+            false => c.runtime.block_on(c.datalake.publish(&path, value)),
+        }
+        Ok(())
+    };
+    let retained = publish.clone();
+    engine.register_fn("publish", move |path: &str, value: Dynamic| retained(path, value, true));
+    engine.register_fn("publish", publish);
+
+    let c = context.clone();
+    engine.register_fn("get", move |path: &str| -> Result<Dynamic, Box<EvalAltResult>>
+    {
+        let path = parse_path("get", path)?;
+        Ok(c.runtime.block_on(c.datalake.get::<Value>(&path)).map(|value| to_dynamic(&value)).unwrap_or(Dynamic::UNIT))
+    });
+
+    let c = context.clone();
+    engine.register_fn("request", move |path: &str, query: Dynamic| -> Result<Dynamic, Box<EvalAltResult>>
+    {
+        let path = parse_path("request", path)?;
+        let query = from_dynamic(query).map_err(|e| format!("request: {}", e))?;
+        // the timer must be created within the runtime
+        let reply = c.runtime.block_on(async { tokio::time::timeout(c.request_timeout, c.datalake.request::<Value, Value>(&path, query)).await });
+        Ok(reply.ok().flatten().map(|reply| to_dynamic(&reply)).unwrap_or(Dynamic::UNIT))
+    });
+}
+
+/// Runs a script until `Event::Stop` or until `stop` is set, to be run on a thread of its own.
+fn run_script(runtime: tokio::runtime::Handle, datalake: TDataLake, config: ScriptingConfig, name: String, source: String,
+    stop: Arc<AtomicBool>, (events, receiver): (std::sync::mpsc::Sender<Event>, std::sync::mpsc::Receiver<Event>))
+{
+    let timeout = Duration::from_millis(config.timeout_milliseconds);
+    let context = Rc::new(Context{
+        runtime: runtime.clone(),
+        datalake: datalake.clone(),
+        request_timeout: timeout,
+        events,
+        handlers: RefCell::new(Vec::new()),
+        forwarders: RefCell::new(Vec::new()),
+    });
+
+    let mut engine = rhai::Engine::new();
+    // scripts only reach the outside via the lake, not by importing files
+    engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+    engine.set_max_operations(config.max_operations);
+    engine.set_max_call_levels(32);
+    engine.set_max_expr_depths(64, 32);
+    engine.set_max_string_size(1024 * 1024);
+    engine.set_max_array_size(100_000);
+    engine.set_max_map_size(100_000);
+    let deadline = Rc::new(Cell::new(Instant::now() + timeout));
+    let progress_deadline = deadline.clone();
+    let progress_stop = stop.clone();
+    engine.on_progress(move |operations|
+    {
+        if progress_stop.load(Ordering::Relaxed)
+        {
+            return Some("script stopped".into());
+        }
+        if operations % TIME_CHECK_OPERATIONS == 0 && Instant::now() > progress_deadline.get()
+        {
+            return Some(format!("time limit of {:?} exceeded", timeout).into());
+        }
+        None
+    });
+    let print_name = name.clone();
+    engine.on_print(move |text| println!("Script '{}': {}", print_name, text));
+    register_functions(&mut engine, &context);
+
+    let setup = engine.compile(&source).map_err(|e| e.to_string())
+        .and_then(|ast| engine.run_ast(&ast).map(|_| ast).map_err(|e| describe(&e)));
+    let ast = match setup
+    {
+        Ok(ast) => ast,
+        Err(e) =>
+        {
+            if !stop.load(Ordering::Relaxed)
+            {
+                runtime.block_on(publish_error(&datalake, &name, e));
+                runtime.block_on(publish_state(&datalake, &name, "status", Value::String("failed".into())));
+            }
+            for forwarder in context.forwarders.borrow().iter()
+            {
+                forwarder.abort();
+            }
+            return;
+        }
+    };
+    runtime.block_on(publish_state(&datalake, &name, "status", Value::String("running".into())));
+
+    while let Ok(Event::Message(index, path, value)) = receiver.recv()
+    {
+        let handler = context.handlers.borrow()[index].clone();
+        deadline.set(Instant::now() + timeout);
+        if let Err(e) = handler.call::<Dynamic>(&engine, &ast, (path.to_string(), to_dynamic(&value)))
+        {
+            if stop.load(Ordering::Relaxed)
+            {
+                break;
+            }
+            runtime.block_on(publish_error(&datalake, &name, format!("handler for {} failed: {}", path, describe(&e))));
+        }
+    }
+    for forwarder in context.forwarders.borrow().iter()
+    {
+        forwarder.abort();
+    }
+}
+
+/// Scripts in the directory by name, with the time of their last modification.
+/// Files named such that no state path can be made of them are ignored.
+fn script_files(directory: &str) -> BTreeMap<String, (std::path::PathBuf, SystemTime)>
+{
+    let entries = match std::fs::read_dir(directory)
+    {
+        Ok(entries) => entries,
+        Err(_) => return BTreeMap::new(),
+    };
+    entries.filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|file| file.extension().is_some_and(|extension| extension == "rhai"))
+        .filter_map(|file| {
+            let name = file.file_stem()?.to_str()?.to_string();
+            let modified = std::fs::metadata(&file).and_then(|metadata| metadata.modified()).ok()?;
+            state_path(&name, "status").ok()?;
+            Some((name, (file, modified)))
+        })
+        .collect()
+}
+
+fn start_script(datalake: &TDataLake, config: &ScriptingConfig, name: &str, file: &std::path::Path, modified: SystemTime) -> Script
+{
+    let stop = Arc::new(AtomicBool::new(false));
+    let (events, receiver) = std::sync::mpsc::channel();
+    let source = std::fs::read_to_string(file).map_err(|e| format!("cannot read '{}': {}", file.display(), e));
+    let runtime = tokio::runtime::Handle::current();
+    let (datalake, config, name, thread_stop, thread_events) = (datalake.clone(), config.clone(), name.to_string(), stop.clone(), events.clone());
+    let thread = std::thread::Builder::new().name(format!("script {}", name));
+    let spawned = thread.spawn(move ||
+    {
+        match source
+        {
+            Ok(source) => run_script(runtime, datalake, config, name, source, thread_stop, (thread_events, receiver)),
+            Err(e) =>
+            {
+                runtime.block_on(publish_error(&datalake, &name, e));
+                runtime.block_on(publish_state(&datalake, &name, "status", Value::String("failed".into())));
+            }
+        }
+    });
+    if let Err(e) = spawned
+    {
+        println!("Script '{}': cannot start thread: {}", file.display(), e);
+    }
+    Script{modified, stop, events}
+}
+
+/// Runs the scripts of the configured directory until stopped.
+pub fn create(datalake: TDataLake, config: ScriptingConfig) -> TaskHandle
+{
+    TaskHandle::spawn(|rx| run_scripting(datalake, config, rx))
+}
+
+async fn run_scripting(datalake: TDataLake, config: ScriptingConfig, mut stop_receiver: tokio::sync::oneshot::Receiver<()>)
+{
+    let mut scripts: BTreeMap<String, Script> = BTreeMap::new();
+    let mut directory_check = tokio::time::interval(DIRECTORY_CHECK_INTERVAL);
+    loop
+    {
+        tokio::select!
+        {
+            _ = directory_check.tick() =>
+            {
+                let files = script_files(&config.directory);
+                let removed: Vec<String> = scripts.keys().filter(|name| !files.contains_key(*name)).cloned().collect();
+                for name in removed
+                {
+                    scripts.remove(&name);
+                    println!("Script '{}' stopped", name);
+                    publish_state(&datalake, &name, "status", Value::String("stopped".into())).await;
+                }
+                for (name, (file, modified)) in files
+                {
+                    if scripts.get(&name).is_some_and(|script| script.modified == modified)
+                    {
+                        continue;
+                    }
+                    // the old script is stopped when replaced
+                    let restarted = scripts.insert(name.clone(), start_script(&datalake, &config, &name, &file, modified)).is_some();
+                    println!("Script '{}' {}", name, if restarted { "restarted" } else { "started" });
+                }
+            }
+            _ = &mut stop_receiver =>
+            {
+                // Quit (explicit stop or handle dropped), dropping the scripts stops them
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_dynamic_conversion()
+{
+    let value = Value::from_json(r#"{"on": true, "level": 3, "rgb": [0.5, null], "name": "lamp"}"#).unwrap();
+    let converted = from_dynamic(to_dynamic(&value)).unwrap();
+    // object maps of scripts are sorted by key
+    assert_eq!(converted.get("on"), Some(&Value::Bool(true)));
+    assert_eq!(converted.get("level"), Some(&Value::Integer(3)));
+    assert_eq!(converted.get("rgb"), Some(&Value::Array(vec![Value::Float(0.5), Value::Nil])));
+    assert_eq!(converted.get("name"), Some(&Value::String("lamp".into())));
+    assert_eq!(from_dynamic(to_dynamic(&Value::Binary(vec![1, 2]))), Ok(Value::Binary(vec![1, 2])));
+    assert_eq!(from_dynamic(Dynamic::from_char('x')), Ok(Value::String("x".into())));
+    assert!(from_dynamic(Dynamic::from(Instant::now())).is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn scripts_run_sandboxed_and_reload()
+{
+    async fn wait_for(datalake: &TDataLake, path: &str, expected: Value)
+    {
+        let path: Path = path.parse().unwrap();
+        let mut value = None;
+        for _ in 0..600
+        {
+            value = datalake.get::<Value>(&path).await;
+            if value.as_ref() == Some(&expected)
+            {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{} is {:?}, expected {:?}", path, value, expected);
+    }
+
+    let directory = std::env::temp_dir().join(format!("homecentral_scripts_{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("lights.rhai"), r#"
+        subscribe("/house/*/switch", |path, value| {
+            if get("/house/mode") != "night" {
+                publish("/house/light", #{ from: path, on: value, doubled: request("/double", 21) });
+            }
+        });
+    "#).unwrap();
+    std::fs::write(directory.join("endless.rhai"), r#"subscribe("/endless", |path, value| { loop {} });"#).unwrap();
+    std::fs::write(directory.join("broken.rhai"), "let = ;").unwrap();
+    std::fs::write(directory.join("importing.rhai"), format!("import \"{}\" as lights;", directory.join("lights").display())).unwrap();
+
+    let mut datalake = TDataLake::new();
+    let mut doubler = datalake.subscribe::<Request<Value, Value>>(&"/double".parse().unwrap()).await;
+    tokio::task::spawn(async move {
+        while let Some(request) = doubler.receive().await
+        {
+            if let Value::Integer(i) = request.query
+            {
+                request.reply(Value::Integer(i * 2));
+            }
+        }
+    });
+    let config = ScriptingConfig{directory: directory.to_string_lossy().into(), max_operations: u64::MAX, timeout_milliseconds: 100};
+    let handle = create(datalake.clone(), config);
+
+    wait_for(&datalake, "/system/scripts/lights/status", Value::String("running".into())).await;
+    wait_for(&datalake, "/system/scripts/broken/status", Value::String("failed".into())).await;
+    assert!(datalake.get::<Value>(&"/system/scripts/broken/error".parse().unwrap()).await.is_some());
+    wait_for(&datalake, "/system/scripts/importing/status", Value::String("failed".into())).await;
+    let message = datalake.get::<Value>(&"/system/scripts/importing/error".parse().unwrap()).await.and_then(|error| error.get("message").cloned());
+    assert!(matches!(&message, Some(Value::String(message)) if message.contains("Module not found")), "{:?}", message);
+
+    datalake.publish(&"/house/eg/switch".parse().unwrap(), Value::Bool(true)).await;
+    let expected = Value::from_json(r#"{"doubled": 42, "from": "/house/eg/switch", "on": true}"#).unwrap();
+    wait_for(&datalake, "/house/light", expected).await;
+
+    // the endless handler is aborted, the script keeps running
+    wait_for(&datalake, "/system/scripts/endless/status", Value::String("running".into())).await;
+    datalake.publish(&"/endless".parse().unwrap(), Value::Nil).await;
+    let error: Path = "/system/scripts/endless/error".parse().unwrap();
+    for _ in 0..300
+    {
+        if datalake.get::<Value>(&error).await.is_some()
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let message = datalake.get::<Value>(&error).await.and_then(|error| error.get("message").cloned());
+    assert!(matches!(&message, Some(Value::String(message)) if message.contains("time limit")), "{:?}", message);
+
+    // changed scripts are restarted, removed ones stopped
+    std::fs::write(directory.join("lights.rhai"), r#"subscribe("/house/*/switch", |path, value| publish("/house/light", "v2"));"#).unwrap();
+    std::fs::remove_file(directory.join("broken.rhai")).unwrap();
+    wait_for(&datalake, "/system/scripts/broken/status", Value::String("stopped".into())).await;
+    for _ in 0..100
+    {
+        datalake.publish(&"/house/eg/switch".parse().unwrap(), Value::Bool(false)).await;
+        if datalake.get::<Value>(&"/house/light".parse().unwrap()).await == Some(Value::String("v2".into()))
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(datalake.get::<Value>(&"/house/light".parse().unwrap()).await, Some(Value::String("v2".into())));
+
+    handle.stop().await;
+    std::fs::remove_dir_all(&directory).unwrap();
+}
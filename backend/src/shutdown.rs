@@ -0,0 +1,148 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+use crate::data_lake::*;
+
+/// Handle to a task running a select loop with a stop receiver.
+/// `stop()` ends the task and waits for it, dropping the handle only ends the task.
+pub struct TaskHandle
+{
+    stop_sender: tokio::sync::oneshot::Sender<()>,
+    join_handle: tokio::task::JoinHandle::<()>,
+}
+
+impl TaskHandle
+{
+    /// Spawns the future created by `task`, which has to finish once the given receiver fires.
+    pub fn spawn<F, Fut>(task: F) -> Self
+    where
+        F: FnOnce(tokio::sync::oneshot::Receiver<()>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let join_handle = tokio::task::spawn(task(rx));
+        TaskHandle{stop_sender: tx, join_handle}
+    }
+
+    /// Signals the task to stop and waits until it finished.
+    pub async fn stop(self: Self)
+    {
+        // the task may already be gone, e.g. after losing its connection
+        let _ = self.stop_sender.send(());
+        if let Err(e) = self.join_handle.await
+        {
+            println!("Task ended abnormally: {}", e);
+        }
+    }
+}
+
+type StopFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Stops registered components in reverse order of registration, so components are
+/// stopped before the components they depend on. Each component gets `stop_timeout`
+/// to finish, after that it is abandoned and the next one is stopped.
+pub struct ShutdownCoordinator
+{
+    stop_timeout: Duration,
+    components: Vec<(String, Box<dyn FnOnce() -> StopFuture + Send>)>,
+}
+
+impl ShutdownCoordinator
+{
+    pub fn new(stop_timeout: Duration) -> Self
+    {
+        ShutdownCoordinator{stop_timeout, components: Vec::new()}
+    }
+
+    /// Registers a component, `stop` is called on shutdown and has to finish the component.
+    pub fn register<F, Fut>(self: &mut Self, name: &str, stop: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.components.push((name.to_string(), Box::new(move || Box::pin(stop()) as StopFuture)));
+    }
+
+    /// Stops all components, then saves the retained values of the lake to `snapshot_path`
+    /// (or prints them if no path is given).
+    /// Returns the names of the components which did not stop in time.
+    pub async fn shutdown(self: Self, datalake: &TDataLake, snapshot_path: Option<&str>) -> Vec<String>
+    {
+        let mut overdue = Vec::new();
+        for (name, stop) in self.components.into_iter().rev()
+        {
+            println!("Stopping {}", name);
+            if tokio::time::timeout(self.stop_timeout, stop()).await.is_err()
+            {
+                println!("{} did not stop within {:?}, abandoning it", name, self.stop_timeout);
+                overdue.push(name);
+            }
+        }
+
+        let snapshot: String = datalake.snapshot().await.into_iter()
+            .map(|(path, value)| format!("{} = {}\n", path, value))
+            .collect();
+        match snapshot_path
+        {
+            Some(snapshot_path) =>
+            {
+                if let Err(e) = std::fs::write(snapshot_path, snapshot)
+                {
+                    println!("Failed to write lake snapshot to '{}': {}", snapshot_path, e);
+                }
+            }
+            None => print!("Final state of the lake:\n{}", snapshot)
+        }
+        return overdue;
+    }
+}
+
+/// Waits until the process is asked to terminate (SIGINT or SIGTERM), returns the signal name.
+pub async fn wait_for_signal() -> &'static str
+{
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+        tokio::select!
+        {
+            _ = tokio::signal::ctrl_c() => return "SIGINT",
+            _ = terminate.recv() => return "SIGTERM",
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.expect("Failed to install ctrl-c handler");
+        return "ctrl-c";
+    }
+}
+
+#[tokio::test]
+async fn stops_in_reverse_order_and_writes_snapshot()
+{
+    let datalake = TDataLake::new();
+    datalake.publish_retained(&"/bus/ug/rx/5".parse().unwrap(), "on".to_string()).await;
+
+    let stopped = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let mut coordinator = ShutdownCoordinator::new(Duration::from_millis(100));
+    for name in ["gateways", "registry"]
+    {
+        let stopped = stopped.clone();
+        let task = TaskHandle::spawn(|mut stop_receiver| async move {
+            let _ = (&mut stop_receiver).await;
+            stopped.lock().unwrap().push(name);
+        });
+        coordinator.register(name, move || task.stop());
+    }
+    coordinator.register("stuck", std::future::pending::<()>);
+
+    let snapshot_path = std::env::temp_dir().join(format!("homecentral_snapshot_{}.txt", std::process::id()));
+    let overdue = coordinator.shutdown(&datalake, snapshot_path.to_str()).await;
+
+    assert_eq!(overdue, vec!["stuck".to_string()]);
+    assert_eq!(*stopped.lock().unwrap(), vec!["registry", "gateways"]);
+    let snapshot = std::fs::read_to_string(&snapshot_path).unwrap();
+    std::fs::remove_file(&snapshot_path).unwrap();
+    assert_eq!(snapshot, "/bus/ug/rx/5 = \"on\"\n");
+}